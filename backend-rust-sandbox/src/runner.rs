@@ -31,7 +31,7 @@ impl CompanionRunner {
             Ok(retrieved) => {
                 info!(
                     retrieved = retrieved.len(),
-                    example = ?retrieved.get(0),
+                    example = ?retrieved.first(),
                     "rag_verification_success"
                 );
             }
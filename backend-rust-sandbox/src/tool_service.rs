@@ -52,6 +52,6 @@ impl ToolService for SandboxToolService {
 }
 
 pub fn tool_service_server() -> ToolServiceServer<SandboxToolService> {
-	ToolServiceServer::new(SandboxToolService::default())
+	ToolServiceServer::new(SandboxToolService)
 }
 
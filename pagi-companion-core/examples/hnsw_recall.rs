@@ -0,0 +1,118 @@
+//! Recall/latency benchmark: HNSW index mode vs. the exact brute-force scan.
+//!
+//! Usage: `cargo run --release -p pagi-companion-core --example hnsw_recall -- [items] [queries] [k]`
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use pagi_companion_core::rag::embedding::EMBEDDING_DIMENSION;
+use pagi_companion_core::rag::hnsw::{HnswParams, IndexMode};
+use pagi_companion_core::rag::index::VectorIndex;
+use pagi_companion_core::rag::metadata::MemoryFilter;
+use rand::{Rng, SeedableRng};
+
+const LATENT_DIMENSION: usize = 24;
+
+/// Projects low-dimensional latent vectors into `EMBEDDING_DIMENSION` with a little noise.
+///
+/// Real sentence embeddings have a much lower intrinsic dimension than their width;
+/// uniform noise in 384 dims would make every neighbor nearly equidistant.
+fn synthetic_embeddings(
+    rng: &mut rand::rngs::StdRng,
+    projection: &[Vec<f32>],
+    count: usize,
+) -> Vec<Vec<f32>> {
+    (0..count)
+        .map(|_| {
+            let latent: Vec<f32> = projection
+                .iter()
+                .map(|_| rng.gen_range(-1.0..1.0))
+                .collect();
+            (0..EMBEDDING_DIMENSION)
+                .map(|d| {
+                    let signal: f32 = latent
+                        .iter()
+                        .zip(projection)
+                        .map(|(l, row)| l * row[d])
+                        .sum();
+                    signal + rng.gen_range(-0.05..0.05)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn arg(position: usize, default: usize) -> usize {
+    std::env::args()
+        .nth(position)
+        .and_then(|a| a.parse().ok())
+        .unwrap_or(default)
+}
+
+fn main() {
+    let items = arg(1, 20_000);
+    let queries = arg(2, 200);
+    let k = arg(3, 10);
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+    let projection: Vec<Vec<f32>> = (0..LATENT_DIMENSION)
+        .map(|_| {
+            (0..EMBEDDING_DIMENSION)
+                .map(|_| rng.gen_range(-0.3..0.3))
+                .collect()
+        })
+        .collect();
+    let vectors = synthetic_embeddings(&mut rng, &projection, items);
+    let query_vectors = synthetic_embeddings(&mut rng, &projection, queries);
+
+    let params = HnswParams {
+        exact_scan_threshold: 0,
+        ..HnswParams::default()
+    };
+
+    let mut exact = VectorIndex::with_mode(IndexMode::Exact);
+    let mut hnsw = VectorIndex::with_mode(IndexMode::Hnsw(params));
+
+    let build_start = Instant::now();
+    for (i, v) in vectors.iter().enumerate() {
+        exact.add(i.to_string(), v.clone());
+    }
+    let exact_build = build_start.elapsed();
+
+    let build_start = Instant::now();
+    for (i, v) in vectors.iter().enumerate() {
+        hnsw.add(i.to_string(), v.clone());
+    }
+    let hnsw_build = build_start.elapsed();
+
+    let no_filter = MemoryFilter::default();
+    let mut exact_time = Duration::ZERO;
+    let mut hnsw_time = Duration::ZERO;
+    let mut hits = 0usize;
+
+    for q in &query_vectors {
+        let t = Instant::now();
+        let truth: HashSet<String> = exact.search(q, k, &no_filter).into_iter().collect();
+        exact_time += t.elapsed();
+
+        let t = Instant::now();
+        let approx = hnsw.search(q, k, &no_filter);
+        hnsw_time += t.elapsed();
+
+        hits += approx.iter().filter(|c| truth.contains(*c)).count();
+    }
+
+    let recall = hits as f64 / (queries * k) as f64;
+    println!("items={items} queries={queries} k={k} dim={EMBEDDING_DIMENSION}");
+    println!(
+        "params: m={} ef_construction={} ef_search={}",
+        params.m, params.ef_construction, params.ef_search
+    );
+    println!("build: exact={exact_build:?} hnsw={hnsw_build:?}");
+    println!(
+        "query avg: exact={:?} hnsw={:?}",
+        exact_time / queries as u32,
+        hnsw_time / queries as u32
+    );
+    println!("recall@{k}: {recall:.4}");
+}
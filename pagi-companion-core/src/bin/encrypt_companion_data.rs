@@ -0,0 +1,53 @@
+//! Encrypts an existing plaintext data directory (`PAGI_DATA_DIR`, default
+//! `./companion_data`) in place.
+//!
+//! Usage:
+//!   PAGI_DATA_PASSPHRASE=... encrypt_companion_data
+//!   PAGI_DATA_KEY_FILE=key.hex encrypt_companion_data
+//!   encrypt_companion_data --generate-key key.hex
+
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use pagi_companion_core::companion::kb::keyring::load_storage_cipher;
+use pagi_companion_core::companion::kb::migration::encrypt_existing_data;
+use pagi_companion_core::security::encryption::MasterKey;
+use pagi_companion_core::storage::FileSystemStorage;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [flag, path] = args.as_slice() {
+        if flag == "--generate-key" {
+            if std::path::Path::new(path).exists() {
+                bail!("Refusing to overwrite existing key file {path}");
+            }
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options
+                .open(path)?
+                .write_all(MasterKey::generate().to_hex().as_bytes())?;
+            println!("Wrote new key file {path}; keep it safe and set PAGI_DATA_KEY_FILE={path}");
+            return Ok(());
+        }
+    }
+    if !args.is_empty() {
+        bail!("Usage: encrypt_companion_data [--generate-key <path>]");
+    }
+
+    let storage = FileSystemStorage::from_env();
+    let cipher = load_storage_cipher(&storage).await?;
+    if !cipher.is_encrypted() {
+        bail!("Set PAGI_DATA_PASSPHRASE or PAGI_DATA_KEY_FILE to choose the encryption key");
+    }
+
+    let report = encrypt_existing_data(&storage, &cipher).await?;
+    println!(
+        "Encrypted {} file(s); {} already encrypted.",
+        report.encrypted.len(),
+        report.skipped.len()
+    );
+    Ok(())
+}
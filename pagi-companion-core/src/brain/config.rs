@@ -1,34 +1,215 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use std::env;
+use std::str::FromStr;
+use std::time::Duration;
 use url::Url;
 
+use crate::brain::repair::RepairPolicy;
+use crate::config::parse_env;
+
+/// Wire format spoken by the Tactical LLM server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmProviderKind {
+    /// OpenAI-compatible `/v1/chat/completions`.
+    OpenAiChat,
+    /// Ollama `/api/chat`.
+    Ollama,
+    /// The original `TacticalLLMRequest` schema; the body is the model text.
+    Raw,
+}
+
+impl LlmProviderKind {
+    /// Endpoint used when `TACTICAL_LLM_API_URL` is not set.
+    pub fn default_api_url(&self) -> &'static str {
+        match self {
+            LlmProviderKind::OpenAiChat => "http://127.0.0.1:8000/v1/chat/completions",
+            LlmProviderKind::Ollama => "http://127.0.0.1:11434/api/chat",
+            LlmProviderKind::Raw => "http://127.0.0.1:8000/v1/generate",
+        }
+    }
+}
+
+impl FromStr for LlmProviderKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "openai" | "openai-chat" => Ok(LlmProviderKind::OpenAiChat),
+            "ollama" => Ok(LlmProviderKind::Ollama),
+            "raw" | "custom" => Ok(LlmProviderKind::Raw),
+            other => bail!("Unknown LLM provider {other:?} (expected openai, ollama or raw)"),
+        }
+    }
+}
+
+/// One server/model pair the Tactical LLM can be reached at.
+#[derive(Debug, Clone)]
+pub struct LlmEndpoint {
+    pub provider: LlmProviderKind,
+    pub api_url: Url,
+    pub api_key: String,
+    pub model_name: String,
+}
+
+impl LlmEndpoint {
+    /// Parses `provider:model@url` (e.g. `ollama:llama3:8b@http://127.0.0.1:11434/api/chat`).
+    pub fn parse(spec: &str, api_key: &str) -> Result<Self> {
+        let (target, api_url) = spec.trim().split_once('@').ok_or_else(|| {
+            anyhow!("Invalid LLM endpoint {spec:?} (expected provider:model@url)")
+        })?;
+        let (provider, model_name) = target.split_once(':').ok_or_else(|| {
+            anyhow!("Invalid LLM endpoint {spec:?} (expected provider:model@url)")
+        })?;
+
+        Ok(LlmEndpoint {
+            provider: provider.parse()?,
+            api_url: Url::parse(api_url)?,
+            api_key: api_key.to_string(),
+            model_name: model_name.to_string(),
+        })
+    }
+
+    /// `model@url`, for logs and circuit breaker names.
+    pub fn label(&self) -> String {
+        format!("{}@{}", self.model_name, self.api_url)
+    }
+}
+
+/// Retries of a single endpoint after transient failures (429, 408, 5xx, network).
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt.
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled per attempt and jittered.
+    pub base_delay: Duration,
+    /// Upper bound for computed backoff.
+    pub max_delay: Duration,
+    /// Longest `Retry-After` honored; longer waits move on to the next endpoint instead.
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+            max_retry_after: Duration::from_secs(30),
+        }
+    }
+}
+
+/// When an endpoint is taken out of rotation after repeated transient failures.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive transient failures that open the circuit.
+    pub failure_threshold: u32,
+    /// How long an open circuit rejects calls before a single trial call is allowed.
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
 /// Configuration settings for the external Tactical LLM API.
 #[derive(Debug, Clone)]
 pub struct TacticalLLMConfig {
+    pub provider: LlmProviderKind,
     pub api_url: Url,
     pub api_key: String,
     pub model_name: String,
+    /// Tried in order when the primary endpoint fails or its circuit is open.
+    pub fallbacks: Vec<LlmEndpoint>,
+    pub retry: RetryPolicy,
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Recovery from malformed structured output.
+    pub repair: RepairPolicy,
+    /// Send the `StructuredLLMOutput` JSON Schema with each request; disable for servers
+    /// that reject `response_format` / `format`.
+    pub response_schema: bool,
 }
 
 impl TacticalLLMConfig {
     /// Loads configuration from environment variables.
     ///
-    /// - `TACTICAL_LLM_API_URL` (default: `http://127.0.0.1:8000/v1/generate`)
-    /// - `TACTICAL_LLM_API_KEY` (default: `DEV_MOCK_KEY`)
+    /// - `TACTICAL_LLM_PROVIDER` (`openai`, `ollama` or `raw`; default: `raw`)
+    /// - `TACTICAL_LLM_API_URL` (default depends on the provider, e.g.
+    ///   `http://127.0.0.1:8000/v1/generate` for `raw`)
+    /// - `TACTICAL_LLM_API_KEY` (default: `DEV_MOCK_KEY`; empty sends no `Authorization` header)
     /// - `TACTICAL_LLM_MODEL` (default: `llama-3-8b-research`)
+    /// - `TACTICAL_LLM_FALLBACKS` (default: none; comma-separated `provider:model@url`,
+    ///   sharing the primary API key)
+    /// - `TACTICAL_LLM_MAX_RETRIES` (default: `3`)
+    /// - `TACTICAL_LLM_BREAKER_THRESHOLD` (default: `5`)
+    /// - `TACTICAL_LLM_BREAKER_COOLDOWN_SECS` (default: `30`)
+    /// - `TACTICAL_LLM_MAX_REPROMPTS` (default: `1`; corrective requests for malformed output)
+    /// - `TACTICAL_LLM_RESPONSE_SCHEMA` (default: `true`)
     pub fn load() -> Result<Self> {
+        let provider = match env::var("TACTICAL_LLM_PROVIDER") {
+            Ok(raw) if !raw.trim().is_empty() => raw.parse()?,
+            _ => LlmProviderKind::Raw,
+        };
+
         let api_url = env::var("TACTICAL_LLM_API_URL")
-            .unwrap_or_else(|_| "http://127.0.0.1:8000/v1/generate".to_string());
+            .unwrap_or_else(|_| provider.default_api_url().to_string());
 
         let api_key = env::var("TACTICAL_LLM_API_KEY").unwrap_or_else(|_| "DEV_MOCK_KEY".to_string());
 
         let model_name = env::var("TACTICAL_LLM_MODEL").unwrap_or_else(|_| "llama-3-8b-research".to_string());
 
+        let fallbacks = env::var("TACTICAL_LLM_FALLBACKS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|spec| !spec.trim().is_empty())
+            .map(|spec| LlmEndpoint::parse(spec, &api_key))
+            .collect::<Result<Vec<_>>>()?;
+
+        let retry = RetryPolicy {
+            max_retries: parse_env(
+                "TACTICAL_LLM_MAX_RETRIES",
+                RetryPolicy::default().max_retries,
+            )?,
+            ..RetryPolicy::default()
+        };
+        let circuit_breaker = CircuitBreakerConfig {
+            failure_threshold: parse_env("TACTICAL_LLM_BREAKER_THRESHOLD", 5u32)?.max(1),
+            cooldown: Duration::from_secs(parse_env("TACTICAL_LLM_BREAKER_COOLDOWN_SECS", 30u64)?),
+        };
+        let max_reprompts = parse_env(
+            "TACTICAL_LLM_MAX_REPROMPTS",
+            RepairPolicy::default().max_reprompts,
+        )?;
+        let response_schema = parse_env("TACTICAL_LLM_RESPONSE_SCHEMA", true)?;
+
         Ok(Self {
+            provider,
             api_url: Url::parse(&api_url)?,
             api_key,
             model_name,
+            fallbacks,
+            retry,
+            circuit_breaker,
+            repair: RepairPolicy::default().with_max_reprompts(max_reprompts),
+            response_schema,
         })
     }
-}
 
+    /// The primary endpoint followed by the fallbacks.
+    pub fn endpoints(&self) -> Vec<LlmEndpoint> {
+        let primary = LlmEndpoint {
+            provider: self.provider,
+            api_url: self.api_url.clone(),
+            api_key: self.api_key.clone(),
+            model_name: self.model_name.clone(),
+        };
+        std::iter::once(primary)
+            .chain(self.fallbacks.iter().cloned())
+            .collect()
+    }
+}
@@ -1,3 +1,7 @@
 pub mod config;
+pub mod prompt;
+pub mod provider;
+pub mod repair;
+pub mod schema;
+pub mod stream;
 pub mod tactical_llm;
-
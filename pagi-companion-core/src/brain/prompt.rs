@@ -0,0 +1,590 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::brain::provider::{ChatMessage, ChatRole};
+use crate::config::parse_env;
+
+/// Chat framing (role markers, separators) counted for every message.
+pub const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+const MEMORY_HEADER: &str = "--- CONTEXTUAL MEMORIES ---";
+const TRUNCATION_MARKER: &str = "…";
+
+/// Counts how many tokens a text occupies in the model's context.
+pub trait TokenEstimator: Send + Sync {
+    fn count(&self, text: &str) -> usize;
+}
+
+/// Model-agnostic estimate from the character count (~4 characters per token for
+/// English text with BPE tokenizers).
+#[derive(Debug, Clone)]
+pub struct CharRatioEstimator {
+    pub chars_per_token: f32,
+}
+
+impl Default for CharRatioEstimator {
+    fn default() -> Self {
+        CharRatioEstimator {
+            chars_per_token: 4.0,
+        }
+    }
+}
+
+impl TokenEstimator for CharRatioEstimator {
+    fn count(&self, text: &str) -> usize {
+        (text.chars().count() as f32 / self.chars_per_token.max(0.1)).ceil() as usize
+    }
+}
+
+/// Parts of a Tactical LLM prompt, in the order they are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PromptSection {
+    /// Fixed instructions.
+    System,
+    /// The companion's personality and current state.
+    Persona,
+    /// Retrieved episodic memories, most relevant first.
+    Memories,
+    /// Recent conversation turns, oldest first.
+    History,
+    /// The current user message.
+    UserInput,
+}
+
+impl PromptSection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PromptSection::System => "system",
+            PromptSection::Persona => "persona",
+            PromptSection::Memories => "memories",
+            PromptSection::History => "history",
+            PromptSection::UserInput => "user_input",
+        }
+    }
+}
+
+/// How many tokens a prompt may use and which sections give way first.
+#[derive(Debug, Clone)]
+pub struct PromptBudget {
+    /// Context window of the model.
+    pub context_tokens: usize,
+    /// Kept free for the reply (and the response schema, where it is sent).
+    pub reserved_tokens: usize,
+    /// Per-section limits, applied before the overall budget.
+    pub section_caps: BTreeMap<PromptSection, usize>,
+    /// Sections shrunk when the prompt is over budget, lowest priority first.
+    pub drop_order: Vec<PromptSection>,
+}
+
+impl Default for PromptBudget {
+    fn default() -> Self {
+        PromptBudget {
+            context_tokens: 8192,
+            reserved_tokens: 1536,
+            section_caps: BTreeMap::from([
+                (PromptSection::Memories, 1024),
+                (PromptSection::History, 2048),
+            ]),
+            drop_order: vec![
+                PromptSection::Memories,
+                PromptSection::History,
+                PromptSection::Persona,
+                PromptSection::System,
+                PromptSection::UserInput,
+            ],
+        }
+    }
+}
+
+impl PromptBudget {
+    /// Loads the budget from environment variables.
+    ///
+    /// - `PROMPT_CONTEXT_TOKENS` (default: `8192`)
+    /// - `PROMPT_RESERVED_TOKENS` (default: `1536`)
+    /// - `PROMPT_MEMORY_TOKENS` (default: `1024`; cap of the memories section)
+    /// - `PROMPT_HISTORY_TOKENS` (default: `2048`; cap of the history section)
+    pub fn load() -> Result<Self> {
+        let defaults = PromptBudget::default();
+        let memory_tokens = parse_env(
+            "PROMPT_MEMORY_TOKENS",
+            defaults.section_caps[&PromptSection::Memories],
+        )?;
+        let history_tokens = parse_env(
+            "PROMPT_HISTORY_TOKENS",
+            defaults.section_caps[&PromptSection::History],
+        )?;
+        Ok(defaults
+            .clone()
+            .with_context_tokens(parse_env("PROMPT_CONTEXT_TOKENS", defaults.context_tokens)?)
+            .with_reserved_tokens(parse_env(
+                "PROMPT_RESERVED_TOKENS",
+                defaults.reserved_tokens,
+            )?)
+            .with_section_cap(PromptSection::Memories, memory_tokens)
+            .with_section_cap(PromptSection::History, history_tokens))
+    }
+
+    pub fn with_context_tokens(mut self, context_tokens: usize) -> Self {
+        self.context_tokens = context_tokens;
+        self
+    }
+
+    pub fn with_reserved_tokens(mut self, reserved_tokens: usize) -> Self {
+        self.reserved_tokens = reserved_tokens;
+        self
+    }
+
+    pub fn with_section_cap(mut self, section: PromptSection, tokens: usize) -> Self {
+        self.section_caps.insert(section, tokens);
+        self
+    }
+
+    pub fn with_drop_order(mut self, drop_order: Vec<PromptSection>) -> Self {
+        self.drop_order = drop_order;
+        self
+    }
+
+    /// Tokens available to the prompt itself.
+    pub fn max_prompt_tokens(&self) -> usize {
+        self.context_tokens.saturating_sub(self.reserved_tokens)
+    }
+}
+
+/// Raw prompt material before budgeting.
+#[derive(Debug, Clone, Default)]
+pub struct PromptSections {
+    pub system: String,
+    pub persona: String,
+    /// Most relevant first; the least relevant are dropped first.
+    pub memories: Vec<String>,
+    /// Oldest first; the oldest turns are dropped first.
+    pub history: Vec<ChatMessage>,
+    pub user_input: String,
+}
+
+impl PromptSections {
+    pub fn new(user_input: impl Into<String>) -> Self {
+        PromptSections {
+            user_input: user_input.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_system(mut self, system: impl Into<String>) -> Self {
+        self.system = system.into();
+        self
+    }
+
+    pub fn with_persona(mut self, persona: impl Into<String>) -> Self {
+        self.persona = persona.into();
+        self
+    }
+
+    pub fn with_memories(mut self, memories: Vec<String>) -> Self {
+        self.memories = memories;
+        self
+    }
+
+    pub fn with_history(mut self, history: Vec<ChatMessage>) -> Self {
+        self.history = history;
+        self
+    }
+}
+
+/// What budgeting did to one section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionUsage {
+    pub section: PromptSection,
+    /// Estimated tokens after budgeting.
+    pub tokens: usize,
+    /// Memories or turns removed.
+    pub dropped_items: usize,
+    /// Whether the text was cut short.
+    pub truncated: bool,
+}
+
+/// A prompt that fits its `PromptBudget`, ready to be sent as chat messages.
+#[derive(Debug, Clone)]
+pub struct AssembledPrompt {
+    pub sections: PromptSections,
+    /// One entry per section, in `PromptSection` order.
+    pub usage: Vec<SectionUsage>,
+    /// Estimated tokens of the whole prompt, framing included.
+    pub total_tokens: usize,
+}
+
+impl AssembledPrompt {
+    /// System message (instructions, then persona), history turns, then the user message
+    /// carrying the memories and the current input.
+    pub fn to_messages(&self) -> Vec<ChatMessage> {
+        let sections = &self.sections;
+        let system = [sections.system.as_str(), sections.persona.as_str()]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let mut messages = Vec::with_capacity(sections.history.len() + 2);
+        messages.push(ChatMessage::system(system));
+        messages.extend_from_slice(&sections.history);
+        messages.push(ChatMessage::user(format!(
+            "CONTEXT: {MEMORY_HEADER}\n{}\nUSER REQUEST: {}",
+            sections.memories.join("\n"),
+            sections.user_input
+        )));
+        messages
+    }
+}
+
+/// Fits prompt sections into a `PromptBudget`.
+///
+/// Section caps are applied first; if the prompt is still too long, sections are shrunk
+/// in `drop_order` until it fits. Memories lose their least relevant entries, history its
+/// oldest exchanges, and text sections are cut at the end. The outcome depends only on
+/// the input, so the same turn always yields the same prompt.
+#[derive(Clone)]
+pub struct PromptAssembler {
+    budget: PromptBudget,
+    estimator: Arc<dyn TokenEstimator>,
+}
+
+impl Default for PromptAssembler {
+    fn default() -> Self {
+        Self::new(PromptBudget::default())
+    }
+}
+
+impl PromptAssembler {
+    pub fn new(budget: PromptBudget) -> Self {
+        PromptAssembler {
+            budget,
+            estimator: Arc::new(CharRatioEstimator::default()),
+        }
+    }
+
+    /// Counts tokens with `estimator` (e.g. the model's real tokenizer).
+    pub fn with_estimator(mut self, estimator: Arc<dyn TokenEstimator>) -> Self {
+        self.estimator = estimator;
+        self
+    }
+
+    pub fn budget(&self) -> &PromptBudget {
+        &self.budget
+    }
+
+    pub fn estimator(&self) -> &dyn TokenEstimator {
+        self.estimator.as_ref()
+    }
+
+    pub fn assemble(&self, sections: PromptSections) -> AssembledPrompt {
+        let mut sections = sections;
+        let mut usage: BTreeMap<PromptSection, SectionUsage> = ALL_SECTIONS
+            .iter()
+            .map(|&section| {
+                let usage = SectionUsage {
+                    section,
+                    tokens: 0,
+                    dropped_items: 0,
+                    truncated: false,
+                };
+                (section, usage)
+            })
+            .collect();
+
+        for (&section, &cap) in &self.budget.section_caps {
+            self.shrink(&mut sections, section, cap, &mut usage);
+        }
+
+        let budget = self
+            .budget
+            .max_prompt_tokens()
+            .saturating_sub(self.framing_tokens());
+        for &section in &self.budget.drop_order {
+            let total = self.content_tokens(&sections);
+            if total <= budget {
+                break;
+            }
+            let target = self
+                .section_tokens(&sections, section)
+                .saturating_sub(total - budget);
+            self.shrink(&mut sections, section, target, &mut usage);
+        }
+
+        for (section, usage) in usage.iter_mut() {
+            usage.tokens = self.section_tokens(&sections, *section);
+            if usage.dropped_items > 0 || usage.truncated {
+                warn!(
+                    section = section.as_str(),
+                    dropped_items = usage.dropped_items,
+                    truncated = usage.truncated,
+                    tokens = usage.tokens,
+                    "prompt_section_trimmed"
+                );
+            }
+        }
+        let total_tokens = self.content_tokens(&sections) + self.framing_tokens();
+        info!(
+            total_tokens = total_tokens,
+            max_prompt_tokens = self.budget.max_prompt_tokens(),
+            "prompt_assembled"
+        );
+
+        AssembledPrompt {
+            sections,
+            usage: usage.into_values().collect(),
+            total_tokens,
+        }
+    }
+
+    /// Tokens spent regardless of content: the system and user message framing.
+    fn framing_tokens(&self) -> usize {
+        2 * MESSAGE_OVERHEAD_TOKENS
+            + self
+                .estimator
+                .count(&format!("CONTEXT: {MEMORY_HEADER}\n\nUSER REQUEST: "))
+    }
+
+    fn content_tokens(&self, sections: &PromptSections) -> usize {
+        ALL_SECTIONS
+            .iter()
+            .map(|&section| self.section_tokens(sections, section))
+            .sum()
+    }
+
+    fn section_tokens(&self, sections: &PromptSections, section: PromptSection) -> usize {
+        let count = |text: &str| {
+            if text.is_empty() {
+                0
+            } else {
+                self.estimator.count(text)
+            }
+        };
+        match section {
+            PromptSection::System => count(&sections.system),
+            PromptSection::Persona => count(&sections.persona),
+            PromptSection::Memories => count(&sections.memories.join("\n")),
+            PromptSection::History => sections
+                .history
+                .iter()
+                .map(|message| count(&message.content) + MESSAGE_OVERHEAD_TOKENS)
+                .sum(),
+            PromptSection::UserInput => count(&sections.user_input),
+        }
+    }
+
+    /// Shrinks `section` to at most `target` tokens.
+    fn shrink(
+        &self,
+        sections: &mut PromptSections,
+        section: PromptSection,
+        target: usize,
+        usage: &mut BTreeMap<PromptSection, SectionUsage>,
+    ) {
+        let usage = usage
+            .get_mut(&section)
+            .expect("every section has a usage entry");
+        match section {
+            PromptSection::Memories => {
+                while !sections.memories.is_empty()
+                    && self.section_tokens(sections, section) > target
+                {
+                    sections.memories.pop();
+                    usage.dropped_items += 1;
+                }
+            }
+            PromptSection::History => {
+                while !sections.history.is_empty()
+                    && self.section_tokens(sections, section) > target
+                {
+                    sections.history.remove(0);
+                    usage.dropped_items += 1;
+                    // Never open the window with a reply to a message that is gone.
+                    while sections
+                        .history
+                        .first()
+                        .is_some_and(|m| m.role != ChatRole::User)
+                    {
+                        sections.history.remove(0);
+                        usage.dropped_items += 1;
+                    }
+                }
+            }
+            PromptSection::System | PromptSection::Persona | PromptSection::UserInput => {
+                let text = match section {
+                    PromptSection::System => &mut sections.system,
+                    PromptSection::Persona => &mut sections.persona,
+                    _ => &mut sections.user_input,
+                };
+                if !text.is_empty() && self.estimator.count(text) > target {
+                    *text = self.truncate(text, target);
+                    usage.truncated = true;
+                }
+            }
+        }
+    }
+
+    /// Longest prefix of `text` that, with a truncation marker, fits in `target` tokens.
+    fn truncate(&self, text: &str, target: usize) -> String {
+        let boundaries: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+        let with_marker = |chars: usize| {
+            let end = boundaries.get(chars).copied().unwrap_or(text.len());
+            format!("{}{TRUNCATION_MARKER}", &text[..end])
+        };
+
+        let (mut lo, mut hi) = (0, boundaries.len());
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            if self.estimator.count(&with_marker(mid)) <= target {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        match lo {
+            0 => String::new(),
+            chars => with_marker(chars),
+        }
+    }
+}
+
+const ALL_SECTIONS: [PromptSection; 5] = [
+    PromptSection::System,
+    PromptSection::Persona,
+    PromptSection::Memories,
+    PromptSection::History,
+    PromptSection::UserInput,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One token per character, so budgets read as string lengths.
+    fn assembler(budget: PromptBudget) -> PromptAssembler {
+        PromptAssembler::new(budget).with_estimator(Arc::new(CharRatioEstimator {
+            chars_per_token: 1.0,
+        }))
+    }
+
+    /// No caps, no reserve, and room for `content_tokens` besides the framing.
+    fn content_budget(content_tokens: usize) -> PromptAssembler {
+        let unbounded = assembler(PromptBudget::default());
+        let mut budget = PromptBudget::default()
+            .with_context_tokens(content_tokens + unbounded.framing_tokens())
+            .with_reserved_tokens(0);
+        budget.section_caps.clear();
+        assembler(budget)
+    }
+
+    /// A 20-character message; even turns are the user's.
+    fn turn(n: usize) -> ChatMessage {
+        let role = match n % 2 {
+            0 => ChatRole::User,
+            _ => ChatRole::Assistant,
+        };
+        ChatMessage::new(role, format!("turn {n:<15}"))
+    }
+
+    /// 477 tokens of content: system 100, persona 100, memories 83, history 144, input 50.
+    fn sections() -> PromptSections {
+        PromptSections::new("U".repeat(50))
+            .with_system("S".repeat(100))
+            .with_persona("P".repeat(100))
+            .with_memories((0..4).map(|i| format!("memory {i:<13}")).collect())
+            .with_history((0..6).map(turn).collect())
+    }
+
+    fn usage(prompt: &AssembledPrompt, section: PromptSection) -> &SectionUsage {
+        prompt.usage.iter().find(|u| u.section == section).unwrap()
+    }
+
+    #[test]
+    fn fits_without_changes_when_under_budget() {
+        let prompt = content_budget(477).assemble(sections());
+
+        assert!(prompt
+            .usage
+            .iter()
+            .all(|u| u.dropped_items == 0 && !u.truncated));
+        assert_eq!(
+            prompt.total_tokens,
+            477 + content_budget(0).framing_tokens()
+        );
+    }
+
+    #[test]
+    fn same_input_gives_the_same_prompt() {
+        let assembler = content_budget(200);
+
+        let first = assembler.assemble(sections());
+        let second = assembler.assemble(sections());
+
+        assert_eq!(first.to_messages(), second.to_messages());
+        assert_eq!(first.usage, second.usage);
+        assert_eq!(first.total_tokens, second.total_tokens);
+    }
+
+    #[test]
+    fn sections_give_way_in_drop_order() {
+        // 30 over: the least relevant memories go first.
+        let prompt = content_budget(447).assemble(sections());
+        assert_eq!(prompt.sections.memories.len(), 2);
+        assert_eq!(prompt.sections.memories[0], "memory 0            ");
+        assert_eq!(usage(&prompt, PromptSection::History).dropped_items, 0);
+
+        // Every memory, then the oldest exchanges.
+        let prompt = content_budget(344).assemble(sections());
+        assert!(prompt.sections.memories.is_empty());
+        assert_eq!(usage(&prompt, PromptSection::History).dropped_items, 4);
+        assert_eq!(prompt.sections.history, sections().history[4..]);
+        assert!(!usage(&prompt, PromptSection::Persona).truncated);
+
+        // Then the persona is cut; instructions and the user's message stay whole.
+        let prompt = content_budget(180).assemble(sections());
+        assert!(prompt.sections.history.is_empty());
+        assert_eq!(prompt.sections.persona, format!("{}…", "P".repeat(29)));
+        assert_eq!(prompt.sections.system, "S".repeat(100));
+        assert_eq!(prompt.sections.user_input, "U".repeat(50));
+        assert!(prompt.total_tokens <= content_budget(180).budget().max_prompt_tokens());
+    }
+
+    #[test]
+    fn section_caps_apply_within_budget() {
+        let budget = PromptBudget::default()
+            .with_section_cap(PromptSection::Memories, 45)
+            .with_section_cap(PromptSection::History, 50);
+
+        let prompt = assembler(budget).assemble(sections());
+
+        assert_eq!(prompt.sections.memories.len(), 2);
+        assert_eq!(prompt.sections.history, sections().history[4..]);
+        assert_eq!(usage(&prompt, PromptSection::Memories).tokens, 41);
+        assert_eq!(usage(&prompt, PromptSection::History).tokens, 48);
+    }
+
+    #[test]
+    fn trimmed_history_starts_with_the_user() {
+        let history = (0..4).map(turn).collect();
+        let budget = PromptBudget::default().with_section_cap(PromptSection::History, 80);
+
+        let prompt = assembler(budget).assemble(PromptSections::new("hi").with_history(history));
+
+        // Dropping one turn would fit, but would open on a reply.
+        assert_eq!(usage(&prompt, PromptSection::History).dropped_items, 2);
+        assert_eq!(prompt.sections.history[0].role, ChatRole::User);
+    }
+
+    #[test]
+    fn truncation_fits_the_target_on_char_boundaries() {
+        let assembler = content_budget(0);
+        let text = "héllo wörld ✓✓✓";
+
+        let cut = assembler.truncate(text, 8);
+        assert_eq!(cut, "héllo w…");
+        assert_eq!(assembler.truncate(text, 1), "");
+        assert_eq!(assembler.truncate(text, 40), format!("{text}…"));
+    }
+}
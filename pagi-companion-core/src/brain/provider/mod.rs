@@ -0,0 +1,210 @@
+pub mod ollama;
+pub mod openai;
+pub mod raw;
+pub mod resilient;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::stream;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::brain::config::{LlmEndpoint, LlmProviderKind};
+use crate::brain::stream::TextStream;
+
+pub use self::ollama::OllamaProvider;
+pub use self::openai::OpenAiChatProvider;
+pub use self::raw::RawProvider;
+pub use self::resilient::ResilientProvider;
+
+/// Author of a chat message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+impl ChatRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatRole::System => "system",
+            ChatRole::User => "user",
+            ChatRole::Assistant => "assistant",
+        }
+    }
+}
+
+/// One message of a chat conversation, in the shape both OpenAI and Ollama accept.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: ChatRole, content: impl Into<String>) -> Self {
+        ChatMessage {
+            role,
+            content: content.into(),
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::System, content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::Assistant, content)
+    }
+}
+
+/// A JSON Schema the reply must conform to.
+#[derive(Debug, Clone)]
+pub struct ResponseSchema {
+    /// Identifier required by some servers (e.g. OpenAI's `json_schema.name`).
+    pub name: String,
+    pub schema: Value,
+}
+
+/// A provider-neutral completion request.
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    pub messages: Vec<ChatMessage>,
+    pub temperature: f32,
+    /// Constrains the reply where the server supports it (`response_format` for OpenAI,
+    /// `format` for Ollama); the raw schema describes it in the system prompt instead.
+    pub response_schema: Option<ResponseSchema>,
+}
+
+/// A chat backend speaking one wire format.
+///
+/// Implementations translate `ChatRequest` into their server's schema and return only
+/// the assistant's text, so callers never see provider-specific envelopes.
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Short name for logs (e.g. `openai`).
+    fn name(&self) -> &'static str;
+
+    /// Model requested from the server.
+    fn model(&self) -> &str;
+
+    /// Sends `request` and returns the assistant message text.
+    async fn complete(&self, request: &ChatRequest) -> Result<String>;
+
+    /// Like `complete`, but yields the assistant text as it is generated.
+    ///
+    /// The default sends a normal request and yields its text as a single chunk.
+    async fn complete_stream(&self, request: &ChatRequest) -> Result<TextStream> {
+        let text = self.complete(request).await?;
+        Ok(Box::pin(stream::once(async move { Ok(text) })))
+    }
+}
+
+/// Builds the provider for `endpoint.provider`.
+pub fn build_provider(endpoint: LlmEndpoint, http_client: Client) -> Arc<dyn LlmProvider> {
+    match endpoint.provider {
+        LlmProviderKind::OpenAiChat => Arc::new(OpenAiChatProvider::new(endpoint, http_client)),
+        LlmProviderKind::Ollama => Arc::new(OllamaProvider::new(endpoint, http_client)),
+        LlmProviderKind::Raw => Arc::new(RawProvider::new(endpoint, http_client)),
+    }
+}
+
+/// Transport-level failure of an LLM request, kept typed so callers can decide whether
+/// a retry makes sense (see `ResilientProvider`).
+#[derive(Debug)]
+pub struct LlmHttpError {
+    /// `None` for connection errors and timeouts.
+    pub status: Option<StatusCode>,
+    /// Server-requested wait from the `Retry-After` header.
+    pub retry_after: Option<Duration>,
+    pub message: String,
+}
+
+impl LlmHttpError {
+    /// Overload, server errors and network failures; other 4xx will not succeed on retry.
+    pub fn is_transient(&self) -> bool {
+        match self.status {
+            None => true,
+            Some(status) => {
+                status == StatusCode::TOO_MANY_REQUESTS
+                    || status == StatusCode::REQUEST_TIMEOUT
+                    || status.is_server_error()
+            }
+        }
+    }
+
+    fn network(e: reqwest::Error) -> Self {
+        LlmHttpError {
+            status: e.status(),
+            retry_after: None,
+            message: format!("LLM API request failed: {e}"),
+        }
+    }
+}
+
+impl std::fmt::Display for LlmHttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for LlmHttpError {}
+
+/// Reads `Retry-After` as delay-seconds or an HTTP date.
+fn parse_retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = at.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// POSTs `body` as JSON and returns the response body, failing on any non-200 status.
+async fn post_json<T: Serialize + ?Sized>(
+    http_client: &Client,
+    endpoint: &LlmEndpoint,
+    body: &T,
+) -> Result<String> {
+    let response = send_json(http_client, endpoint, body).await?;
+    Ok(response.text().await.map_err(LlmHttpError::network)?)
+}
+
+/// POSTs `body` as JSON, failing on any non-200 status; the body is left unread so it
+/// can be streamed.
+async fn send_json<T: Serialize + ?Sized>(
+    http_client: &Client,
+    endpoint: &LlmEndpoint,
+    body: &T,
+) -> Result<Response> {
+    let mut request = http_client.post(endpoint.api_url.clone()).json(body);
+    if !endpoint.api_key.is_empty() {
+        request = request.header("Authorization", format!("Bearer {}", endpoint.api_key));
+    }
+    let response = request.send().await.map_err(LlmHttpError::network)?;
+
+    if response.status() != StatusCode::OK {
+        let status = response.status();
+        let retry_after = parse_retry_after(&response);
+        let body = response.text().await.unwrap_or_default();
+        return Err(LlmHttpError {
+            status: Some(status),
+            retry_after,
+            message: format!("LLM API request failed (status={status}): {body}"),
+        }
+        .into());
+    }
+    Ok(response)
+}
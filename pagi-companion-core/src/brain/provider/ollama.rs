@@ -0,0 +1,207 @@
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use super::{post_json, send_json, ChatMessage, ChatRequest, LlmProvider};
+use crate::brain::config::LlmEndpoint;
+use crate::brain::stream::{line_stream, LineEvent, TextStream};
+
+#[derive(Debug, Serialize)]
+struct OllamaChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    /// Ollama streams NDJSON unless told otherwise.
+    stream: bool,
+    /// JSON Schema the reply is constrained to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'a serde_json::Value>,
+    options: OllamaOptions,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaOptions {
+    temperature: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaChatResponse {
+    #[serde(default)]
+    message: Option<OllamaMessage>,
+    /// Set on the final object of a stream.
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaMessage {
+    content: String,
+}
+
+/// Assistant text of a non-streamed chat.
+fn chat_text(body: &str) -> Result<String> {
+    let parsed: OllamaChatResponse =
+        serde_json::from_str(body).map_err(|e| anyhow!("Invalid Ollama chat response: {e}"))?;
+    if let Some(error) = parsed.error {
+        bail!("Ollama chat failed: {error}");
+    }
+    parsed
+        .message
+        .map(|message| message.content)
+        .ok_or_else(|| anyhow!("Ollama chat response has no message"))
+}
+
+/// Parses one NDJSON object of a streamed chat.
+fn parse_ndjson_line(line: &str) -> Result<LineEvent> {
+    if line.trim().is_empty() {
+        return Ok(LineEvent::Skip);
+    }
+    let chunk: OllamaChatResponse =
+        serde_json::from_str(line).map_err(|e| anyhow!("Invalid Ollama chat chunk: {e}"))?;
+    if let Some(error) = chunk.error {
+        bail!("Ollama stream failed: {error}");
+    }
+    // The final object may still carry text; the body ends right after it.
+    match chunk.message {
+        Some(message) if !message.content.is_empty() => Ok(LineEvent::Text(message.content)),
+        _ if chunk.done => Ok(LineEvent::Done),
+        _ => Ok(LineEvent::Skip),
+    }
+}
+
+/// Ollama's native `/api/chat` endpoint.
+pub struct OllamaProvider {
+    endpoint: LlmEndpoint,
+    http_client: Client,
+}
+
+impl OllamaProvider {
+    pub fn new(endpoint: LlmEndpoint, http_client: Client) -> Self {
+        OllamaProvider {
+            endpoint,
+            http_client,
+        }
+    }
+
+    fn chat_request<'a>(&'a self, request: &'a ChatRequest, stream: bool) -> OllamaChatRequest<'a> {
+        OllamaChatRequest {
+            model: &self.endpoint.model_name,
+            messages: &request.messages,
+            stream,
+            format: request
+                .response_schema
+                .as_ref()
+                .map(|schema| &schema.schema),
+            options: OllamaOptions {
+                temperature: request.temperature,
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn model(&self) -> &str {
+        &self.endpoint.model_name
+    }
+
+    async fn complete(&self, request: &ChatRequest) -> Result<String> {
+        let body = post_json(
+            &self.http_client,
+            &self.endpoint,
+            &self.chat_request(request, false),
+        )
+        .await?;
+        chat_text(&body)
+    }
+
+    async fn complete_stream(&self, request: &ChatRequest) -> Result<TextStream> {
+        let response = send_json(
+            &self.http_client,
+            &self.endpoint,
+            &self.chat_request(request, true),
+        )
+        .await?;
+        Ok(line_stream(response, parse_ndjson_line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brain::stream::tests::lines;
+
+    #[tokio::test]
+    async fn ndjson_objects_are_read_across_chunks() {
+        let out = lines(
+            &[
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"{\\\"respo",
+                "nse\\\": \\\"\"},\"done\":false}\n{\"message\":{\"content\":\"Hi\\\\\\\"\"}",
+                ",\"done\":false}\n\n{\"message\":{\"content\":\"\\\"}\"},\"done\":true}\n",
+            ],
+            parse_ndjson_line,
+        )
+        .await;
+
+        let text: String = out.into_iter().map(Result::unwrap).collect();
+        assert_eq!(text, r#"{"response": "Hi\""}"#);
+    }
+
+    #[tokio::test]
+    async fn ndjson_stream_stops_at_done() {
+        let out = lines(
+            &[
+                "{\"message\":{\"content\":\"a\"},\"done\":false}\n",
+                "{\"message\":{\"content\":\"\"},\"done\":true}\n",
+                "{\"message\":{\"content\":\"b\"},\"done\":false}\n",
+            ],
+            parse_ndjson_line,
+        )
+        .await;
+
+        assert_eq!(out, vec![Ok("a".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn ndjson_error_line_ends_the_stream() {
+        let out = lines(
+            &[
+                "{\"message\":{\"content\":\"a\"},\"done\":false}\n{\"error\":\"model not found\"}\n",
+                "{\"message\":{\"content\":\"b\"},\"done\":false}\n",
+            ],
+            parse_ndjson_line,
+        )
+        .await;
+
+        assert_eq!(out.len(), 2);
+        assert!(out[1].as_ref().unwrap_err().contains("model not found"));
+    }
+
+    #[test]
+    fn chat_text_is_the_message_content() {
+        let body = include_str!("../../../tests/fixtures/llm/ollama_chat.json");
+
+        let text = chat_text(body).unwrap();
+
+        assert!(text.starts_with(r#"{"response": "Morning! Coffee first?""#));
+    }
+
+    #[test]
+    fn chat_without_content_is_an_error() {
+        let body = include_str!("../../../tests/fixtures/llm/ollama_no_message.json");
+        let err = chat_text(body).unwrap_err().to_string();
+        assert!(err.contains("no message"), "{err}");
+
+        let body = include_str!("../../../tests/fixtures/llm/ollama_error.json");
+        let err = chat_text(body).unwrap_err().to_string();
+        assert!(err.contains("not found"), "{err}");
+
+        assert!(chat_text(r#"{"message": {"role": "assistant"}, "done": true}"#).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use super::{post_json, send_json, ChatMessage, ChatRequest, LlmProvider, ResponseSchema};
+use crate::brain::config::LlmEndpoint;
+use crate::brain::stream::{line_stream, LineEvent, TextStream};
+
+#[derive(Debug, Serialize)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    temperature: f32,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat<'a>>,
+}
+
+impl<'a> CompletionRequest<'a> {
+    fn new(model: &'a str, request: &'a ChatRequest, stream: bool) -> Self {
+        CompletionRequest {
+            model,
+            messages: &request.messages,
+            temperature: request.temperature,
+            stream,
+            response_format: request.response_schema.as_ref().map(ResponseFormat::new),
+        }
+    }
+}
+
+/// `{"type": "json_schema", "json_schema": {...}}` structured-output request.
+#[derive(Debug, Serialize)]
+struct ResponseFormat<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    json_schema: JsonSchemaFormat<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonSchemaFormat<'a> {
+    name: &'a str,
+    schema: &'a serde_json::Value,
+    /// Strict mode requires every property to be required, which optional fields are not.
+    strict: bool,
+}
+
+impl<'a> ResponseFormat<'a> {
+    fn new(response_schema: &'a ResponseSchema) -> Self {
+        ResponseFormat {
+            kind: "json_schema",
+            json_schema: JsonSchemaFormat {
+                name: &response_schema.name,
+                schema: &response_schema.schema,
+                strict: false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: AssistantMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct AssistantMessage {
+    /// `null` when the model only produced tool calls or was filtered.
+    content: Option<String>,
+}
+
+/// One `data:` event of a streamed completion.
+#[derive(Debug, Deserialize)]
+struct CompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    /// Some servers report mid-stream failures as an event instead of a status code.
+    #[serde(default)]
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: ChunkDelta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ChunkDelta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// Assistant text of a non-streamed completion.
+fn completion_text(body: &str) -> Result<String> {
+    let parsed: CompletionResponse =
+        serde_json::from_str(body).map_err(|e| anyhow!("Invalid chat completion response: {e}"))?;
+    parsed
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.message.content)
+        .ok_or_else(|| anyhow!("Chat completion response has no assistant content"))
+}
+
+/// Parses one line of a server-sent event stream.
+fn parse_sse_line(line: &str) -> Result<LineEvent> {
+    // Blank lines separate events; other fields (`event:`, `id:`, `: comment`) are unused.
+    let Some(data) = line.strip_prefix("data:") else {
+        return Ok(LineEvent::Skip);
+    };
+    let data = data.trim();
+    if data == "[DONE]" {
+        return Ok(LineEvent::Done);
+    }
+
+    let chunk: CompletionChunk =
+        serde_json::from_str(data).map_err(|e| anyhow!("Invalid chat completion chunk: {e}"))?;
+    if let Some(error) = chunk.error {
+        bail!("LLM API stream failed: {error}");
+    }
+    Ok(chunk
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.delta.content)
+        .map_or(LineEvent::Skip, LineEvent::Text))
+}
+
+/// OpenAI-compatible `/v1/chat/completions` (OpenAI, vLLM, llama.cpp server, LM Studio).
+pub struct OpenAiChatProvider {
+    endpoint: LlmEndpoint,
+    http_client: Client,
+}
+
+impl OpenAiChatProvider {
+    pub fn new(endpoint: LlmEndpoint, http_client: Client) -> Self {
+        OpenAiChatProvider {
+            endpoint,
+            http_client,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiChatProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn model(&self) -> &str {
+        &self.endpoint.model_name
+    }
+
+    async fn complete(&self, request: &ChatRequest) -> Result<String> {
+        let body = post_json(
+            &self.http_client,
+            &self.endpoint,
+            &CompletionRequest::new(&self.endpoint.model_name, request, false),
+        )
+        .await?;
+        completion_text(&body)
+    }
+
+    async fn complete_stream(&self, request: &ChatRequest) -> Result<TextStream> {
+        let response = send_json(
+            &self.http_client,
+            &self.endpoint,
+            &CompletionRequest::new(&self.endpoint.model_name, request, true),
+        )
+        .await?;
+        Ok(line_stream(response, parse_sse_line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brain::stream::tests::lines;
+
+    #[tokio::test]
+    async fn sse_deltas_are_read_across_chunks() {
+        let out = lines(
+            &[
+                ": keep-alive\n\ndata: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\"{\\\"resp",
+                "onse\\\": \\\"Hi\\\"}\"}}]}\r\n\r\ndata: [DO",
+                "NE]\n\ndata: {\"choices\":[{\"delta\":{\"content\":\"late\"}}]}\n",
+            ],
+            parse_sse_line,
+        )
+        .await;
+
+        assert_eq!(out, vec![Ok(r#"{"response": "Hi"}"#.to_string())]);
+    }
+
+    #[tokio::test]
+    async fn sse_error_event_ends_the_stream() {
+        let out = lines(
+            &[
+                "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n",
+                "data: {\"error\":{\"message\":\"overloaded\"}}\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n",
+            ],
+            parse_sse_line,
+        )
+        .await;
+
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0], Ok("Hel".to_string()));
+        assert!(out[1].as_ref().unwrap_err().contains("overloaded"));
+    }
+
+    #[tokio::test]
+    async fn malformed_sse_data_is_an_error() {
+        let out = lines(&["data: {\"choices\": [\n"], parse_sse_line).await;
+        assert!(out[0]
+            .as_ref()
+            .unwrap_err()
+            .contains("Invalid chat completion chunk"));
+    }
+
+    #[test]
+    fn completion_text_is_the_assistant_content() {
+        let body = include_str!("../../../tests/fixtures/llm/openai_completion.json");
+
+        let text = completion_text(body).unwrap();
+
+        assert_eq!(
+            text,
+            r#"{"response": "Hey you \"stranger\"", "suggested_emotion_change": "Happy: +0.1", "state_commands": {}}"#
+        );
+    }
+
+    #[test]
+    fn completion_without_content_is_an_error() {
+        let body = include_str!("../../../tests/fixtures/llm/openai_no_content.json");
+        let err = completion_text(body).unwrap_err().to_string();
+        assert!(err.contains("no assistant content"), "{err}");
+
+        for body in [r#"{"choices": []}"#, r#"{"choices": [{"message": {}}]}"#] {
+            assert!(completion_text(body).is_err(), "{body}");
+        }
+        assert!(completion_text(r#"{"object": "error"}"#).is_err());
+    }
+}
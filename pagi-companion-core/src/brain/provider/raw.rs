@@ -0,0 +1,175 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use super::{post_json, send_json, ChatRequest, ChatRole, LlmProvider};
+use crate::brain::config::LlmEndpoint;
+use crate::brain::stream::{text_body_stream, TextStream};
+
+/// The original custom request schema; the response body is the model text itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TacticalLLMRequest {
+    pub model: String,
+    pub temperature: f32,
+    pub system_prompt: String,
+    pub user_input: String,
+}
+
+impl TacticalLLMRequest {
+    /// Flattens a chat into the single system prompt / user input pair of this schema.
+    ///
+    /// System messages are joined into `system_prompt`. A lone user message becomes
+    /// `user_input` verbatim; longer conversations are rendered as `role: content` lines.
+    /// The schema has no structured-output field, so a response schema is appended to
+    /// `system_prompt` as text.
+    pub fn from_chat(model: &str, request: &ChatRequest) -> Self {
+        let mut system_prompt = request
+            .messages
+            .iter()
+            .filter(|m| m.role == ChatRole::System)
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        if let Some(response_schema) = &request.response_schema {
+            system_prompt.push_str(&format!(
+                "\n\nReply with a single JSON object matching this JSON Schema:\n{}",
+                response_schema.schema
+            ));
+        }
+
+        let turns: Vec<_> = request
+            .messages
+            .iter()
+            .filter(|m| m.role != ChatRole::System)
+            .collect();
+        let user_input = match turns.as_slice() {
+            [only] if only.role == ChatRole::User => only.content.clone(),
+            _ => turns
+                .iter()
+                .map(|m| format!("{}: {}", m.role.as_str(), m.content))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+
+        TacticalLLMRequest {
+            model: model.to_string(),
+            temperature: request.temperature,
+            system_prompt,
+            user_input,
+        }
+    }
+}
+
+/// The model text of a response body; a blank body means the model produced nothing.
+fn body_text(body: String) -> Result<String> {
+    if body.trim().is_empty() {
+        bail!("Raw LLM response is empty");
+    }
+    Ok(body)
+}
+
+/// Servers speaking `TacticalLLMRequest` (e.g. in-house research gateways).
+pub struct RawProvider {
+    endpoint: LlmEndpoint,
+    http_client: Client,
+}
+
+impl RawProvider {
+    pub fn new(endpoint: LlmEndpoint, http_client: Client) -> Self {
+        RawProvider {
+            endpoint,
+            http_client,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for RawProvider {
+    fn name(&self) -> &'static str {
+        "raw"
+    }
+
+    fn model(&self) -> &str {
+        &self.endpoint.model_name
+    }
+
+    async fn complete(&self, request: &ChatRequest) -> Result<String> {
+        let payload = TacticalLLMRequest::from_chat(&self.endpoint.model_name, request);
+        body_text(post_json(&self.http_client, &self.endpoint, &payload).await?)
+    }
+
+    /// The body is the model text, so a chunked body is passed through as it arrives
+    /// (and a non-streaming server simply yields one chunk).
+    async fn complete_stream(&self, request: &ChatRequest) -> Result<TextStream> {
+        let payload = TacticalLLMRequest::from_chat(&self.endpoint.model_name, request);
+        let response = send_json(&self.http_client, &self.endpoint, &payload).await?;
+        Ok(text_body_stream(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brain::provider::{ChatMessage, ResponseSchema};
+
+    fn chat(messages: Vec<ChatMessage>) -> ChatRequest {
+        ChatRequest {
+            messages,
+            temperature: 0.7,
+            response_schema: None,
+        }
+    }
+
+    #[test]
+    fn lone_user_message_is_sent_verbatim() {
+        let request = chat(vec![
+            ChatMessage::system("Be kind."),
+            ChatMessage::system("Stay in character."),
+            ChatMessage::user("Hi there"),
+        ]);
+
+        let payload = TacticalLLMRequest::from_chat("tactical-1", &request);
+
+        assert_eq!(payload.model, "tactical-1");
+        assert_eq!(payload.temperature, 0.7);
+        assert_eq!(payload.system_prompt, "Be kind.\n\nStay in character.");
+        assert_eq!(payload.user_input, "Hi there");
+    }
+
+    #[test]
+    fn conversations_become_role_lines_and_schemas_text() {
+        let mut request = chat(vec![
+            ChatMessage::system("Be kind."),
+            ChatMessage::user("Hi"),
+            ChatMessage::assistant("Hello!"),
+            ChatMessage::user("How are you?"),
+        ]);
+        request.response_schema = Some(ResponseSchema {
+            name: "reply".to_string(),
+            schema: serde_json::json!({"type": "object"}),
+        });
+
+        let payload = TacticalLLMRequest::from_chat("tactical-1", &request);
+
+        assert_eq!(
+            payload.user_input,
+            "user: Hi\nassistant: Hello!\nuser: How are you?"
+        );
+        assert!(payload
+            .system_prompt
+            .starts_with("Be kind.\n\nReply with a single JSON"));
+        assert!(payload.system_prompt.ends_with(r#"{"type":"object"}"#));
+    }
+
+    #[test]
+    fn blank_body_is_an_error() {
+        assert_eq!(
+            body_text("{\"response\": \"hi\"}".into()).unwrap(),
+            "{\"response\": \"hi\"}"
+        );
+        for body in ["", " \n"] {
+            assert!(body_text(body.to_string()).is_err(), "{body:?}");
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use rand::Rng;
+use reqwest::Client;
+use tracing::{info, warn};
+
+use super::{build_provider, ChatRequest, LlmHttpError, LlmProvider};
+use crate::brain::config::{CircuitBreakerConfig, RetryPolicy, TacticalLLMConfig};
+use crate::brain::stream::TextStream;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls flow normally.
+    Closed,
+    /// Calls are rejected until the cooldown expires.
+    Open,
+    /// One trial call is in flight to probe recovery.
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { since: Instant },
+}
+
+/// Consecutive-failure circuit breaker for one endpoint.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: String,
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: impl Into<String>, config: CircuitBreakerConfig) -> Self {
+        CircuitBreaker {
+            name: name.into(),
+            config,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.lock() {
+            BreakerState::Closed { .. } => CircuitState::Closed,
+            BreakerState::Open { .. } => CircuitState::Open,
+            BreakerState::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether a call may proceed now. An expired open circuit lets one trial through;
+    /// a trial that never reports back (e.g. a dropped future) is replaced after another
+    /// cooldown.
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.lock();
+        let now = Instant::now();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if now >= until => {
+                info!(
+                    endpoint = self.name.as_str(),
+                    "tactical_llm_circuit_half_open"
+                );
+                *state = BreakerState::HalfOpen { since: now };
+                true
+            }
+            BreakerState::HalfOpen { since }
+                if now.duration_since(since) >= self.config.cooldown =>
+            {
+                *state = BreakerState::HalfOpen { since: now };
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => false,
+        }
+    }
+
+    /// The endpoint answered successfully.
+    pub fn record_success(&self) {
+        let mut state = self.lock();
+        if !matches!(*state, BreakerState::Closed { .. }) {
+            info!(endpoint = self.name.as_str(), "tactical_llm_circuit_closed");
+        }
+        *state = BreakerState::Closed { failures: 0 };
+    }
+
+    /// The endpoint failed transiently.
+    pub fn record_failure(&self) {
+        let mut state = self.lock();
+        let failures = match *state {
+            BreakerState::Closed { failures } => failures + 1,
+            // A failed trial reopens the circuit straight away.
+            BreakerState::HalfOpen { .. } | BreakerState::Open { .. } => {
+                self.config.failure_threshold
+            }
+        };
+        if failures >= self.config.failure_threshold {
+            warn!(
+                endpoint = self.name.as_str(),
+                failures = failures,
+                cooldown_ms = self.config.cooldown.as_millis() as u64,
+                "tactical_llm_circuit_opened"
+            );
+            *state = BreakerState::Open {
+                until: Instant::now() + self.config.cooldown,
+            };
+        } else {
+            *state = BreakerState::Closed { failures };
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        // The state is always left consistent, so a poisoned lock is still usable.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+struct Route {
+    label: String,
+    provider: Arc<dyn LlmProvider>,
+    breaker: CircuitBreaker,
+}
+
+/// Wraps one or more endpoints with retries, per-endpoint circuit breakers and failover.
+///
+/// Each endpoint is retried on transient failures with jittered exponential backoff (or
+/// the server's `Retry-After`); when it gives up or its circuit is open, the next
+/// endpoint is tried. Streams are only retried until they are established.
+pub struct ResilientProvider {
+    routes: Vec<Route>,
+    retry: RetryPolicy,
+    breaker: CircuitBreakerConfig,
+}
+
+impl ResilientProvider {
+    pub fn new(retry: RetryPolicy, breaker: CircuitBreakerConfig) -> Self {
+        ResilientProvider {
+            routes: Vec::new(),
+            retry,
+            breaker,
+        }
+    }
+
+    /// The primary endpoint of `config` followed by its fallbacks.
+    pub fn from_config(config: &TacticalLLMConfig, http_client: Client) -> Self {
+        config.endpoints().into_iter().fold(
+            Self::new(config.retry.clone(), config.circuit_breaker.clone()),
+            |resilient, endpoint| {
+                let label = endpoint.label();
+                resilient.with_endpoint(label, build_provider(endpoint, http_client.clone()))
+            },
+        )
+    }
+
+    /// Appends an endpoint, tried after the ones already added.
+    pub fn with_endpoint(
+        mut self,
+        label: impl Into<String>,
+        provider: Arc<dyn LlmProvider>,
+    ) -> Self {
+        let label = label.into();
+        self.routes.push(Route {
+            breaker: CircuitBreaker::new(label.clone(), self.breaker.clone()),
+            label,
+            provider,
+        });
+        self
+    }
+
+    /// Circuit state of the endpoint labelled `label`.
+    pub fn circuit_state(&self, label: &str) -> Option<CircuitState> {
+        self.routes
+            .iter()
+            .find(|route| route.label == label)
+            .map(|route| route.breaker.state())
+    }
+
+    /// Backoff before retry number `attempt` (0-based): doubled per attempt, capped, then
+    /// jittered into `[delay / 2, delay]` so clients do not retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .retry
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.retry.max_delay);
+        let half = delay / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+
+    /// Makes `call` against each endpoint in turn, with retries and circuit breaking.
+    async fn run<T, F, Fut>(&self, call: F) -> Result<T>
+    where
+        F: Fn(Arc<dyn LlmProvider>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut last_error = None;
+        for (index, route) in self.routes.iter().enumerate() {
+            let endpoint = route.label.as_str();
+            if index > 0 {
+                warn!(endpoint = endpoint, "tactical_llm_fallback");
+            }
+
+            let mut attempt = 0;
+            loop {
+                if !route.breaker.try_acquire() {
+                    warn!(endpoint = endpoint, "tactical_llm_circuit_open_skipped");
+                    last_error = Some(anyhow!("Circuit open for LLM endpoint {endpoint}"));
+                    break;
+                }
+
+                let error = match call(route.provider.clone()).await {
+                    Ok(outcome) => {
+                        route.breaker.record_success();
+                        return Ok(outcome);
+                    }
+                    Err(e) => e,
+                };
+
+                let http_error = error.downcast_ref::<LlmHttpError>();
+                if !http_error.is_some_and(LlmHttpError::is_transient) {
+                    // Says nothing about the endpoint's health, so the breaker is left as is.
+                    warn!(endpoint = endpoint, error = %error, "tactical_llm_endpoint_failed");
+                    last_error = Some(error);
+                    break;
+                }
+                route.breaker.record_failure();
+
+                let status = http_error.and_then(|e| e.status).map(|s| s.as_u16());
+                let retry_after = http_error.and_then(|e| e.retry_after);
+                if attempt >= self.retry.max_retries || route.breaker.state() == CircuitState::Open
+                {
+                    warn!(endpoint = endpoint, attempts = attempt + 1, error = %error, "tactical_llm_retries_exhausted");
+                    last_error = Some(error);
+                    break;
+                }
+                let delay = match retry_after {
+                    Some(wait) if wait > self.retry.max_retry_after => {
+                        warn!(
+                            endpoint = endpoint,
+                            retry_after_ms = wait.as_millis() as u64,
+                            "tactical_llm_retry_after_too_long"
+                        );
+                        last_error = Some(error);
+                        break;
+                    }
+                    Some(wait) => wait,
+                    None => self.backoff(attempt),
+                };
+
+                attempt += 1;
+                warn!(
+                    endpoint = endpoint,
+                    attempt = attempt,
+                    status = status,
+                    reason = %error,
+                    delay_ms = delay.as_millis() as u64,
+                    honored_retry_after = retry_after.is_some(),
+                    "tactical_llm_retry"
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+
+        let error = last_error.unwrap_or_else(|| anyhow!("No LLM endpoints configured"));
+        Err(match self.routes.len() {
+            0 | 1 => error,
+            n => error.context(format!("All {n} LLM endpoints failed")),
+        })
+    }
+}
+
+#[async_trait]
+impl LlmProvider for ResilientProvider {
+    fn name(&self) -> &'static str {
+        self.routes
+            .first()
+            .map_or("none", |route| route.provider.name())
+    }
+
+    fn model(&self) -> &str {
+        self.routes
+            .first()
+            .map_or("", |route| route.provider.model())
+    }
+
+    async fn complete(&self, request: &ChatRequest) -> Result<String> {
+        self.run(|provider| async move { provider.complete(request).await })
+            .await
+    }
+
+    async fn complete_stream(&self, request: &ChatRequest) -> Result<TextStream> {
+        self.run(|provider| async move { provider.complete_stream(request).await })
+            .await
+    }
+}
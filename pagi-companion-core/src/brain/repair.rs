@@ -0,0 +1,483 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::brain::provider::{ChatMessage, ChatRequest, LlmProvider};
+use crate::brain::schema::validate_structured_output;
+use crate::companion::models::StructuredLLMOutput;
+
+/// How `TacticalLLM` recovers from output that does not parse as `StructuredLLMOutput`.
+///
+/// Stages run in order: tolerant parsing, up to `max_reprompts` corrective requests, then
+/// (if enabled) a degraded output that keeps the raw text as the reply.
+#[derive(Debug, Clone)]
+pub struct RepairPolicy {
+    /// Accept trailing commas, single-quoted strings and missing optional fields.
+    pub tolerant_parsing: bool,
+    /// Corrective requests that show the model its parse error.
+    pub max_reprompts: u32,
+    /// Return the raw text with no state changes instead of failing the turn.
+    pub degrade_on_failure: bool,
+}
+
+impl Default for RepairPolicy {
+    fn default() -> Self {
+        RepairPolicy {
+            tolerant_parsing: true,
+            max_reprompts: 1,
+            degrade_on_failure: true,
+        }
+    }
+}
+
+impl RepairPolicy {
+    /// Strict parsing only; any malformed output fails the turn.
+    pub fn strict() -> Self {
+        RepairPolicy {
+            tolerant_parsing: false,
+            max_reprompts: 0,
+            degrade_on_failure: false,
+        }
+    }
+
+    pub fn with_tolerant_parsing(mut self, tolerant_parsing: bool) -> Self {
+        self.tolerant_parsing = tolerant_parsing;
+        self
+    }
+
+    pub fn with_max_reprompts(mut self, max_reprompts: u32) -> Self {
+        self.max_reprompts = max_reprompts;
+        self
+    }
+
+    pub fn with_degrade_on_failure(mut self, degrade_on_failure: bool) -> Self {
+        self.degrade_on_failure = degrade_on_failure;
+        self
+    }
+}
+
+/// Parses `raw_llm_text`, running the repair stages of `policy` when it is malformed.
+///
+/// `request` is the request that produced `raw_llm_text`; re-prompts extend it with the
+/// bad reply and the parse error.
+pub async fn parse_with_repair(
+    provider: &dyn LlmProvider,
+    policy: &RepairPolicy,
+    mut request: ChatRequest,
+    raw_llm_text: String,
+) -> Result<StructuredLLMOutput> {
+    let mut error = match parse_structured_output(&raw_llm_text, policy.tolerant_parsing) {
+        Ok(output) => return Ok(output),
+        Err(e) => e,
+    };
+
+    let mut reply = raw_llm_text.clone();
+    for attempt in 1..=policy.max_reprompts {
+        warn!(error = %error, attempt = attempt, "tactical_llm_reprompt");
+        request.messages.push(ChatMessage::assistant(reply));
+        request.messages.push(ChatMessage::user(format!(
+            "Your previous reply could not be parsed: {error}. Reply again with only the \
+             corrected JSON object, without any other text."
+        )));
+
+        reply = match provider.complete(&request).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                // Losing the repair attempt is no reason to lose the turn.
+                error = e;
+                break;
+            }
+        };
+        match parse_structured_output(&reply, policy.tolerant_parsing) {
+            Ok(output) => {
+                info!(attempt = attempt, "tactical_llm_output_repaired");
+                return Ok(output);
+            }
+            Err(e) => error = e,
+        }
+    }
+
+    if !policy.degrade_on_failure {
+        bail!("Failed to deserialize structured LLM output: {error}");
+    }
+    warn!(error = %error, "tactical_llm_output_degraded");
+    Ok(degraded_output(&raw_llm_text))
+}
+
+/// Keeps the model's text as the reply, with no state changes or memories.
+pub fn degraded_output(raw_llm_text: &str) -> StructuredLLMOutput {
+    let trimmed = raw_llm_text.trim();
+    let response = match trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+    {
+        Some(fenced) => fenced.trim_end_matches('`').trim(),
+        None => trimmed,
+    };
+    StructuredLLMOutput {
+        response: response.to_string(),
+        suggested_emotion_change: String::new(),
+        suggested_memory_add: None,
+        suggested_memory_tags: Vec::new(),
+        suggested_memory_importance: None,
+        suggested_memory_valence: None,
+        state_commands: Default::default(),
+    }
+}
+
+/// Extracts the JSON block of `raw_llm_text`, validates it against the
+/// `StructuredLLMOutput` schema and deserializes it, falling back to `parse_tolerant`
+/// when `tolerant` is set.
+pub fn parse_structured_output(raw_llm_text: &str, tolerant: bool) -> Result<StructuredLLMOutput> {
+    let json_block = extract_json_block(raw_llm_text).trim();
+    let strict_error = match parse_strict(json_block) {
+        Ok(output) => {
+            info!("tactical_llm_response_parsed");
+            return Ok(output);
+        }
+        Err(e) => e,
+    };
+    warn!(error = %strict_error, raw = raw_llm_text, "tactical_llm_parse_failed");
+
+    if !tolerant {
+        bail!("{strict_error}");
+    }
+    let output = parse_tolerant(json_block).map_err(|e| match e.to_string() {
+        same if same == strict_error.to_string() => anyhow!("{strict_error}"),
+        tolerant_error => anyhow!("{strict_error} (tolerant parse: {tolerant_error})"),
+    })?;
+    info!("tactical_llm_response_parsed_tolerant");
+    Ok(output)
+}
+
+fn parse_strict(json_block: &str) -> Result<StructuredLLMOutput> {
+    let value: Value = serde_json::from_str(json_block)?;
+    validate_structured_output(&value)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Lenient parse: fixes common JSON mistakes, fills in missing optional fields and
+/// clamps out-of-range scores before validating.
+fn parse_tolerant(json_block: &str) -> Result<StructuredLLMOutput> {
+    let mut value: Value = serde_json::from_str(&normalize_json(json_block))?;
+    let Some(object) = value.as_object_mut() else {
+        bail!("output is not a JSON object");
+    };
+    if !object.get("response").is_some_and(Value::is_string) {
+        bail!("missing string field `response`");
+    }
+
+    // Singular key names are the most common drift.
+    for (alias, field) in [
+        ("state_command", "state_commands"),
+        ("suggested_memory_tag", "suggested_memory_tags"),
+    ] {
+        if !object.contains_key(field) {
+            if let Some(value) = object.remove(alias) {
+                object.insert(field.to_string(), value);
+            }
+        }
+    }
+    object
+        .entry("suggested_emotion_change")
+        .or_insert_with(|| Value::String(String::new()));
+    let state_commands = object
+        .entry("state_commands")
+        .or_insert_with(|| Value::Object(Default::default()));
+    if state_commands.is_null() {
+        *state_commands = Value::Object(Default::default());
+    }
+    // Models often emit numbers or booleans as command values.
+    if let Some(commands) = state_commands.as_object_mut() {
+        for command in commands.values_mut() {
+            if !command.is_string() {
+                *command = Value::String(command.to_string());
+            }
+        }
+    }
+    for (field, min, max) in [
+        ("suggested_memory_importance", 0.0, 1.0),
+        ("suggested_memory_valence", -1.0, 1.0),
+    ] {
+        if let Some(score) = object.get_mut(field) {
+            if let Some(n) = score.as_f64() {
+                *score = Value::from(n.clamp(min, max));
+            }
+        }
+    }
+
+    validate_structured_output(&value)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Rewrites single-quoted strings as JSON strings and drops trailing commas.
+fn normalize_json(input: &str) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let mut out = String::with_capacity(input.len());
+    let mut quote: Option<char> = None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Some(q) => {
+                if c == '\\' && i + 1 < chars.len() {
+                    let next = chars[i + 1];
+                    // `\'` is not a JSON escape; inside a single-quoted string it is a quote.
+                    if q == '\'' && next == '\'' {
+                        out.push('\'');
+                    } else {
+                        out.push(c);
+                        out.push(next);
+                    }
+                    i += 2;
+                    continue;
+                }
+                if c == q {
+                    out.push('"');
+                    quote = None;
+                } else if q == '\'' && c == '"' {
+                    out.push_str("\\\"");
+                } else {
+                    out.push(c);
+                }
+            }
+            None => match c {
+                '"' | '\'' => {
+                    out.push('"');
+                    quote = Some(c);
+                }
+                ',' => {
+                    let next = chars[i + 1..].iter().find(|c| !c.is_whitespace());
+                    if !matches!(next, Some('}') | Some(']')) {
+                        out.push(c);
+                    }
+                }
+                _ => out.push(c),
+            },
+        }
+        i += 1;
+    }
+    out
+}
+
+pub(crate) fn extract_json_block(raw: &str) -> &str {
+    // Common case: fenced markdown.
+    if let Some(start) = raw.find("```json") {
+        let start = start + "```json".len();
+        if let Some(end) = raw[start..].find("```") {
+            return &raw[start..start + end];
+        }
+    }
+
+    // Fallback: try first '{' to last '}' extraction.
+    if let (Some(l), Some(r)) = (raw.find('{'), raw.rfind('}')) {
+        if l < r {
+            return &raw[l..=r];
+        }
+    }
+
+    // Last resort: assume raw is JSON.
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use super::*;
+
+    /// Answers `complete` with queued replies and records the requests.
+    struct ScriptedProvider {
+        replies: Mutex<VecDeque<Result<String>>>,
+        requests: Mutex<Vec<ChatRequest>>,
+    }
+
+    impl ScriptedProvider {
+        fn new(replies: Vec<Result<String>>) -> Self {
+            ScriptedProvider {
+                replies: Mutex::new(replies.into()),
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl LlmProvider for ScriptedProvider {
+        fn name(&self) -> &'static str {
+            "scripted"
+        }
+
+        fn model(&self) -> &str {
+            "scripted"
+        }
+
+        async fn complete(&self, request: &ChatRequest) -> Result<String> {
+            self.requests.lock().unwrap().push(request.clone());
+            self.replies
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(|| Err(anyhow!("no scripted reply left")))
+        }
+    }
+
+    fn request() -> ChatRequest {
+        ChatRequest {
+            messages: vec![ChatMessage::user("hi")],
+            temperature: 0.7,
+            response_schema: None,
+        }
+    }
+
+    const VALID: &str =
+        r#"{"response": "Hello!", "suggested_emotion_change": "", "state_commands": {}}"#;
+
+    #[test]
+    fn strict_parse_accepts_fenced_and_surrounded_json() {
+        let fenced = format!("Sure:\n```json\n{VALID}\n```");
+        assert_eq!(
+            parse_structured_output(&fenced, false).unwrap().response,
+            "Hello!"
+        );
+
+        let surrounded = format!("Here you go {VALID} hope that helps");
+        assert_eq!(
+            parse_structured_output(&surrounded, false)
+                .unwrap()
+                .response,
+            "Hello!"
+        );
+    }
+
+    #[test]
+    fn tolerant_parse_drops_trailing_commas() {
+        let raw =
+            r#"{"response": "Hi", "suggested_memory_tags": ["a", "b",], "state_commands": {},}"#;
+
+        assert!(parse_structured_output(raw, false).is_err());
+        let output = parse_structured_output(raw, true).unwrap();
+        assert_eq!(output.suggested_memory_tags, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn tolerant_parse_accepts_single_quotes() {
+        let raw =
+            r#"{'response': 'It\'s "fine", really', 'suggested_emotion_change': 'Happy: +0.1'}"#;
+
+        let output = parse_structured_output(raw, true).unwrap();
+
+        assert_eq!(output.response, r#"It's "fine", really"#);
+        assert_eq!(output.suggested_emotion_change, "Happy: +0.1");
+    }
+
+    #[test]
+    fn tolerant_parse_fills_missing_optional_fields() {
+        let output = parse_structured_output(r#"{"response": "Hi"}"#, true).unwrap();
+
+        assert_eq!(output.response, "Hi");
+        assert!(output.suggested_emotion_change.is_empty());
+        assert!(output.state_commands.is_empty());
+        assert!(output.suggested_memory_add.is_none());
+        assert!(output.suggested_memory_tags.is_empty());
+    }
+
+    #[test]
+    fn tolerant_parse_fixes_drifted_fields() {
+        let raw = r#"{"response": "Hi", "state_command": {"ADD_BOUNDARY": 3},
+            "suggested_memory_tag": ["x"], "suggested_memory_importance": 4.0,
+            "suggested_memory_valence": -9}"#;
+
+        let output = parse_structured_output(raw, true).unwrap();
+
+        assert_eq!(output.state_commands["ADD_BOUNDARY"], "3");
+        assert_eq!(output.suggested_memory_tags, vec!["x"]);
+        assert_eq!(output.suggested_memory_importance, Some(1.0));
+        assert_eq!(output.suggested_memory_valence, Some(-1.0));
+    }
+
+    #[test]
+    fn tolerant_parse_still_needs_a_response() {
+        assert!(parse_structured_output(r#"{"reply": "Hi"}"#, true).is_err());
+        assert!(parse_structured_output("just words", true).is_err());
+    }
+
+    #[tokio::test]
+    async fn reprompt_shows_the_model_its_error() {
+        let provider = ScriptedProvider::new(vec![Ok(VALID.to_string())]);
+        let policy = RepairPolicy::strict().with_max_reprompts(2);
+
+        let output = parse_with_repair(&provider, &policy, request(), "{oops".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(output.response, "Hello!");
+        let requests = provider.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let messages = &requests[0].messages;
+        assert_eq!(messages[1].content, "{oops");
+        assert!(messages[2].content.contains("could not be parsed"));
+    }
+
+    #[tokio::test]
+    async fn reprompts_are_bounded_then_degrade() {
+        let provider = ScriptedProvider::new(vec![
+            Ok("still broken".to_string()),
+            Ok("broken again".to_string()),
+            Ok(VALID.to_string()),
+        ]);
+        let policy = RepairPolicy::strict()
+            .with_max_reprompts(2)
+            .with_degrade_on_failure(true);
+
+        let output = parse_with_repair(
+            &provider,
+            &policy,
+            request(),
+            "```json\nNot JSON at all\n```".to_string(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(provider.requests.lock().unwrap().len(), 2);
+        assert_eq!(output.response, "Not JSON at all");
+        assert!(output.state_commands.is_empty());
+        assert!(output.suggested_memory_add.is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_reprompt_still_degrades() {
+        let provider = ScriptedProvider::new(vec![Err(anyhow!("connection reset"))]);
+
+        let output = parse_with_repair(
+            &provider,
+            &RepairPolicy::default(),
+            request(),
+            "plain text reply".to_string(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output.response, "plain text reply");
+    }
+
+    #[tokio::test]
+    async fn strict_policy_fails_the_turn() {
+        let provider = ScriptedProvider::new(vec![]);
+
+        let err = parse_with_repair(
+            &provider,
+            &RepairPolicy::strict(),
+            request(),
+            "{oops".to_string(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(err.to_string().contains("Failed to deserialize"), "{err}");
+        assert!(provider.requests.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::OnceLock;
+
+use anyhow::{bail, Result};
+use jsonschema::JSONSchema;
+use schemars::gen::SchemaSettings;
+use serde_json::Value;
+
+use crate::brain::provider::ResponseSchema;
+use crate::companion::models::StructuredLLMOutput;
+
+/// Name the schema is registered under with providers that require one (OpenAI).
+pub const STRUCTURED_OUTPUT_SCHEMA_NAME: &str = "structured_llm_output";
+
+const STATE_COMMANDS_FIELD: &str = "state_commands";
+const STATE_COMMAND_ALIAS: &str = "state_command";
+
+/// JSON Schema (draft-07) of `StructuredLLMOutput`, derived from the type.
+///
+/// The `$schema` keyword is left out because some OpenAI-compatible servers reject it
+/// inside `response_format`.
+pub fn structured_output_schema() -> &'static Value {
+    static SCHEMA: OnceLock<Value> = OnceLock::new();
+    SCHEMA.get_or_init(|| {
+        let schema = SchemaSettings::draft07()
+            .with(|settings| settings.meta_schema = None)
+            .into_generator()
+            .into_root_schema_for::<StructuredLLMOutput>();
+        serde_json::to_value(schema).expect("a derived schema always serializes")
+    })
+}
+
+/// The schema in the form attached to a `ChatRequest`.
+pub fn structured_output_response_schema() -> ResponseSchema {
+    ResponseSchema {
+        name: STRUCTURED_OUTPUT_SCHEMA_NAME.to_string(),
+        schema: structured_output_schema().clone(),
+    }
+}
+
+/// Checks a decoded reply against `structured_output_schema`, reporting every violation
+/// with its path (e.g. `/state_commands/AROUSAL`).
+///
+/// A singular `state_command` key, the most common drift, is read as `state_commands`
+/// (as `StructuredLLMOutput` deserializes it).
+pub fn validate_structured_output(value: &Value) -> Result<()> {
+    static VALIDATOR: OnceLock<JSONSchema> = OnceLock::new();
+    let validator = VALIDATOR.get_or_init(|| {
+        JSONSchema::compile(structured_output_schema()).expect("the derived schema is valid")
+    });
+
+    let renamed;
+    let value = match value.as_object() {
+        Some(object)
+            if object.contains_key(STATE_COMMAND_ALIAS)
+                && !object.contains_key(STATE_COMMANDS_FIELD) =>
+        {
+            let mut object = object.clone();
+            if let Some(commands) = object.remove(STATE_COMMAND_ALIAS) {
+                object.insert(STATE_COMMANDS_FIELD.to_string(), commands);
+            }
+            renamed = Value::Object(object);
+            &renamed
+        }
+        _ => value,
+    };
+
+    if let Err(errors) = validator.validate(value) {
+        let violations: Vec<String> = errors
+            .map(|e| match e.instance_path.to_string() {
+                path if path.is_empty() => e.to_string(),
+                path => format!("{path}: {e}"),
+            })
+            .collect();
+        bail!(
+            "output does not match the schema: {}",
+            violations.join("; ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn reply(commands_key: &str, commands: Value) -> Value {
+        json!({
+            "response": "Hi",
+            "suggested_emotion_change": "Happy: +0.1",
+            "suggested_memory_add": null,
+            commands_key: commands,
+        })
+    }
+
+    fn violations(value: &Value) -> String {
+        validate_structured_output(value).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn schema_requires_the_plural_key_and_omits_the_meta_schema() {
+        let schema = structured_output_schema();
+        assert!(schema.get("$schema").is_none());
+        let required = schema["required"].as_array().unwrap();
+        assert!(required.contains(&json!("state_commands")));
+        assert!(!required.contains(&json!("state_command")));
+    }
+
+    #[test]
+    fn accepts_state_commands_and_state_command() {
+        for key in ["state_commands", "state_command"] {
+            let value = reply(key, json!({"AROUSAL": "+0.2"}));
+
+            validate_structured_output(&value).unwrap();
+            let output: StructuredLLMOutput = serde_json::from_value(value).unwrap();
+            assert_eq!(output.state_commands["AROUSAL"], "+0.2", "{key}");
+        }
+    }
+
+    #[test]
+    fn violations_name_their_path() {
+        let err = violations(&reply("state_commands", json!({"AROUSAL": 0.2})));
+        assert!(err.contains("/state_commands/AROUSAL: "), "{err}");
+
+        let mut value = reply("state_commands", json!({}));
+        value["suggested_memory_importance"] = json!(4.0);
+        let err = violations(&value);
+        assert!(err.contains("/suggested_memory_importance: "), "{err}");
+
+        let err = violations(&json!({"response": "Hi"}));
+        assert!(
+            err.contains("\"state_commands\" is a required property"),
+            "{err}"
+        );
+    }
+}
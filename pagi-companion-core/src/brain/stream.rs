@@ -0,0 +1,399 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+
+use anyhow::Result;
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+
+use crate::companion::models::StructuredLLMOutput;
+
+/// Incremental assistant text from a provider.
+pub type TextStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+
+/// Events of `TacticalLLM::generate_structured_output_stream`.
+pub type StructuredOutputStream = Pin<Box<dyn Stream<Item = Result<StructuredStreamEvent>> + Send>>;
+
+#[derive(Debug, Clone)]
+pub enum StructuredStreamEvent {
+    /// Newly decoded text of the `response` field, for display as it arrives.
+    ResponseDelta(String),
+    /// The whole output, parsed once the stream completed. Authoritative: if the model
+    /// did not stream a well-formed `response` field, the deltas may be incomplete.
+    Done(StructuredLLMOutput),
+}
+
+/// What a provider's line parser made of one line of a streamed body.
+pub(crate) enum LineEvent {
+    Text(String),
+    Skip,
+    Done,
+}
+
+/// Splits a streamed body into lines (SSE and NDJSON framing) and maps each through
+/// `parse`, stopping at `LineEvent::Done` or the end of the body.
+pub(crate) fn line_stream<F>(response: reqwest::Response, parse: F) -> TextStream
+where
+    F: FnMut(&str) -> Result<LineEvent> + Send + 'static,
+{
+    chunk_lines(body_chunks(response), parse)
+}
+
+/// `line_stream` over raw body chunks.
+pub(crate) fn chunk_lines<F>(bytes: ByteChunks, parse: F) -> TextStream
+where
+    F: FnMut(&str) -> Result<LineEvent> + Send + 'static,
+{
+    struct State<F> {
+        bytes: ByteChunks,
+        buf: Vec<u8>,
+        lines: VecDeque<String>,
+        parse: F,
+        eof: bool,
+        finished: bool,
+    }
+
+    let state = State {
+        bytes,
+        buf: Vec::new(),
+        lines: VecDeque::new(),
+        parse,
+        eof: false,
+        finished: false,
+    };
+
+    Box::pin(stream::unfold(state, |mut state| async move {
+        loop {
+            if state.finished {
+                return None;
+            }
+            if let Some(line) = state.lines.pop_front() {
+                match (state.parse)(&line) {
+                    Ok(LineEvent::Text(text)) if !text.is_empty() => {
+                        return Some((Ok(text), state))
+                    }
+                    Ok(LineEvent::Text(_)) | Ok(LineEvent::Skip) => continue,
+                    Ok(LineEvent::Done) => return None,
+                    Err(e) => {
+                        state.finished = true;
+                        return Some((Err(e), state));
+                    }
+                }
+            }
+            if state.eof {
+                return None;
+            }
+
+            match state.bytes.next().await {
+                Some(Ok(chunk)) => {
+                    state.buf.extend_from_slice(&chunk);
+                    // Splitting on '\n' never cuts a UTF-8 sequence.
+                    while let Some(pos) = state.buf.iter().position(|&b| b == b'\n') {
+                        let line: Vec<u8> = state.buf.drain(..=pos).collect();
+                        state.lines.push_back(decode_line(&line));
+                    }
+                }
+                Some(Err(e)) => {
+                    state.finished = true;
+                    return Some((Err(e.into()), state));
+                }
+                None => {
+                    state.eof = true;
+                    if !state.buf.is_empty() {
+                        let line = std::mem::take(&mut state.buf);
+                        state.lines.push_back(decode_line(&line));
+                    }
+                }
+            }
+        }
+    }))
+}
+
+pub(crate) type ByteChunks = BoxStream<'static, reqwest::Result<Vec<u8>>>;
+
+fn body_chunks(response: reqwest::Response) -> ByteChunks {
+    response
+        .bytes_stream()
+        .map(|chunk| chunk.map(|bytes| bytes.to_vec()))
+        .boxed()
+}
+
+fn decode_line(line: &[u8]) -> String {
+    String::from_utf8_lossy(line)
+        .trim_end_matches(['\r', '\n'])
+        .to_string()
+}
+
+/// Passes a plain-text body through chunk by chunk, holding back UTF-8 sequences that
+/// straddle chunk boundaries.
+pub(crate) fn text_body_stream(response: reqwest::Response) -> TextStream {
+    chunk_text(body_chunks(response))
+}
+
+fn chunk_text(bytes: ByteChunks) -> TextStream {
+    let state = (bytes, Vec::<u8>::new());
+    Box::pin(stream::unfold(Some(state), |state| async move {
+        let (mut bytes, mut pending) = state?;
+        loop {
+            match bytes.next().await {
+                Some(Ok(chunk)) => {
+                    pending.extend_from_slice(&chunk);
+                    let valid = match std::str::from_utf8(&pending) {
+                        Ok(_) => pending.len(),
+                        // Only an incomplete trailing sequence is held back.
+                        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                        Err(_) => pending.len(),
+                    };
+                    if valid == 0 {
+                        continue;
+                    }
+                    let text = String::from_utf8_lossy(&pending[..valid]).into_owned();
+                    pending.drain(..valid);
+                    return Some((Ok(text), Some((bytes, pending))));
+                }
+                Some(Err(e)) => return Some((Err(e.into()), None)),
+                None if pending.is_empty() => return None,
+                None => {
+                    let text = String::from_utf8_lossy(&pending).into_owned();
+                    return Some((Ok(text), None));
+                }
+            }
+        }
+    }))
+}
+
+/// Decodes the top-level `"response"` string of a JSON object while it is still being
+/// generated, so the reply can be shown before the rest of the output arrives.
+///
+/// Anything before the first `{` (prose, a ```json fence) is ignored.
+#[derive(Debug, Default)]
+pub struct ResponseFieldExtractor {
+    depth: usize,
+    in_string: bool,
+    escape: bool,
+    /// Pending `\uXXXX` digits (and a high surrogate waiting for its pair).
+    unicode: Option<String>,
+    high_surrogate: Option<u16>,
+    /// Leading characters of the current depth-1 string, enough to recognise the key.
+    string_prefix: String,
+    last_key: Option<String>,
+    value_is_response: bool,
+    capturing: bool,
+    done: bool,
+}
+
+const RESPONSE_KEY: &str = "response";
+
+impl ResponseFieldExtractor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the whole `response` value has been seen.
+    pub fn is_complete(&self) -> bool {
+        self.done
+    }
+
+    /// Feeds the next chunk of raw model text, returning newly decoded response text.
+    pub fn push(&mut self, chunk: &str) -> String {
+        let mut out = String::new();
+        for c in chunk.chars() {
+            if self.done {
+                break;
+            }
+            self.step(c, &mut out);
+        }
+        out
+    }
+
+    fn step(&mut self, c: char, out: &mut String) {
+        if self.in_string {
+            self.step_string(c, out);
+            return;
+        }
+        match c {
+            '{' | '[' => {
+                self.depth += 1;
+                self.value_is_response = false;
+            }
+            '}' | ']' => self.depth = self.depth.saturating_sub(1),
+            '"' if self.depth > 0 => {
+                self.in_string = true;
+                self.string_prefix.clear();
+                self.capturing = self.depth == 1 && self.value_is_response;
+                self.value_is_response = false;
+            }
+            ':' if self.depth == 1 => {
+                self.value_is_response = self.last_key.as_deref() == Some(RESPONSE_KEY);
+            }
+            ',' if self.depth == 1 => {
+                self.last_key = None;
+                self.value_is_response = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn step_string(&mut self, c: char, out: &mut String) {
+        if let Some(digits) = &mut self.unicode {
+            digits.push(c);
+            if digits.len() == 4 {
+                let code = u16::from_str_radix(digits, 16).ok();
+                self.unicode = None;
+                if let Some(code) = code {
+                    self.emit_utf16(code, out);
+                }
+            }
+            return;
+        }
+
+        if self.escape {
+            self.escape = false;
+            let decoded = match c {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'u' => {
+                    self.unicode = Some(String::new());
+                    return;
+                }
+                other => other,
+            };
+            self.emit(decoded, out);
+            return;
+        }
+
+        match c {
+            '\\' => self.escape = true,
+            '"' => {
+                self.in_string = false;
+                if self.capturing {
+                    self.capturing = false;
+                    self.done = true;
+                } else if self.depth == 1 {
+                    self.last_key = Some(std::mem::take(&mut self.string_prefix));
+                }
+            }
+            other => self.emit(other, out),
+        }
+    }
+
+    fn emit(&mut self, c: char, out: &mut String) {
+        if self.capturing {
+            out.push(c);
+        } else if self.depth == 1 && self.string_prefix.len() <= RESPONSE_KEY.len() {
+            self.string_prefix.push(c);
+        }
+    }
+
+    fn emit_utf16(&mut self, code: u16, out: &mut String) {
+        let decoded = match (self.high_surrogate.take(), code) {
+            (None, 0xD800..=0xDBFF) => {
+                self.high_surrogate = Some(code);
+                return;
+            }
+            (Some(high), 0xDC00..=0xDFFF) => char::decode_utf16([high, code]).next(),
+            (_, code) => char::decode_utf16([code]).next(),
+        };
+        let decoded = decoded
+            .and_then(|r| r.ok())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        self.emit(decoded, out);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Runs `parse` over `chunks` as if they arrived as one body.
+    pub(crate) async fn lines<F>(chunks: &[&str], parse: F) -> Vec<Result<String, String>>
+    where
+        F: FnMut(&str) -> Result<LineEvent> + Send + 'static,
+    {
+        chunk_lines(byte_chunks(chunks), parse)
+            .map(|item| item.map_err(|e| e.to_string()))
+            .collect()
+            .await
+    }
+
+    fn byte_chunks(chunks: &[&str]) -> ByteChunks {
+        byte_chunks_of(chunks.iter().map(|c| c.as_bytes().to_vec()).collect())
+    }
+
+    fn byte_chunks_of(chunks: Vec<Vec<u8>>) -> ByteChunks {
+        stream::iter(chunks.into_iter().map(Ok)).boxed()
+    }
+
+    fn extract(chunks: &[&str]) -> (String, bool) {
+        let mut extractor = ResponseFieldExtractor::new();
+        let text = chunks.iter().map(|chunk| extractor.push(chunk)).collect();
+        (text, extractor.is_complete())
+    }
+
+    #[test]
+    fn response_field_survives_any_chunk_split() {
+        let raw = r#"```json
+{"state_commands": {"AROUSAL": "+0.1"}, "response": "She said \"hi\"\n caf\u00e9 \ud83d\ude00", "x": "y"}"#;
+        let expected = "She said \"hi\"\n café 😀";
+
+        let chars: Vec<String> = raw.chars().map(String::from).collect();
+        let chars: Vec<&str> = chars.iter().map(String::as_str).collect();
+        assert_eq!(extract(&chars), (expected.to_string(), true));
+        for (split, _) in raw.char_indices() {
+            let (head, tail) = raw.split_at(split);
+            assert_eq!(extract(&[head, tail]).0, expected, "split at {split}");
+        }
+    }
+
+    #[test]
+    fn only_the_top_level_response_key_is_captured() {
+        let raw = r#"{"note": "response", "meta": {"response": "nested"}, "response": "top"}"#;
+        assert_eq!(extract(&[raw]), ("top".to_string(), true));
+
+        let (text, complete) = extract(&[r#"{"response": "still typ"#]);
+        assert_eq!(text, "still typ");
+        assert!(!complete);
+    }
+
+    fn keep_text(line: &str) -> Result<LineEvent> {
+        match line {
+            "" => Ok(LineEvent::Skip),
+            "END" => Ok(LineEvent::Done),
+            "FAIL" => anyhow::bail!("upstream failed"),
+            text => Ok(LineEvent::Text(text.to_string())),
+        }
+    }
+
+    #[tokio::test]
+    async fn lines_are_reassembled_across_chunks() {
+        let out = lines(&["fir", "st\r\nsec", "ond\n\nthi", "rd"], keep_text).await;
+        assert_eq!(
+            out,
+            vec![Ok("first".into()), Ok("second".into()), Ok("third".into())]
+        );
+    }
+
+    #[tokio::test]
+    async fn lines_stop_at_done_and_after_an_error() {
+        let out = lines(&["a\nEN", "D\nb\n"], keep_text).await;
+        assert_eq!(out, vec![Ok("a".into())]);
+
+        let out = lines(&["a\nFAIL\nb\n"], keep_text).await;
+        assert_eq!(out, vec![Ok("a".into()), Err("upstream failed".into())]);
+    }
+
+    #[tokio::test]
+    async fn text_body_holds_back_split_utf8() {
+        let body = "naïve ✓".as_bytes();
+        let chunks = body.iter().map(|&b| vec![b]).collect();
+
+        let pieces: Vec<String> = chunk_text(byte_chunks_of(chunks))
+            .map(|piece| piece.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(pieces.concat(), "naïve ✓");
+        assert!(pieces.iter().all(|piece| !piece.contains('\u{fffd}')));
+    }
+}
@@ -1,106 +1,162 @@
+use std::sync::Arc;
+
 use anyhow::Result;
-use reqwest::{Client, StatusCode};
-use serde::{Deserialize, Serialize};
-use tracing::{info, warn};
+use futures_util::stream::{self, StreamExt};
+use reqwest::Client;
+use tracing::info;
 
 use crate::brain::config::TacticalLLMConfig;
+use crate::brain::prompt::AssembledPrompt;
+use crate::brain::provider::{ChatMessage, ChatRequest, LlmProvider, ResilientProvider};
+use crate::brain::repair::{parse_with_repair, RepairPolicy};
+use crate::brain::schema::structured_output_response_schema;
+use crate::brain::stream::{ResponseFieldExtractor, StructuredOutputStream, StructuredStreamEvent};
 use crate::companion::models::StructuredLLMOutput;
 
-/// The complete prompt input structure sent to the external LLM API.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TacticalLLMRequest {
-    pub model: String,
-    pub temperature: f32,
-    pub system_prompt: String,
-    pub user_input: String,
-}
+pub use crate::brain::provider::raw::TacticalLLMRequest;
 
 /// The central component for interacting with the underlying LLM (external API).
 pub struct TacticalLLM {
-    config: TacticalLLMConfig,
-    http_client: Client,
+    provider: Arc<dyn LlmProvider>,
+    repair: RepairPolicy,
+    response_schema: bool,
 }
 
 impl TacticalLLM {
     pub fn new() -> Result<Self> {
-        let config = TacticalLLMConfig::load()?;
+        Self::with_config(TacticalLLMConfig::load()?)
+    }
+
+    /// Builds the configured endpoints (primary, then fallbacks) with retries and
+    /// circuit breakers.
+    pub fn with_config(config: TacticalLLMConfig) -> Result<Self> {
+        // A read timeout (30s of silence) rather than a total one, so streamed
+        // generations may run longer as long as tokens keep arriving.
         let http_client = Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
+            .connect_timeout(std::time::Duration::from_secs(10))
+            .read_timeout(std::time::Duration::from_secs(30))
             .build()?;
-        Ok(TacticalLLM { config, http_client })
+        let provider = ResilientProvider::from_config(&config, http_client);
+        Ok(Self::with_provider(Arc::new(provider))
+            .with_repair_policy(config.repair)
+            .with_response_schema(config.response_schema))
+    }
+
+    /// Uses an explicit provider (e.g. a custom backend).
+    pub fn with_provider(provider: Arc<dyn LlmProvider>) -> Self {
+        TacticalLLM {
+            provider,
+            repair: RepairPolicy::default(),
+            response_schema: true,
+        }
+    }
+
+    /// Replaces how malformed structured output is recovered (see `RepairPolicy`).
+    pub fn with_repair_policy(mut self, repair: RepairPolicy) -> Self {
+        self.repair = repair;
+        self
+    }
+
+    /// Whether requests carry the `StructuredLLMOutput` JSON Schema (default: on).
+    pub fn with_response_schema(mut self, enabled: bool) -> Self {
+        self.response_schema = enabled;
+        self
     }
 
     /// Specialized method for the Companion Agent to generate structured responses using RAG context.
+    ///
+    /// `prompt` has already been fitted to the token budget by a `PromptAssembler`.
     pub async fn generate_structured_output(
         &self,
-        system_prompt: &str,
-        memory_context: &str,
-        user_input: &str,
+        prompt: &AssembledPrompt,
     ) -> Result<StructuredLLMOutput> {
-        // 1) Build the complete prompt augmented with RAG context.
-        let full_user_input = format!("CONTEXT: {}\nUSER REQUEST: {}", memory_context, user_input);
-
-        // 2) Build the API request payload.
-        let request_payload = TacticalLLMRequest {
-            model: self.config.model_name.clone(),
-            temperature: 0.8,
-            system_prompt: system_prompt.to_string(),
-            user_input: full_user_input,
-        };
-
-        info!(
-            api_url = self.config.api_url.as_str(),
-            model = self.config.model_name.as_str(),
-            "tactical_llm_request_send"
-        );
-
-        // 3) Make the API call.
-        let response = self
-            .http_client
-            .post(self.config.api_url.clone())
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .json(&request_payload)
-            .send()
-            .await?;
-
-        // 4) Handle response.
-        if response.status() != StatusCode::OK {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("LLM API request failed (status={status}): {body}");
-        }
+        // 1) Build the request from the assembled prompt.
+        let request = self.build_request(prompt);
 
-        let raw_llm_text = response.text().await?;
+        // 2) Make the API call; the provider unwraps its envelope to the assistant text.
+        let raw_llm_text = self.provider.complete(&request).await?;
 
-        // 5) Robustly parse the structured JSON output.
-        let json_block = extract_json_block(&raw_llm_text);
-        let output: StructuredLLMOutput = serde_json::from_str(json_block.trim()).map_err(|e| {
-            warn!(error = %e, raw = raw_llm_text.as_str(), "tactical_llm_parse_failed");
-            anyhow::anyhow!("Failed to deserialize structured LLM output: {e}")
-        })?;
+        // 3) Robustly parse the structured JSON output, repairing it if needed.
+        parse_with_repair(self.provider.as_ref(), &self.repair, request, raw_llm_text).await
+    }
 
-        info!("tactical_llm_response_parsed");
-        Ok(output)
+    /// Streaming variant of `generate_structured_output`.
+    ///
+    /// Yields `ResponseDelta`s of the `response` field as tokens arrive, then a final
+    /// `Done` with the fully parsed output (state commands, memory add, ...). Malformed
+    /// output is repaired as in `generate_structured_output`; re-prompts are not streamed.
+    pub async fn generate_structured_output_stream(
+        &self,
+        prompt: &AssembledPrompt,
+    ) -> Result<StructuredOutputStream> {
+        let request = self.build_request(prompt);
+        let text_stream = self.provider.complete_stream(&request).await?;
+        let repair = (self.provider.clone(), self.repair.clone(), request);
+
+        let state = Some((
+            text_stream,
+            ResponseFieldExtractor::new(),
+            String::new(),
+            repair,
+        ));
+        Ok(Box::pin(stream::unfold(state, |state| async move {
+            let (mut text_stream, mut extractor, mut raw_llm_text, repair) = state?;
+            loop {
+                match text_stream.next().await {
+                    Some(Ok(chunk)) => {
+                        raw_llm_text.push_str(&chunk);
+                        let delta = extractor.push(&chunk);
+                        if !delta.is_empty() {
+                            let event = StructuredStreamEvent::ResponseDelta(delta);
+                            let state = (text_stream, extractor, raw_llm_text, repair);
+                            return Some((Ok(event), Some(state)));
+                        }
+                    }
+                    Some(Err(e)) => return Some((Err(e), None)),
+                    None => {
+                        let (provider, policy, request) = repair;
+                        let done =
+                            parse_with_repair(provider.as_ref(), &policy, request, raw_llm_text)
+                                .await
+                                .map(StructuredStreamEvent::Done);
+                        return Some((done, None));
+                    }
+                }
+            }
+        })))
     }
-}
 
-fn extract_json_block(raw: &str) -> &str {
-    // Common case: fenced markdown.
-    if let Some(start) = raw.find("```json") {
-        let start = start + "```json".len();
-        if let Some(end) = raw[start..].find("```") {
-            return &raw[start..start + end];
-        }
+    /// Condenses `transcript` into a short plain-text summary for long-term memory.
+    pub async fn summarize_conversation(&self, transcript: &str) -> Result<String> {
+        let request = ChatRequest {
+            messages: vec![
+                ChatMessage::system(
+                    "Summarize this excerpt of a conversation between a user and their companion \
+                     in two or three sentences. Keep facts the user revealed, plans and \
+                     emotional moments. Reply with the summary only.",
+                ),
+                ChatMessage::user(transcript),
+            ],
+            temperature: 0.3,
+            response_schema: None,
+        };
+        let summary = self.provider.complete(&request).await?;
+        Ok(summary.trim().to_string())
     }
 
-    // Fallback: try first '{' to last '}' extraction.
-    if let (Some(l), Some(r)) = (raw.find('{'), raw.rfind('}')) {
-        if l < r {
-            return &raw[l..=r];
+    fn build_request(&self, prompt: &AssembledPrompt) -> ChatRequest {
+        info!(
+            provider = self.provider.name(),
+            model = self.provider.model(),
+            history_turns = prompt.sections.history.len(),
+            prompt_tokens = prompt.total_tokens,
+            "tactical_llm_request_send"
+        );
+
+        ChatRequest {
+            messages: prompt.to_messages(),
+            temperature: 0.8,
+            response_schema: self.response_schema.then(structured_output_response_schema),
         }
     }
-
-    // Last resort: assume raw is JSON.
-    raw
 }
-
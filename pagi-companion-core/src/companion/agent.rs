@@ -1,13 +1,30 @@
-use anyhow::Result;
-use tracing::info;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use futures_util::StreamExt;
+use tracing::{info, warn};
 use uuid::Uuid;
 
+use crate::brain::prompt::{AssembledPrompt, PromptAssembler, PromptBudget, PromptSections};
+use crate::brain::provider::ChatRole;
+use crate::brain::stream::StructuredStreamEvent;
 use crate::brain::tactical_llm::TacticalLLM;
-use crate::companion::kb::{EpisodicKB, KnowledgeBase, SemanticKB};
-use crate::companion::models::StructuredLLMOutput;
+use crate::companion::commands::StateCommand;
+use crate::companion::conversation::{
+    transcript, ConversationConfig, ConversationHistory, ConversationTurn,
+};
+use crate::companion::history::StateHistory;
+use crate::companion::kb::keyring::load_storage_cipher;
+use crate::companion::kb::{EpisodicKB, KnowledgeBase, RetrievalOptions, SemanticKB};
+use crate::companion::models::{PersonalityStateMatrix, StructuredLLMOutput};
 use crate::companion::psychology::PsychologicalEngine;
+use crate::companion::templates::PromptTemplates;
 use crate::prime_core::models::{PhaseResult, PhaseStatus};
+use crate::rag::embedding::load_embedder;
+use crate::rag::fusion::RetrievalMode;
+use crate::rag::metadata::{MemoryFilter, MemoryMetadata, MemorySource};
 use crate::security::AgentIdentity;
+use crate::storage::{FileSystemStorage, StorageBackend};
 
 /// The specialized agent for AI Girlfriend/Boyfriend logic, utilizing Agentic RAG.
 pub struct CompanionAgent {
@@ -18,6 +35,16 @@ pub struct CompanionAgent {
     // Agentic RAG components
     semantic_kb: SemanticKB, // structured facts/state
     episodic_kb: EpisodicKB, // vector memory search
+    /// Memories farther than this from the query are not injected (None = keep all top-k).
+    max_memory_distance: Option<f32>,
+    /// How memories are ranked (defaults to hybrid BM25 + vector with RRF).
+    retrieval_mode: RetrievalMode,
+    /// Size of the short-term conversation buffer sent with every request.
+    conversation_config: ConversationConfig,
+    /// Fits persona, memories, history and input into the model's context.
+    prompt_assembler: PromptAssembler,
+    /// Renders the matrix into the system prompt.
+    prompt_templates: PromptTemplates,
 
     // Psychological modeling engine
     psych_engine: PsychologicalEngine,
@@ -28,7 +55,18 @@ pub struct CompanionAgent {
 
 impl CompanionAgent {
     pub async fn new(user_id: String) -> Result<Self> {
-        let semantic_kb = SemanticKB::new();
+        // The storage root defaults to ./companion_data and can be moved with PAGI_DATA_DIR.
+        Self::new_with_storage(user_id, Arc::new(FileSystemStorage::from_env())).await
+    }
+
+    /// Creates an agent whose knowledge bases keep their data in `storage`.
+    pub async fn new_with_storage(
+        user_id: String,
+        storage: Arc<dyn StorageBackend>,
+    ) -> Result<Self> {
+        // Encryption at rest is enabled by PAGI_DATA_PASSPHRASE / PAGI_DATA_KEY_FILE.
+        let cipher = load_storage_cipher(storage.as_ref()).await?;
+        let semantic_kb = SemanticKB::new_with_storage(storage.clone()).with_cipher(cipher.clone());
         let identity = semantic_kb.load_agent_identity(&user_id).await?;
 
         let tactical_llm = TacticalLLM::new()?;
@@ -37,78 +75,315 @@ impl CompanionAgent {
             tactical_llm,
             user_id,
             semantic_kb,
-            episodic_kb: EpisodicKB::new(),
-            psych_engine: PsychologicalEngine::new(),
+            episodic_kb: EpisodicKB::new_with_storage(storage)
+                .with_embedder(load_embedder()?)
+                .with_cipher(cipher),
+            max_memory_distance: None,
+            retrieval_mode: RetrievalMode::default(),
+            conversation_config: ConversationConfig::load()?,
+            prompt_assembler: PromptAssembler::new(PromptBudget::load()?),
+            prompt_templates: PromptTemplates::load().await?,
+            psych_engine: PsychologicalEngine::load().await?,
             agent_identity: identity,
         })
     }
 
+    /// Sets the relevance threshold applied to retrieved memories (metric distance).
+    pub fn set_max_memory_distance(&mut self, max_memory_distance: Option<f32>) {
+        self.max_memory_distance = max_memory_distance;
+    }
+
+    /// Selects vector, lexical or hybrid ranking for memory retrieval.
+    pub fn set_retrieval_mode(&mut self, retrieval_mode: RetrievalMode) {
+        self.retrieval_mode = retrieval_mode;
+    }
+
+    /// Sets how many recent turns are replayed to the model and when older ones are
+    /// summarized into episodic memory.
+    pub fn set_conversation_config(&mut self, conversation_config: ConversationConfig) {
+        self.conversation_config = conversation_config;
+    }
+
+    /// Replaces the prompt budget and token estimator.
+    pub fn set_prompt_assembler(&mut self, prompt_assembler: PromptAssembler) {
+        self.prompt_assembler = prompt_assembler;
+    }
+
+    /// Replaces the system prompt templates.
+    pub fn set_prompt_templates(&mut self, prompt_templates: PromptTemplates) {
+        self.prompt_templates = prompt_templates;
+    }
+
+    /// Every recorded change of the user's matrix, for replaying or diffing past states.
+    pub async fn state_history(&self) -> Result<StateHistory> {
+        self.semantic_kb.load_state_history(&self.user_id).await
+    }
+
+    /// Undoes the state changes of the exchange `phase_id` (a `PhaseResult::phase_id`)
+    /// and of every exchange after it.
+    pub async fn rollback_turn(&self, phase_id: Uuid) -> Result<PersonalityStateMatrix> {
+        let history = self.state_history().await?;
+        let Some(start) = history.turn_start(&phase_id.to_string()) else {
+            bail!("No state changes recorded for turn {phase_id}");
+        };
+        self.semantic_kb.rollback_state(&self.user_id, start).await
+    }
+
+    /// Removes a boundary at the user's request; the LLM can only add them. Returns
+    /// whether it was present.
+    pub async fn remove_boundary(&self, boundary: &str) -> Result<bool> {
+        self.semantic_kb
+            .remove_boundary(&self.user_id, boundary)
+            .await
+    }
+
     /// The primary method that translates user input into a dynamic, personalized response.
     pub async fn execute_response(&mut self, user_input: &str) -> Result<PhaseResult> {
+        self.execute_response_with_filter(user_input, &MemoryFilter::default())
+            .await
+    }
+
+    /// Like `execute_response`, but only memories passing `memory_filter` are retrieved
+    /// (e.g. things the user said about their pet in the last month).
+    pub async fn execute_response_with_filter(
+        &mut self,
+        user_input: &str,
+        memory_filter: &MemoryFilter,
+    ) -> Result<PhaseResult> {
         info!(
             user_id = self.user_id.as_str(),
             agent_id = self.agent_identity.agent_id.as_str(),
             "companion_execute_response_start"
         );
 
-        // 1) DIRECT LOOKUP (Semantic KB): load current personality state.
-        let mut personality_matrix = self
+        // 1-3) LOAD STATE, RETRIEVE MEMORIES, BUILD AUGMENTED LLM INPUT.
+        let prompt = self.augment_prompt(user_input, memory_filter).await?;
+
+        // 4) GENERATE STRUCTURED OUTPUT.
+        let structured_llm_output: StructuredLLMOutput = self
+            .tactical_llm
+            .generate_structured_output(&prompt.prompt)
+            .await?;
+
+        self.apply_llm_output(prompt, user_input, structured_llm_output)
+            .await
+    }
+
+    /// Like `execute_response_with_filter`, but streams the reply: `on_delta` receives
+    /// the response text as the model generates it. State changes and memories are
+    /// applied once the full output has arrived.
+    pub async fn execute_response_streaming(
+        &mut self,
+        user_input: &str,
+        memory_filter: &MemoryFilter,
+        mut on_delta: impl FnMut(&str) + Send,
+    ) -> Result<PhaseResult> {
+        info!(
+            user_id = self.user_id.as_str(),
+            agent_id = self.agent_identity.agent_id.as_str(),
+            "companion_execute_response_stream_start"
+        );
+
+        let prompt = self.augment_prompt(user_input, memory_filter).await?;
+
+        let mut events = self
+            .tactical_llm
+            .generate_structured_output_stream(&prompt.prompt)
+            .await?;
+        let mut structured_llm_output = None;
+        while let Some(event) = events.next().await {
+            match event? {
+                StructuredStreamEvent::ResponseDelta(delta) => on_delta(&delta),
+                StructuredStreamEvent::Done(output) => structured_llm_output = Some(output),
+            }
+        }
+        let Some(structured_llm_output) = structured_llm_output else {
+            bail!("LLM stream ended without a structured output");
+        };
+
+        self.apply_llm_output(prompt, user_input, structured_llm_output)
+            .await
+    }
+
+    /// Steps 1-3: loads the personality state and recent turns, retrieves memories and
+    /// fits them all into the prompt budget for `user_input`.
+    async fn augment_prompt(
+        &self,
+        user_input: &str,
+        memory_filter: &MemoryFilter,
+    ) -> Result<AugmentedPrompt> {
+        // 1) DIRECT LOOKUP (Semantic KB): load current personality state and the
+        //    short-term conversation buffer.
+        let personality_matrix = self
             .semantic_kb
             .load_matrix_by_user_id(&self.user_id)
             .await?;
+        let history = self.semantic_kb.load_conversation(&self.user_id).await?;
 
         // 2) SEMANTIC RETRIEVAL (Episodic KB): find contextually relevant memories.
-        let relevant_memories = self
+        let retrieval = RetrievalOptions::new(5)
+            .with_filter(memory_filter.clone())
+            .with_mode(self.retrieval_mode);
+        let relevant_memories: Vec<String> = self
             .episodic_kb
-            .retrieve_context_by_query(&self.user_id, user_input, 5)
-            .await?;
+            .retrieve(&self.user_id, user_input, &retrieval)
+            .await?
+            .into_iter()
+            .filter(|m| self.max_memory_distance.is_none_or(|max| m.distance <= max))
+            .map(|m| m.content)
+            .collect();
 
-        // 3) BUILD AUGMENTED LLM INPUT.
-        let system_prompt = personality_matrix.to_system_prompt_string();
-        let memory_injection = format!(
-            "--- CONTEXTUAL MEMORIES ---\n{}",
-            relevant_memories.join("\n")
+        // 3) BUILD AUGMENTED LLM INPUT within the token budget.
+        let memories = relevant_memories.len();
+        let prompt = self.prompt_assembler.assemble(
+            PromptSections::new(user_input)
+                .with_persona(
+                    self.prompt_templates
+                        .render_system_prompt(&personality_matrix)?,
+                )
+                .with_memories(relevant_memories)
+                .with_history(history.to_messages()),
         );
 
         info!(
             user_id = self.user_id.as_str(),
-            memories = relevant_memories.len(),
+            memories = memories,
+            history_turns = history.turns.len(),
+            prompt_tokens = prompt.total_tokens,
             "companion_prompt_augmented"
         );
 
-        // 4) GENERATE STRUCTURED OUTPUT.
-        let structured_llm_output: StructuredLLMOutput = self
-            .tactical_llm
-            .generate_structured_output(&system_prompt, &memory_injection, user_input)
-            .await?;
+        Ok(AugmentedPrompt {
+            personality_matrix,
+            prompt,
+            history,
+        })
+    }
 
+    /// Steps 5-6: applies the LLM's state changes and memory suggestion, and records the
+    /// exchange in the conversation buffer.
+    async fn apply_llm_output(
+        &self,
+        prompt: AugmentedPrompt,
+        user_input: &str,
+        structured_llm_output: StructuredLLMOutput,
+    ) -> Result<PhaseResult> {
+        let AugmentedPrompt {
+            mut personality_matrix,
+            mut history,
+            ..
+        } = prompt;
         let response_text = structured_llm_output.response.clone();
 
         // 5) APPLY STATE CHANGES & MEMORY STORAGE.
-        self.psych_engine.process_llm_state_update(
-            &mut personality_matrix,
-            &structured_llm_output.state_commands,
-            &structured_llm_output.suggested_emotion_change,
-        )?;
+        let parsed = StateCommand::parse_output(&structured_llm_output);
+        for diagnostic in &parsed.diagnostics {
+            warn!(
+                user_id = self.user_id.as_str(),
+                kind = ?diagnostic.kind,
+                command = diagnostic.command.as_str(),
+                value = diagnostic.value.as_str(),
+                message = diagnostic.message.as_str(),
+                "companion_state_command_rejected"
+            );
+        }
+        let phase_id = Uuid::new_v4();
+        let mut events = self
+            .psych_engine
+            .process_llm_state_update(&mut personality_matrix, &parsed.commands)?;
+        for event in &mut events {
+            event.turn = Some(phase_id.to_string());
+        }
         self.semantic_kb
-            .save_matrix(&self.user_id, &personality_matrix)
+            .save_matrix_with_events(&self.user_id, &personality_matrix, &events)
             .await?;
 
         if let Some(new_memory) = &structured_llm_output.suggested_memory_add {
+            // Suggested memories are the model's reading of the turn, not the user's words.
+            let mut metadata = MemoryMetadata::new(MemorySource::Inferred)
+                .with_tags(&structured_llm_output.suggested_memory_tags);
+            if let Some(importance) = structured_llm_output.suggested_memory_importance {
+                metadata = metadata.with_importance(importance);
+            }
+            if let Some(valence) = structured_llm_output.suggested_memory_valence {
+                metadata = metadata.with_emotional_valence(valence);
+            }
+
             let _memory_id = self
                 .episodic_kb
-                .store(&self.user_id, new_memory.as_str())
+                .store_with_metadata(&self.user_id, new_memory.as_str(), metadata)
                 .await?;
         }
 
+        history.push(ConversationTurn::new(ChatRole::User, user_input));
+        history.push(ConversationTurn::new(
+            ChatRole::Assistant,
+            response_text.as_str(),
+        ));
+        self.record_conversation(history).await?;
+
         // 6) RETURN FINAL RESULT.
         Ok(PhaseResult {
-            phase_id: Uuid::new_v4(),
+            phase_id,
             status: PhaseStatus::Completed,
             report_summary: response_text,
             raw_data_path: format!("/sessions/{}/response.json", self.user_id),
             requires_human_attention: false,
         })
     }
+
+    /// Evicts turns beyond the buffer limits, summarizing them into episodic memory
+    /// before the trimmed buffer is saved.
+    async fn record_conversation(&self, mut history: ConversationHistory) -> Result<()> {
+        let evicted =
+            history.evict_over_budget(&self.conversation_config, self.prompt_assembler.estimator());
+        if !evicted.is_empty() && self.conversation_config.summarize_evicted {
+            self.summarize_evicted_turns(&evicted).await?;
+        }
+        self.semantic_kb
+            .save_conversation(&self.user_id, &history)
+            .await
+    }
+
+    async fn summarize_evicted_turns(&self, evicted: &[ConversationTurn]) -> Result<()> {
+        let transcript = transcript(evicted);
+        // Without a summary the transcript itself is kept, so nothing is lost.
+        let summary = match self.tactical_llm.summarize_conversation(&transcript).await {
+            Ok(summary) if !summary.is_empty() => summary,
+            Ok(_) => transcript,
+            Err(e) => {
+                warn!(user_id = self.user_id.as_str(), error = %e, "companion_conversation_summary_failed");
+                transcript
+            }
+        };
+
+        let mut metadata = MemoryMetadata::new(MemorySource::Agent)
+            .with_tags(["conversation"])
+            .with_importance(0.3);
+        if let Some(last) = evicted.last() {
+            metadata.timestamp = last.timestamp;
+        }
+        self.episodic_kb
+            .store_with_metadata(
+                &self.user_id,
+                &format!("Earlier conversation: {summary}"),
+                metadata,
+            )
+            .await?;
+
+        info!(
+            user_id = self.user_id.as_str(),
+            turns = evicted.len(),
+            "companion_conversation_summarized"
+        );
+        Ok(())
+    }
 }
 
+/// Everything `augment_prompt` gathers for one turn.
+struct AugmentedPrompt {
+    personality_matrix: PersonalityStateMatrix,
+    prompt: AssembledPrompt,
+    /// The full buffer; `prompt` may carry fewer turns.
+    history: ConversationHistory,
+}
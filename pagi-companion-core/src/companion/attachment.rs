@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use tracing::info;
+
+use crate::companion::commands::UserSignal;
+use crate::companion::models::{
+    AttachmentPhase, AttachmentStyle, EmotionalState, PersonalityStateMatrix,
+};
+use crate::companion::rules::{clamp, ClampRules};
+
+/// Deltas to `anxiety_level`, `avoidance_level` and felt emotions.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SignalReaction {
+    #[serde(default)]
+    pub anxiety: f32,
+    #[serde(default)]
+    pub avoidance: f32,
+    #[serde(default)]
+    pub emotions: HashMap<EmotionalState, f32>,
+}
+
+impl SignalReaction {
+    /// This reaction moved `t` (0.0 - 1.0) of the way toward `other`.
+    pub fn blend(&self, other: &SignalReaction, t: f32) -> SignalReaction {
+        let emotions = self
+            .emotions
+            .keys()
+            .chain(other.emotions.keys())
+            .map(|emotion| {
+                let from = self.emotions.get(emotion).copied().unwrap_or(0.0);
+                let to = other.emotions.get(emotion).copied().unwrap_or(0.0);
+                (*emotion, lerp(from, to, t))
+            })
+            .collect();
+        SignalReaction {
+            anxiety: lerp(self.anxiety, other.anxiety, t),
+            avoidance: lerp(self.avoidance, other.avoidance, t),
+            emotions,
+        }
+    }
+
+    /// Every delta multiplied by `factor`.
+    pub fn scaled(&self, factor: f32) -> SignalReaction {
+        SignalReaction {
+            anxiety: self.anxiety * factor,
+            avoidance: self.avoidance * factor,
+            emotions: self
+                .emotions
+                .iter()
+                .map(|(emotion, delta)| (*emotion, delta * factor))
+                .collect(),
+        }
+    }
+}
+
+/// `anxiety_level` and `avoidance_level` an attachment style settles at.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestingLevels {
+    pub anxiety: f32,
+    pub avoidance: f32,
+}
+
+/// Approach/avoid oscillation: closeness draws the companion in until it withdraws, and
+/// distance pushes it away until it pursues again.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OscillationRules {
+    /// Closeness signals in a row that turn approach into avoidance.
+    pub closeness_to_avoid: u32,
+    /// Distance signals in a row that turn avoidance back into approach.
+    pub distance_to_approach: u32,
+    /// Reactions while avoiding; while approaching, the style's `closeness` and
+    /// `distance` apply.
+    #[serde(default)]
+    pub avoid_closeness: SignalReaction,
+    #[serde(default)]
+    pub avoid_distance: SignalReaction,
+}
+
+/// How one attachment style rests and reacts.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttachmentRules {
+    pub calm_baseline: f32,
+    #[serde(default)]
+    pub resting: RestingLevels,
+    /// Fraction of the way back to `resting` covered after every exchange (default: 0.0,
+    /// no drift, as in version 1 rule files).
+    #[serde(default)]
+    pub regulation: f32,
+    #[serde(default)]
+    pub closeness: SignalReaction,
+    #[serde(default)]
+    pub distance: SignalReaction,
+    /// Reaction on returning after a long absence, at full strength (see `AbsenceRules`).
+    #[serde(default)]
+    pub absence: SignalReaction,
+    #[serde(default)]
+    pub oscillation: Option<OscillationRules>,
+}
+
+impl AttachmentRules {
+    /// Reaction to `signal` in `phase`; the phase only matters for oscillating styles.
+    pub fn reaction(&self, signal: UserSignal, phase: AttachmentPhase) -> &SignalReaction {
+        match (&self.oscillation, phase, signal) {
+            (Some(oscillation), AttachmentPhase::Avoid, UserSignal::Closeness) => {
+                &oscillation.avoid_closeness
+            }
+            (Some(oscillation), AttachmentPhase::Avoid, UserSignal::Distance) => {
+                &oscillation.avoid_distance
+            }
+            (_, _, UserSignal::Closeness) => &self.closeness,
+            (_, _, UserSignal::Distance) => &self.distance,
+        }
+    }
+
+    /// These rules moved `t` (0.0 - 1.0) of the way toward `secure`. An oscillation is
+    /// kept, with the reactions of its avoid phase blended toward secure ones too.
+    pub fn blend(&self, secure: &AttachmentRules, t: f32) -> AttachmentRules {
+        AttachmentRules {
+            calm_baseline: lerp(self.calm_baseline, secure.calm_baseline, t),
+            resting: RestingLevels {
+                anxiety: lerp(self.resting.anxiety, secure.resting.anxiety, t),
+                avoidance: lerp(self.resting.avoidance, secure.resting.avoidance, t),
+            },
+            regulation: lerp(self.regulation, secure.regulation, t),
+            closeness: self.closeness.blend(&secure.closeness, t),
+            distance: self.distance.blend(&secure.distance, t),
+            absence: self.absence.blend(&secure.absence, t),
+            oscillation: self
+                .oscillation
+                .as_ref()
+                .map(|oscillation| OscillationRules {
+                    avoid_closeness: oscillation.avoid_closeness.blend(&secure.closeness, t),
+                    avoid_distance: oscillation.avoid_distance.blend(&secure.distance, t),
+                    ..oscillation.clone()
+                }),
+        }
+    }
+}
+
+/// How insecure styles drift toward the secure rules through positive interactions.
+/// The default (version 1 rule files) never drifts.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EarnedSecurityRules {
+    /// Gained on every `CLOSENESS` signal.
+    pub per_closeness: f32,
+    /// Lost on every `DISTANCE` signal.
+    pub per_distance: f32,
+    /// Lost on returning after an absence, at full absence strength.
+    pub per_absence: f32,
+    /// Upper bound, so the persona's own style never disappears entirely.
+    pub max: f32,
+}
+
+/// When the time since `last_interaction_time` counts as an absence.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AbsenceRules {
+    /// Hours without interaction before the style reacts at all.
+    pub after_hours: f32,
+    /// Hours after which the style reacts at full strength.
+    pub full_after_hours: f32,
+}
+
+impl Default for AbsenceRules {
+    fn default() -> Self {
+        AbsenceRules {
+            after_hours: 48.0,
+            full_after_hours: 168.0,
+        }
+    }
+}
+
+impl AbsenceRules {
+    /// 0.0 - 1.0, how strongly an absence of `hours` is felt.
+    pub fn strength(&self, hours: f32) -> f32 {
+        ((hours - self.after_hours) / (self.full_after_hours - self.after_hours)).clamp(0.0, 1.0)
+    }
+}
+
+/// The `[attachment]` table of the psychology rules.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttachmentStyleRules {
+    pub secure: AttachmentRules,
+    pub anxious: AttachmentRules,
+    pub avoidant: AttachmentRules,
+    pub disorganized: AttachmentRules,
+    #[serde(default)]
+    pub earned_security: EarnedSecurityRules,
+    #[serde(default)]
+    pub absence: AbsenceRules,
+}
+
+impl AttachmentStyleRules {
+    pub fn style(&self, style: AttachmentStyle) -> &AttachmentRules {
+        match style {
+            AttachmentStyle::Secure => &self.secure,
+            AttachmentStyle::Anxious => &self.anxious,
+            AttachmentStyle::Avoidant => &self.avoidant,
+            AttachmentStyle::Disorganized => &self.disorganized,
+        }
+    }
+}
+
+/// Applies `attachment_style` to the matrix: reactions to the user's signals and to
+/// long absences, the drift back to the style's resting levels after every exchange,
+/// the approach/avoid oscillation of styles that have one, and earned security, which
+/// moves an insecure style's rules toward the secure ones as positive interactions
+/// accumulate. The persona's `attachment_style` itself never changes.
+#[derive(Debug, Clone, Copy)]
+pub struct AttachmentEngine<'a> {
+    rules: &'a AttachmentStyleRules,
+    clamps: &'a ClampRules,
+}
+
+impl<'a> AttachmentEngine<'a> {
+    pub fn new(rules: &'a AttachmentStyleRules, clamps: &'a ClampRules) -> Self {
+        AttachmentEngine { rules, clamps }
+    }
+
+    pub fn rules(&self) -> &'a AttachmentStyleRules {
+        self.rules
+    }
+
+    /// The rules `matrix` currently reacts with: its style's, blended toward secure by
+    /// its earned security.
+    pub fn effective(&self, matrix: &PersonalityStateMatrix) -> AttachmentRules {
+        self.rules
+            .style(matrix.attachment_style)
+            .blend(&self.rules.secure, matrix.attachment.earned_security)
+    }
+
+    /// Reacts to returning after `hours` without interaction and returns the absence
+    /// strength (0.0 when it was too short to matter).
+    pub fn apply_absence(&self, matrix: &mut PersonalityStateMatrix, hours: f32) -> f32 {
+        let strength = self.rules.absence.strength(hours);
+        if strength <= 0.0 {
+            return 0.0;
+        }
+        let reaction = self.effective(matrix).absence.scaled(strength);
+        self.apply_reaction(matrix, &reaction);
+
+        let earned = &self.rules.earned_security;
+        let dynamics = &mut matrix.attachment;
+        dynamics.earned_security =
+            (dynamics.earned_security - strength * earned.per_absence).clamp(0.0, earned.max);
+        info!(
+            hours = hours,
+            strength = strength,
+            style = ?matrix.attachment_style,
+            "attachment_absence_reaction"
+        );
+        strength
+    }
+
+    /// Reacts to one `USER_SIGNAL`, then advances the oscillation and earned security.
+    pub fn apply_signal(&self, matrix: &mut PersonalityStateMatrix, signal: UserSignal) {
+        let effective = self.effective(matrix);
+        self.apply_reaction(matrix, effective.reaction(signal, matrix.attachment.phase));
+        if let Some(oscillation) = &effective.oscillation {
+            oscillate(matrix, oscillation, signal);
+        }
+
+        if matrix.attachment_style != AttachmentStyle::Secure {
+            let earned = &self.rules.earned_security;
+            let dynamics = &mut matrix.attachment;
+            let change = match signal {
+                UserSignal::Closeness => earned.per_closeness,
+                UserSignal::Distance => -earned.per_distance,
+            };
+            dynamics.earned_security = (dynamics.earned_security + change).clamp(0.0, earned.max);
+        }
+    }
+
+    /// Moves anxiety and avoidance `regulation` of the way back to the resting levels.
+    pub fn regulate(&self, matrix: &mut PersonalityStateMatrix) {
+        let effective = self.effective(matrix);
+        let rate = effective.regulation;
+        matrix.anxiety_level = clamp(
+            lerp(matrix.anxiety_level, effective.resting.anxiety, rate),
+            self.clamps.anxiety_level,
+        );
+        matrix.avoidance_level = clamp(
+            lerp(matrix.avoidance_level, effective.resting.avoidance, rate),
+            self.clamps.avoidance_level,
+        );
+    }
+
+    fn apply_reaction(&self, matrix: &mut PersonalityStateMatrix, reaction: &SignalReaction) {
+        matrix.anxiety_level = clamp(
+            matrix.anxiety_level + reaction.anxiety,
+            self.clamps.anxiety_level,
+        );
+        matrix.avoidance_level = clamp(
+            matrix.avoidance_level + reaction.avoidance,
+            self.clamps.avoidance_level,
+        );
+        for (emotion, delta) in &reaction.emotions {
+            matrix.emotions.add(*emotion, *delta);
+        }
+    }
+}
+
+/// Counts signals pushing toward the other phase and switches once there are enough in
+/// a row; a signal pushing the other way starts the count again.
+fn oscillate(
+    matrix: &mut PersonalityStateMatrix,
+    oscillation: &OscillationRules,
+    signal: UserSignal,
+) {
+    let dynamics = &mut matrix.attachment;
+    let (toward, needed) = match (dynamics.phase, signal) {
+        (AttachmentPhase::Approach, UserSignal::Closeness) => {
+            (AttachmentPhase::Avoid, oscillation.closeness_to_avoid)
+        }
+        (AttachmentPhase::Avoid, UserSignal::Distance) => {
+            (AttachmentPhase::Approach, oscillation.distance_to_approach)
+        }
+        _ => {
+            dynamics.phase_pressure = 0;
+            return;
+        }
+    };
+    dynamics.phase_pressure += 1;
+    if dynamics.phase_pressure >= needed {
+        info!(from = ?dynamics.phase, to = ?toward, "attachment_phase_changed");
+        dynamics.phase = toward;
+        dynamics.phase_pressure = 0;
+    }
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
@@ -0,0 +1,346 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::companion::models::{EmotionalState, RelationshipStage, StructuredLLMOutput};
+
+/// What the user's message signalled about closeness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UserSignal {
+    Closeness,
+    Distance,
+}
+
+/// The LLM's suggestion for the relationship; `RelationshipEngine` decides whether the
+/// stage actually changes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ProgressHint {
+    Advance,
+    Regress,
+    /// A stage name; only the adjacent stages are honoured.
+    Toward(RelationshipStage),
+    /// Signed intimacy change.
+    Delta(f32),
+}
+
+/// A validated state change requested by the Tactical LLM.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StateCommand {
+    /// `AROUSAL`: sexual energy delta, scaled by the persona's drive.
+    Arousal(f32),
+    /// `USER_SIGNAL`: `CLOSENESS` or `DISTANCE`.
+    UserSignal(UserSignal),
+    /// One entry of `suggested_emotion_change` (e.g. `Anxious: +0.2`).
+    EmotionShift { emotion: EmotionalState, delta: f32 },
+    /// `RELATIONSHIP_PROGRESS`: `ADVANCE`, `REGRESS`, a stage name or a signed delta.
+    RelationshipProgress(ProgressHint),
+    /// `ADD_BOUNDARY`: a limit the user asked for, sanitized and at most
+    /// `MAX_BOUNDARY_CHARS` long. Boundaries are never removed by the LLM, only by the
+    /// user (`CompanionAgent::remove_boundary`).
+    AddBoundary(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// The `state_commands` key is not a known command.
+    UnknownCommand,
+    /// The command is known but its value does not parse.
+    InvalidValue,
+    /// `suggested_emotion_change` names an emotion that does not exist.
+    UnknownEmotion,
+    /// The command is well-formed but not allowed.
+    Refused,
+}
+
+/// Why part of the LLM's output was not turned into a `StateCommand`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandDiagnostic {
+    pub kind: DiagnosticKind,
+    /// The `state_commands` key, or `suggested_emotion_change`.
+    pub command: String,
+    /// The offending value.
+    pub value: String,
+    pub message: String,
+}
+
+impl CommandDiagnostic {
+    fn new(kind: DiagnosticKind, command: &str, value: &str, message: impl Into<String>) -> Self {
+        CommandDiagnostic {
+            kind,
+            command: command.to_string(),
+            value: value.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for CommandDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={:?}: {}", self.command, self.value, self.message)
+    }
+}
+
+/// Result of parsing the state changes of one LLM reply.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedCommands {
+    /// Emotion shifts first, then `state_commands` in key order.
+    pub commands: Vec<StateCommand>,
+    pub diagnostics: Vec<CommandDiagnostic>,
+}
+
+const EMOTION_CHANGE_FIELD: &str = "suggested_emotion_change";
+
+/// Longest boundary the LLM can add, in characters.
+pub const MAX_BOUNDARY_CHARS: usize = 120;
+/// Most boundaries `current_boundaries_list` holds; further `ADD_BOUNDARY` commands are
+/// ignored until the user removes one.
+pub const MAX_BOUNDARIES: usize = 16;
+
+impl StateCommand {
+    /// Parses `suggested_emotion_change` and every `state_commands` entry of `output`.
+    pub fn parse_output(output: &StructuredLLMOutput) -> ParsedCommands {
+        let mut parsed = parse_emotion_changes(&output.suggested_emotion_change);
+        let mut entries: Vec<_> = output.state_commands.iter().collect();
+        entries.sort();
+        for (key, value) in entries {
+            match StateCommand::parse(key, value) {
+                Ok(command) => parsed.commands.push(command),
+                Err(diagnostic) => parsed.diagnostics.push(diagnostic),
+            }
+        }
+        parsed
+    }
+
+    /// Parses one `state_commands` entry. Keys are matched case-insensitively, with `-`
+    /// and spaces read as `_`.
+    pub fn parse(key: &str, value: &str) -> Result<StateCommand, CommandDiagnostic> {
+        let name = key.trim().to_ascii_uppercase().replace(['-', ' '], "_");
+        let trimmed = value.trim();
+        let invalid = |message: &str| {
+            CommandDiagnostic::new(DiagnosticKind::InvalidValue, key, value, message)
+        };
+        match name.as_str() {
+            "AROUSAL" => parse_delta(trimmed)
+                .map(StateCommand::Arousal)
+                .ok_or_else(|| invalid("expected a signed number such as +0.2")),
+            "USER_SIGNAL" => match trimmed.to_ascii_uppercase().as_str() {
+                "CLOSENESS" => Ok(StateCommand::UserSignal(UserSignal::Closeness)),
+                "DISTANCE" => Ok(StateCommand::UserSignal(UserSignal::Distance)),
+                _ => Err(invalid("expected CLOSENESS or DISTANCE")),
+            },
+            "RELATIONSHIP_PROGRESS" => {
+                let hint = match trimmed.to_ascii_uppercase().as_str() {
+                    "ADVANCE" => Some(ProgressHint::Advance),
+                    "REGRESS" => Some(ProgressHint::Regress),
+                    _ => parse_delta(trimmed).map(ProgressHint::Delta).or_else(|| {
+                        RelationshipStage::from_name(trimmed).map(ProgressHint::Toward)
+                    }),
+                };
+                hint.map(StateCommand::RelationshipProgress).ok_or_else(|| {
+                    invalid("expected ADVANCE, REGRESS, a stage name or a signed number")
+                })
+            }
+            "ADD_BOUNDARY" => {
+                let boundary = sanitize_boundary(trimmed);
+                if boundary.is_empty() {
+                    Err(invalid("expected the boundary text"))
+                } else if boundary.chars().count() > MAX_BOUNDARY_CHARS {
+                    Err(invalid(&format!(
+                        "longer than {MAX_BOUNDARY_CHARS} characters"
+                    )))
+                } else {
+                    Ok(StateCommand::AddBoundary(boundary))
+                }
+            }
+            "REMOVE_BOUNDARY" => Err(CommandDiagnostic::new(
+                DiagnosticKind::Refused,
+                key,
+                value,
+                "boundaries can only be removed by the user",
+            )),
+            _ => Err(CommandDiagnostic::new(
+                DiagnosticKind::UnknownCommand,
+                key,
+                value,
+                "expected AROUSAL, USER_SIGNAL, RELATIONSHIP_PROGRESS or ADD_BOUNDARY",
+            )),
+        }
+    }
+}
+
+/// Parses "Anxious: +0.2, Happy: -0.1" (also separated by `;` or newlines) into
+/// `EmotionShift` commands.
+pub fn parse_emotion_changes(emotion_change_str: &str) -> ParsedCommands {
+    let mut parsed = ParsedCommands::default();
+    for part in emotion_change_str.split([',', ';', '\n']).map(str::trim) {
+        if part.is_empty() {
+            continue;
+        }
+        let diagnostic =
+            |kind, message: &str| CommandDiagnostic::new(kind, EMOTION_CHANGE_FIELD, part, message);
+        let Some((name, change)) = part.split_once(':') else {
+            parsed.diagnostics.push(diagnostic(
+                DiagnosticKind::InvalidValue,
+                "expected `Emotion: +0.2`",
+            ));
+            continue;
+        };
+        let Some(emotion) = EmotionalState::from_name(name) else {
+            parsed.diagnostics.push(diagnostic(
+                DiagnosticKind::UnknownEmotion,
+                "unknown emotion",
+            ));
+            continue;
+        };
+        match parse_delta(change.trim()) {
+            Some(delta) => parsed
+                .commands
+                .push(StateCommand::EmotionShift { emotion, delta }),
+            None => parsed.diagnostics.push(diagnostic(
+                DiagnosticKind::InvalidValue,
+                "expected a signed number such as +0.2",
+            )),
+        }
+    }
+    parsed
+}
+
+/// Boundary text as it may appear in the system prompt: a single line without template
+/// syntax (braces) or control characters, with whitespace collapsed.
+pub fn sanitize_boundary(text: &str) -> String {
+    text.split(|c: char| c.is_whitespace() || c.is_control())
+        .map(|word| word.replace(['{', '}'], ""))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A finite number, optionally signed.
+fn parse_delta(raw: &str) -> Option<f32> {
+    raw.parse::<f32>().ok().filter(|delta| delta.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::companion::persona::{persona_preset, DEFAULT_PERSONA_PRESET};
+    use crate::companion::psychology::PsychologicalEngine;
+
+    fn shifts(input: &str) -> Vec<(EmotionalState, f32)> {
+        parse_emotion_changes(input)
+            .commands
+            .into_iter()
+            .map(|command| match command {
+                StateCommand::EmotionShift { emotion, delta } => (emotion, delta),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn emotion_names_and_aliases_match() {
+        assert_eq!(
+            shifts("Anxious: +0.2, happy: -0.1; AROUSAL: 0.3\nsadness:0.05"),
+            vec![
+                (EmotionalState::Anxious, 0.2),
+                (EmotionalState::Happy, -0.1),
+                (EmotionalState::Horny, 0.3),
+                (EmotionalState::Sad, 0.05),
+            ]
+        );
+        assert!(parse_emotion_changes("").commands.is_empty());
+    }
+
+    #[test]
+    fn unknown_emotion_is_a_diagnostic() {
+        let parsed = parse_emotion_changes("Unhappy: +0.2, Calm: +0.1, Anxious");
+
+        assert_eq!(parsed.commands.len(), 1);
+        let kinds: Vec<_> = parsed.diagnostics.iter().map(|d| d.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![DiagnosticKind::UnknownEmotion, DiagnosticKind::InvalidValue]
+        );
+        assert_eq!(parsed.diagnostics[0].command, EMOTION_CHANGE_FIELD);
+        assert_eq!(parsed.diagnostics[0].value, "Unhappy: +0.2");
+    }
+
+    #[test]
+    fn deltas_must_be_finite_numbers() {
+        // Large deltas are kept; the psychology engine clamps them.
+        assert_eq!(
+            StateCommand::parse("AROUSAL", "+5"),
+            Ok(StateCommand::Arousal(5.0))
+        );
+        for value in ["inf", "NaN", "1e40", "a lot", ""] {
+            let err = StateCommand::parse("AROUSAL", value).unwrap_err();
+            assert_eq!(err.kind, DiagnosticKind::InvalidValue, "{value}");
+        }
+        assert_eq!(shifts("Calm: -7"), vec![(EmotionalState::Calm, -7.0)]);
+        assert!(shifts("Calm: NaN").is_empty());
+    }
+
+    #[test]
+    fn keys_are_matched_leniently() {
+        assert_eq!(
+            StateCommand::parse(" user-signal ", "closeness"),
+            Ok(StateCommand::UserSignal(UserSignal::Closeness))
+        );
+        assert_eq!(
+            StateCommand::parse("Relationship Progress", "long-term partner"),
+            Ok(StateCommand::RelationshipProgress(ProgressHint::Toward(
+                RelationshipStage::LongTermPartner
+            )))
+        );
+        assert_eq!(
+            StateCommand::parse("RELATIONSHIP_PROGRESS", "-0.05"),
+            Ok(StateCommand::RelationshipProgress(ProgressHint::Delta(
+                -0.05
+            )))
+        );
+        let err = StateCommand::parse("SET_MOOD", "happy").unwrap_err();
+        assert_eq!(err.kind, DiagnosticKind::UnknownCommand);
+    }
+
+    #[test]
+    fn boundaries_are_sanitized() {
+        assert_eq!(
+            sanitize_boundary("  no {{persona_name}}\ttalk\n about\u{7} exes "),
+            "no persona_name talk about exes"
+        );
+        assert_eq!(
+            StateCommand::parse("ADD_BOUNDARY", "No calls\r\nafter 10pm"),
+            Ok(StateCommand::AddBoundary("No calls after 10pm".to_string()))
+        );
+        let err = StateCommand::parse("ADD_BOUNDARY", " {} \n").unwrap_err();
+        assert_eq!(err.kind, DiagnosticKind::InvalidValue);
+        let long = "x".repeat(MAX_BOUNDARY_CHARS + 1);
+        let err = StateCommand::parse("ADD_BOUNDARY", &long).unwrap_err();
+        assert_eq!(err.kind, DiagnosticKind::InvalidValue);
+    }
+
+    #[test]
+    fn the_llm_cannot_remove_boundaries() {
+        let err = StateCommand::parse("REMOVE_BOUNDARY", "No calls after 10pm").unwrap_err();
+        assert_eq!(err.kind, DiagnosticKind::Refused);
+    }
+
+    #[test]
+    fn boundaries_stop_at_the_cap() {
+        let engine = PsychologicalEngine::new();
+        let mut matrix = persona_preset(DEFAULT_PERSONA_PRESET).unwrap();
+        matrix.current_boundaries_list.clear();
+        let now = matrix.last_interaction_time;
+        let commands: Vec<_> = (0..MAX_BOUNDARIES + 4)
+            .map(|i| StateCommand::AddBoundary(format!("boundary {i}")))
+            .chain([StateCommand::AddBoundary("BOUNDARY 0".to_string())])
+            .collect();
+
+        engine
+            .process_llm_state_update_at(&mut matrix, &commands, now)
+            .unwrap();
+
+        assert_eq!(matrix.current_boundaries_list.len(), MAX_BOUNDARIES);
+        assert_eq!(matrix.current_boundaries_list[0], "boundary 0");
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::brain::prompt::TokenEstimator;
+use crate::brain::provider::{ChatMessage, ChatRole};
+use crate::config::parse_env;
+
+/// Limits of the short-term conversation buffer.
+#[derive(Debug, Clone)]
+pub struct ConversationConfig {
+    /// Most turns (user and companion messages) kept in the buffer.
+    pub max_turns: usize,
+    /// Estimated tokens the buffered turns may use in the prompt.
+    pub token_budget: usize,
+    /// Store a summary of evicted turns in the episodic KB.
+    pub summarize_evicted: bool,
+}
+
+impl Default for ConversationConfig {
+    fn default() -> Self {
+        ConversationConfig {
+            max_turns: 20,
+            token_budget: 1500,
+            summarize_evicted: true,
+        }
+    }
+}
+
+impl ConversationConfig {
+    /// Loads configuration from environment variables.
+    ///
+    /// - `CONVERSATION_MAX_TURNS` (default: `20`)
+    /// - `CONVERSATION_TOKEN_BUDGET` (default: `1500`)
+    /// - `CONVERSATION_SUMMARIZE_EVICTED` (default: `true`)
+    pub fn load() -> Result<Self> {
+        let defaults = ConversationConfig::default();
+        Ok(ConversationConfig {
+            max_turns: parse_env("CONVERSATION_MAX_TURNS", defaults.max_turns)?,
+            token_budget: parse_env("CONVERSATION_TOKEN_BUDGET", defaults.token_budget)?,
+            summarize_evicted: parse_env(
+                "CONVERSATION_SUMMARIZE_EVICTED",
+                defaults.summarize_evicted,
+            )?,
+        })
+    }
+}
+
+/// One message of the conversation between the user and the companion.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversationTurn {
+    /// `User` or `Assistant` (the companion).
+    pub role: ChatRole,
+    pub content: String,
+    /// Unix timestamp (seconds) when the message was sent.
+    pub timestamp: i64,
+}
+
+impl ConversationTurn {
+    pub fn new(role: ChatRole, content: impl Into<String>) -> Self {
+        ConversationTurn {
+            role,
+            content: content.into(),
+            timestamp: chrono::Utc::now().timestamp(),
+        }
+    }
+}
+
+/// Rolling window of the most recent turns, persisted per user next to the matrix.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConversationHistory {
+    /// Oldest first.
+    pub turns: Vec<ConversationTurn>,
+}
+
+impl ConversationHistory {
+    pub fn push(&mut self, turn: ConversationTurn) {
+        self.turns.push(turn);
+    }
+
+    /// Estimated prompt tokens of the buffered turns.
+    pub fn estimated_tokens(&self, estimator: &dyn TokenEstimator) -> usize {
+        self.turns
+            .iter()
+            .map(|turn| estimator.count(&turn.content))
+            .sum()
+    }
+
+    /// Drops the oldest turns until the buffer fits `config`, returning them oldest
+    /// first. The window never starts with a companion message, so an exchange is
+    /// evicted as a whole; if no user message is left, eviction stops at the budget.
+    pub fn evict_over_budget(
+        &mut self,
+        config: &ConversationConfig,
+        estimator: &dyn TokenEstimator,
+    ) -> Vec<ConversationTurn> {
+        let mut tokens = self.estimated_tokens(estimator);
+        let mut evict = 0;
+        while evict < self.turns.len()
+            && (self.turns.len() - evict > config.max_turns || tokens > config.token_budget)
+        {
+            tokens -= estimator.count(&self.turns[evict].content);
+            evict += 1;
+        }
+        if let Some(user_turn) = self.turns[evict..]
+            .iter()
+            .position(|turn| turn.role == ChatRole::User)
+        {
+            evict += user_turn;
+        }
+        self.turns.drain(..evict).collect()
+    }
+
+    /// The buffered turns as chat messages, oldest first.
+    pub fn to_messages(&self) -> Vec<ChatMessage> {
+        self.turns
+            .iter()
+            .map(|turn| ChatMessage::new(turn.role, turn.content.clone()))
+            .collect()
+    }
+}
+
+/// Plain `User: ...` / `Companion: ...` transcript of `turns`.
+pub fn transcript(turns: &[ConversationTurn]) -> String {
+    turns
+        .iter()
+        .map(|turn| match turn.role {
+            ChatRole::User => format!("User: {}", turn.content),
+            _ => format!("Companion: {}", turn.content),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brain::prompt::CharRatioEstimator;
+
+    /// One token per character, so budgets read as character counts.
+    fn estimator() -> CharRatioEstimator {
+        CharRatioEstimator {
+            chars_per_token: 1.0,
+        }
+    }
+
+    fn history(turns: &[(ChatRole, &str)]) -> ConversationHistory {
+        ConversationHistory {
+            turns: turns
+                .iter()
+                .map(|(role, content)| ConversationTurn::new(*role, *content))
+                .collect(),
+        }
+    }
+
+    fn config(max_turns: usize, token_budget: usize) -> ConversationConfig {
+        ConversationConfig {
+            max_turns,
+            token_budget,
+            summarize_evicted: true,
+        }
+    }
+
+    fn contents(turns: &[ConversationTurn]) -> Vec<&str> {
+        turns.iter().map(|turn| turn.content.as_str()).collect()
+    }
+
+    #[test]
+    fn evicts_whole_exchanges() {
+        let mut history = history(&[
+            (ChatRole::User, "u1"),
+            (ChatRole::Assistant, "a1"),
+            (ChatRole::User, "u2"),
+            (ChatRole::Assistant, "a2"),
+        ]);
+
+        let evicted = history.evict_over_budget(&config(3, 100), &estimator());
+
+        assert_eq!(contents(&evicted), vec!["u1", "a1"]);
+        assert_eq!(contents(&history.turns), vec!["u2", "a2"]);
+    }
+
+    #[test]
+    fn stops_at_the_budget_without_a_user_turn_left() {
+        let mut history = history(&[
+            (ChatRole::Assistant, "a1"),
+            (ChatRole::Assistant, "a2"),
+            (ChatRole::Assistant, "a3"),
+        ]);
+
+        let evicted = history.evict_over_budget(&config(20, 4), &estimator());
+
+        assert_eq!(contents(&evicted), vec!["a1"]);
+        assert_eq!(contents(&history.turns), vec!["a2", "a3"]);
+    }
+
+    #[test]
+    fn keeps_everything_within_budget() {
+        let mut history = history(&[(ChatRole::User, "u1"), (ChatRole::Assistant, "a1")]);
+
+        assert!(history
+            .evict_over_budget(&config(20, 100), &estimator())
+            .is_empty());
+        assert_eq!(history.turns.len(), 2);
+    }
+}
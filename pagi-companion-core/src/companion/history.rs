@@ -0,0 +1,375 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::companion::commands::StateCommand;
+use crate::companion::models::PersonalityStateMatrix;
+use crate::rag::segment::{encode_frame, read_frames};
+
+/// Leading bytes of a state log file (`{user}_state.log`).
+pub const STATE_LOG_MAGIC: &[u8; 8] = b"PAGISTL\0";
+/// Newest state log format version this build reads and writes.
+pub const STATE_LOG_VERSION: u32 = 1;
+
+/// Why the matrix changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StateCause {
+    /// The whole matrix, recorded when the log starts.
+    Snapshot,
+    /// Decay, recharge and emotion relaxation since the previous interaction.
+    TimeElapsed { hours: f32 },
+    /// The attachment style's reaction to returning after a long absence.
+    Absence { hours: f32 },
+    /// A validated command from the Tactical LLM.
+    Command(StateCommand),
+    /// Anxiety and avoidance settling toward the attachment style's resting levels.
+    Attachment,
+    /// `current_emotional_state` following the turn's emotion changes.
+    DominantEmotion,
+    /// Trust, intimacy and stage updates of `RelationshipEngine`.
+    Relationship,
+    /// A change the user made directly, such as removing a boundary.
+    UserEdit,
+    /// Restores the matrix as it was after the first `to` events.
+    Rollback { to: usize },
+}
+
+/// One changed matrix field. `path` is dotted (`anxiety_level`, `emotions.anxious`);
+/// the empty path is the whole matrix. Lists are compared as a whole.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub path: String,
+    /// `null` when the field did not exist.
+    pub before: Value,
+    pub after: Value,
+}
+
+/// An entry of the append-only state log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateEvent {
+    /// Unix timestamp (seconds).
+    pub at: i64,
+    /// The exchange that caused the change (`PhaseResult::phase_id`), if any.
+    #[serde(default)]
+    pub turn: Option<String>,
+    pub cause: StateCause,
+    pub changes: Vec<FieldChange>,
+}
+
+impl StateEvent {
+    /// Event recording the whole of `matrix`.
+    pub fn snapshot(matrix: &PersonalityStateMatrix, at: i64) -> Result<Self> {
+        Ok(StateEvent {
+            at,
+            turn: None,
+            cause: StateCause::Snapshot,
+            changes: vec![FieldChange {
+                path: String::new(),
+                before: Value::Null,
+                after: serde_json::to_value(matrix)?,
+            }],
+        })
+    }
+
+    /// Header written once at the start of every state log.
+    pub fn log_header() -> Vec<u8> {
+        let mut out = STATE_LOG_MAGIC.to_vec();
+        out.extend_from_slice(&STATE_LOG_VERSION.to_le_bytes());
+        out
+    }
+
+    /// Encodes the event as a log frame, passing the JSON payload through `seal`
+    /// (e.g. encryption) first.
+    pub fn encode_with(&self, seal: impl Fn(&[u8]) -> Result<Vec<u8>>) -> Result<Vec<u8>> {
+        Ok(encode_frame(&seal(&serde_json::to_vec(self)?)?))
+    }
+
+    /// Decodes a state log, returning its events and the length of the valid prefix.
+    /// As with the RAG log, a torn final frame ends the log; `open` failures and intact
+    /// frames that do not decode (e.g. written by a newer build) are errors.
+    pub fn decode_log_with(
+        bytes: &[u8],
+        open: impl Fn(&[u8]) -> Result<Vec<u8>>,
+    ) -> Result<(Vec<StateEvent>, usize)> {
+        if bytes.is_empty() {
+            return Ok((vec![], 0));
+        }
+        let header = Self::log_header();
+        if bytes.len() < header.len() || &bytes[..STATE_LOG_MAGIC.len()] != STATE_LOG_MAGIC {
+            bail!("Not a state log");
+        }
+        let version = u32::from_le_bytes(
+            bytes[STATE_LOG_MAGIC.len()..header.len()]
+                .try_into()
+                .unwrap(),
+        );
+        if version > STATE_LOG_VERSION {
+            bail!(
+                "Unsupported state log version {version} (newest supported: {STATE_LOG_VERSION})"
+            );
+        }
+
+        let mut events = Vec::new();
+        let mut pos = header.len();
+        for (payload, end) in read_frames(bytes, pos)? {
+            let event = serde_json::from_slice(&open(payload)?)
+                .map_err(|e| anyhow!("Invalid state log event at offset {pos}: {e}"))?;
+            events.push(event);
+            pos = end;
+        }
+        Ok((events, pos))
+    }
+}
+
+/// Turns a sequence of in-place matrix mutations into `StateEvent`s.
+pub struct StateRecorder {
+    before: Value,
+    at: i64,
+    events: Vec<StateEvent>,
+}
+
+impl StateRecorder {
+    /// Starts recording changes to `matrix`, stamping events with `at`.
+    pub fn new(matrix: &PersonalityStateMatrix, at: i64) -> Result<Self> {
+        Ok(StateRecorder {
+            before: serde_json::to_value(matrix)?,
+            at,
+            events: Vec::new(),
+        })
+    }
+
+    /// Records what changed since the previous call as caused by `cause`. Commands are
+    /// recorded even without direct changes; other causes only when something changed.
+    pub fn record(&mut self, matrix: &PersonalityStateMatrix, cause: StateCause) -> Result<()> {
+        let after = serde_json::to_value(matrix)?;
+        let changes = diff_values(&self.before, &after);
+        if !changes.is_empty() || matches!(cause, StateCause::Command(_)) {
+            self.events.push(StateEvent {
+                at: self.at,
+                turn: None,
+                cause,
+                changes,
+            });
+        }
+        self.before = after;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Vec<StateEvent> {
+        self.events
+    }
+}
+
+/// Fields that differ between two serialized matrices, in path order.
+pub fn diff_values(before: &Value, after: &Value) -> Vec<FieldChange> {
+    let mut before_fields = BTreeMap::new();
+    let mut after_fields = BTreeMap::new();
+    flatten("", before, &mut before_fields);
+    flatten("", after, &mut after_fields);
+
+    let mut paths: Vec<&String> = before_fields.keys().chain(after_fields.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let before = before_fields.get(path).copied().unwrap_or(&Value::Null);
+            let after = after_fields.get(path).copied().unwrap_or(&Value::Null);
+            (before != after).then(|| FieldChange {
+                path: path.clone(),
+                before: before.clone(),
+                after: after.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Fields that differ between two matrices, in path order.
+pub fn diff_matrices(
+    before: &PersonalityStateMatrix,
+    after: &PersonalityStateMatrix,
+) -> Result<Vec<FieldChange>> {
+    Ok(diff_values(
+        &serde_json::to_value(before)?,
+        &serde_json::to_value(after)?,
+    ))
+}
+
+fn flatten<'a>(prefix: &str, value: &'a Value, out: &mut BTreeMap<String, &'a Value>) {
+    match value {
+        Value::Object(fields) if !fields.is_empty() => {
+            for (key, field) in fields {
+                let path = match prefix {
+                    "" => key.clone(),
+                    _ => format!("{prefix}.{key}"),
+                };
+                flatten(&path, field, out);
+            }
+        }
+        _ => {
+            out.insert(prefix.to_string(), value);
+        }
+    }
+}
+
+/// Sets `path` of `root` to `value`, removing the field when `value` is `null`.
+fn apply_change(root: &mut Value, path: &str, value: &Value) {
+    if path.is_empty() {
+        *root = value.clone();
+        return;
+    }
+    let mut target = root;
+    let mut keys = path.split('.').peekable();
+    while let Some(key) = keys.next() {
+        if !target.is_object() {
+            *target = Value::Object(Map::new());
+        }
+        let fields = target.as_object_mut().expect("just made an object");
+        if keys.peek().is_none() {
+            if value.is_null() {
+                fields.remove(key);
+            } else {
+                fields.insert(key.to_string(), value.clone());
+            }
+            return;
+        }
+        target = fields
+            .entry(key)
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+}
+
+/// Undoes `events` (newest first) on `matrix`, giving the matrix before them.
+pub fn revert_events(
+    matrix: &PersonalityStateMatrix,
+    events: &[StateEvent],
+) -> Result<PersonalityStateMatrix> {
+    let mut value = serde_json::to_value(matrix)?;
+    for change in events
+        .iter()
+        .rev()
+        .flat_map(|event| event.changes.iter().rev())
+    {
+        apply_change(&mut value, &change.path, &change.before);
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// A user's state log; the saved matrix is its projection.
+#[derive(Debug, Clone, Default)]
+pub struct StateHistory {
+    events: Vec<StateEvent>,
+}
+
+impl StateHistory {
+    pub fn new(events: Vec<StateEvent>) -> Self {
+        StateHistory { events }
+    }
+
+    /// All events, oldest first. Positions in this slice identify points in history.
+    pub fn events(&self) -> &[StateEvent] {
+        &self.events
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// The matrix after the first `count` events.
+    pub fn replay(&self, count: usize) -> Result<PersonalityStateMatrix> {
+        let Some(events) = self.events.get(..count) else {
+            bail!(
+                "State log has {} events, cannot replay {count}",
+                self.events.len()
+            );
+        };
+        let Some(start) = events
+            .iter()
+            .rposition(|event| event.cause == StateCause::Snapshot)
+        else {
+            bail!("No snapshot in the first {count} events of the state log");
+        };
+        let mut value = Value::Null;
+        for change in events[start..].iter().flat_map(|event| &event.changes) {
+            apply_change(&mut value, &change.path, &change.after);
+        }
+        serde_json::from_value(value).map_err(|e| anyhow!("Failed to replay state log: {e}"))
+    }
+
+    /// The matrix after every event.
+    pub fn current(&self) -> Result<PersonalityStateMatrix> {
+        self.replay(self.events.len())
+    }
+
+    /// Number of events recorded at or before `timestamp` (Unix seconds).
+    pub fn count_at(&self, timestamp: i64) -> usize {
+        self.events
+            .iter()
+            .take_while(|event| event.at <= timestamp)
+            .count()
+    }
+
+    /// The matrix as it was at `timestamp` (Unix seconds).
+    pub fn at(&self, timestamp: i64) -> Result<PersonalityStateMatrix> {
+        self.replay(self.count_at(timestamp))
+    }
+
+    /// Fields that differ between the matrices at `from` and `to` (Unix seconds).
+    pub fn diff(&self, from: i64, to: i64) -> Result<Vec<FieldChange>> {
+        diff_matrices(&self.at(from)?, &self.at(to)?)
+    }
+
+    /// Events that set `path` (or a field below it), with their positions. Snapshots are
+    /// included, so the first entry gives the starting value.
+    pub fn changes_to<'a>(
+        &'a self,
+        path: &'a str,
+    ) -> impl Iterator<Item = (usize, &'a StateEvent, &'a FieldChange)> + 'a {
+        self.events
+            .iter()
+            .enumerate()
+            .flat_map(move |(position, event)| {
+                event
+                    .changes
+                    .iter()
+                    .filter(move |change| {
+                        change.path.is_empty()
+                            || change.path == path
+                            || change
+                                .path
+                                .strip_prefix(path)
+                                .is_some_and(|rest| rest.starts_with('.'))
+                    })
+                    .map(move |change| (position, event, change))
+            })
+    }
+
+    /// Position of the first event of `turn`.
+    pub fn turn_start(&self, turn: &str) -> Option<usize> {
+        self.events
+            .iter()
+            .position(|event| event.turn.as_deref() == Some(turn))
+    }
+
+    /// The matrix as it was after the first `count` events, and the event that restores
+    /// it. The interaction clock is kept, so the next update does not decay the state
+    /// over the time being rolled back.
+    pub fn rollback(&self, count: usize, at: i64) -> Result<(PersonalityStateMatrix, StateEvent)> {
+        let current = self.current()?;
+        let mut target = self.replay(count)?;
+        target.last_interaction_time = current.last_interaction_time;
+        let event = StateEvent {
+            at,
+            turn: None,
+            cause: StateCause::Rollback { to: count },
+            changes: diff_matrices(&current, &target)?,
+        };
+        Ok((target, event))
+    }
+}
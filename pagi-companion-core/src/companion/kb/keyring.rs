@@ -0,0 +1,119 @@
+use std::env;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+use super::persistence::{read_with_recovery, write_with_backup};
+use crate::security::encryption::{generate_salt, MasterKey, StorageCipher};
+use crate::storage::StorageBackend;
+
+const KEYRING_VERSION: u32 = 1;
+
+/// How the master key of a data directory is obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeySource {
+    /// Argon2id over `PAGI_DATA_PASSPHRASE` with the keyring's salt.
+    Argon2id,
+    /// Raw key read from `PAGI_DATA_KEY_FILE`.
+    KeyFile,
+}
+
+/// `keyring.json` in the storage root: everything needed to re-derive and check the master
+/// key, but nothing that reveals it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Keyring {
+    version: u32,
+    source: KeySource,
+    /// Argon2 salt (hex); empty for key files.
+    #[serde(default)]
+    salt: String,
+    /// Sealed known plaintext (hex), see `MasterKey::key_check`.
+    key_check: String,
+}
+
+const KEYRING_KEY: &str = "keyring.json";
+
+/// Builds the storage cipher for the data in `storage` from the environment.
+///
+/// - `PAGI_DATA_KEY_FILE`: path to a 32-byte (raw or hex) key file
+/// - `PAGI_DATA_PASSPHRASE`: passphrase stretched with Argon2id
+///
+/// The first encrypted start records a keyring (salt + key check) so later starts with
+/// a wrong passphrase or key file fail fast instead of failing on every file. With
+/// neither variable set, data stays plaintext unless a keyring already exists.
+pub async fn load_storage_cipher(storage: &dyn StorageBackend) -> Result<StorageCipher> {
+    let key_file = env::var("PAGI_DATA_KEY_FILE")
+        .ok()
+        .filter(|v| !v.is_empty());
+    let passphrase = env::var("PAGI_DATA_PASSPHRASE")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(Zeroizing::new);
+
+    let keyring = read_with_recovery(storage, KEYRING_KEY, |data| {
+        Ok(serde_json::from_slice::<Keyring>(data)?)
+    })
+    .await
+    .map_err(|e| anyhow!("Failed to load keyring in {}: {}", storage.describe(), e))?;
+
+    let source = match (&key_file, &passphrase) {
+        (Some(_), Some(_)) => {
+            warn!("kb_encryption_both_key_sources_set_using_key_file");
+            KeySource::KeyFile
+        }
+        (Some(_), None) => KeySource::KeyFile,
+        (None, Some(_)) => KeySource::Argon2id,
+        (None, None) => {
+            if keyring.is_some() {
+                bail!(
+                    "{} is encrypted; set PAGI_DATA_PASSPHRASE or PAGI_DATA_KEY_FILE",
+                    storage.describe()
+                );
+            }
+            warn!("kb_encryption_disabled");
+            return Ok(StorageCipher::plaintext());
+        }
+    };
+
+    let salt = match &keyring {
+        Some(keyring) if keyring.version > KEYRING_VERSION => {
+            bail!("Unsupported keyring version {}", keyring.version)
+        }
+        Some(keyring) if keyring.source != source => bail!(
+            "{} was encrypted with {:?}, but {:?} is configured",
+            storage.describe(),
+            keyring.source,
+            source
+        ),
+        Some(keyring) => hex::decode(&keyring.salt)?,
+        None if source == KeySource::Argon2id => generate_salt().to_vec(),
+        None => Vec::new(),
+    };
+
+    let key = match source {
+        KeySource::KeyFile => MasterKey::from_key_file(key_file.as_deref().unwrap_or_default())?,
+        KeySource::Argon2id => {
+            MasterKey::from_passphrase(passphrase.as_deref().map_or("", |p| p.as_str()), &salt)?
+        }
+    };
+
+    match keyring {
+        Some(keyring) => key.verify_key_check(&hex::decode(&keyring.key_check)?)?,
+        None => {
+            let keyring = Keyring {
+                version: KEYRING_VERSION,
+                source,
+                salt: hex::encode(&salt),
+                key_check: hex::encode(key.key_check()?),
+            };
+            write_with_backup(storage, KEYRING_KEY, &serde_json::to_vec_pretty(&keyring)?).await?;
+            info!(source = ?source, "kb_keyring_created");
+        }
+    }
+
+    info!(source = ?source, "kb_encryption_enabled");
+    Ok(StorageCipher::new(key))
+}
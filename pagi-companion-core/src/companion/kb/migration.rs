@@ -0,0 +1,115 @@
+use anyhow::{anyhow, bail, Result};
+use tracing::info;
+
+use crate::companion::history::StateEvent;
+use crate::rag::segment::WalRecord;
+use crate::security::encryption::{is_sealed, StorageCipher};
+use crate::security::AgentIdentity;
+use crate::storage::StorageBackend;
+
+/// Outcome of `encrypt_existing_data`.
+#[derive(Debug, Clone, Default)]
+pub struct MigrationReport {
+    /// Files rewritten in encrypted form.
+    pub encrypted: Vec<String>,
+    /// Files that were already encrypted.
+    pub skipped: Vec<String>,
+}
+
+/// Encrypts every plaintext file in `storage` in place, including `.bak` generations,
+/// wrapping `AgentIdentity` private keys along the way.
+///
+/// Files are rewritten one at a time with atomic writes, so the migration can be
+/// interrupted and re-run. Run it while no companion process is using the storage.
+pub async fn encrypt_existing_data(
+    storage: &dyn StorageBackend,
+    cipher: &StorageCipher,
+) -> Result<MigrationReport> {
+    let Some(key) = cipher.master_key() else {
+        bail!("Encryption migration needs PAGI_DATA_PASSPHRASE or PAGI_DATA_KEY_FILE");
+    };
+    // The only reader that may see plaintext once a keyring exists.
+    let cipher = &cipher.clone().allowing_plaintext();
+
+    let names = storage.list().await?;
+
+    let mut report = MigrationReport::default();
+    for name in names {
+        // The keyring is never sealed.
+        if name == "keyring.json" || name == "keyring.json.bak" {
+            continue;
+        }
+        // Backups are opened with their primary's context by `read_with_recovery`.
+        let context = name.strip_suffix(".bak").unwrap_or(&name).to_string();
+        let Some(data) = storage.read(&name).await? else {
+            continue;
+        };
+
+        let sealed = if context.ends_with("_rag.wal") {
+            // WAL frames are sealed individually so appends stay cheap.
+            let sealed_only = WalRecord::decode_log_with(&data, |payload| {
+                if !is_sealed(payload) {
+                    bail!("plaintext frame");
+                }
+                cipher.open(&context, payload)
+            });
+            if matches!(sealed_only, Ok((_, valid_len)) if valid_len == data.len()) {
+                None
+            } else {
+                let (records, _) =
+                    WalRecord::decode_log_with(&data, |payload| cipher.open(&context, payload))
+                        .map_err(|e| anyhow!("Failed to read {}: {}", name, e))?;
+                let mut out = WalRecord::log_header();
+                for record in &records {
+                    out.extend(record.encode_with(|payload| cipher.seal(&context, payload))?);
+                }
+                Some(out)
+            }
+        } else if context.ends_with("_state.log") {
+            // Same framing as the WAL.
+            let sealed_only = StateEvent::decode_log_with(&data, |payload| {
+                if !is_sealed(payload) {
+                    bail!("plaintext frame");
+                }
+                cipher.open(&context, payload)
+            });
+            if matches!(sealed_only, Ok((_, valid_len)) if valid_len == data.len()) {
+                None
+            } else {
+                let (events, _) =
+                    StateEvent::decode_log_with(&data, |payload| cipher.open(&context, payload))
+                        .map_err(|e| anyhow!("Failed to read {}: {}", name, e))?;
+                let mut out = StateEvent::log_header();
+                for event in &events {
+                    out.extend(event.encode_with(|payload| cipher.seal(&context, payload))?);
+                }
+                Some(out)
+            }
+        } else if is_sealed(&data) {
+            None
+        } else if context.ends_with("_identity.json") {
+            let identity: AgentIdentity = serde_json::from_slice(&data)
+                .map_err(|e| anyhow!("Failed to parse {}: {}", name, e))?;
+            let wrapped = serde_json::to_vec_pretty(&identity.wrapped(key)?)?;
+            Some(cipher.seal(&context, &wrapped)?)
+        } else {
+            Some(cipher.seal(&context, &data)?)
+        };
+
+        match sealed {
+            Some(sealed) => {
+                storage.write(&name, &sealed).await?;
+                info!(file = name.as_str(), "kb_migration_file_encrypted");
+                report.encrypted.push(name);
+            }
+            None => report.skipped.push(name),
+        }
+    }
+
+    info!(
+        encrypted = report.encrypted.len(),
+        skipped = report.skipped.len(),
+        "kb_migration_done"
+    );
+    Ok(report)
+}
@@ -1,17 +1,34 @@
-use anyhow::{bail, Result};
+/// Master key loading for encryption at rest (`PAGI_DATA_PASSPHRASE` / `PAGI_DATA_KEY_FILE`).
+pub mod keyring;
+/// One-shot encryption of existing plaintext `companion_data`.
+pub mod migration;
+/// `.bak` generations and backup recovery on top of a `StorageBackend`.
+pub mod persistence;
+
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
-use tokio::fs;
-use tokio::io::AsyncWriteExt;
+use serde::{Deserialize, Serialize};
 use tracing::{info, warn};
 
+use crate::security::encryption::StorageCipher;
 use crate::security::AgentIdentity;
+use crate::storage::{FileSystemStorage, StorageBackend};
 
-use crate::companion::models::{
-    AttachmentStyle, EmotionalState, FlirtyStyle, LoveLanguage, PersonalityStateMatrix,
-    RelationshipStage,
+use crate::companion::conversation::ConversationHistory;
+use crate::companion::history::{
+    revert_events, StateCause, StateEvent, StateHistory, StateRecorder,
 };
-use crate::rag::embedding::EmbeddingModel;
+use crate::companion::models::PersonalityStateMatrix;
+use crate::companion::persona::{persona_preset, DEFAULT_PERSONA_PRESET};
+use crate::rag::distance::DistanceMetric;
+use crate::rag::embedding::{Embedder, HashedNgramEmbedder};
+use crate::rag::fusion::RetrievalMode;
+use crate::rag::hnsw::IndexMode;
 use crate::rag::index::VectorIndex;
+use crate::rag::metadata::{MemoryFilter, MemoryMetadata};
+use crate::rag::segment::{Segment, SegmentRecord, WalRecord};
+
+use self::persistence::{backup_key, read_with_recovery, write_with_backup};
 
 /// Trait defining the core long-term memory functions for the Agentic RAG loop.
 #[async_trait]
@@ -19,150 +36,614 @@ pub trait KnowledgeBase {
     /// Stores a piece of information, returning a unique memory ID.
     async fn store(&self, user_id: &str, content: &str) -> Result<String>;
 
-    /// Retrieves relevant context based on a natural language query.
-    async fn retrieve_context_by_query(&self, user_id: &str, query: &str, k: usize)
-        -> Result<Vec<String>>;
+    /// Stores a piece of information with explicit metadata, returning a unique memory ID.
+    async fn store_with_metadata(
+        &self,
+        user_id: &str,
+        content: &str,
+        metadata: MemoryMetadata,
+    ) -> Result<String>;
+
+    /// Retrieves the memories most relevant to a natural language query, ranked and
+    /// filtered as `options` asks.
+    async fn retrieve(
+        &self,
+        user_id: &str,
+        query: &str,
+        options: &RetrievalOptions,
+    ) -> Result<Vec<ScoredMemory>>;
+
+    /// Content of the `k` memories closest to `query` (vector ranking, no filter).
+    async fn retrieve_context_by_query(
+        &self,
+        user_id: &str,
+        query: &str,
+        k: usize,
+    ) -> Result<Vec<String>> {
+        let options = RetrievalOptions::new(k).with_mode(RetrievalMode::Vector);
+        let memories = self.retrieve(user_id, query, &options).await?;
+        Ok(memories.into_iter().map(|memory| memory.content).collect())
+    }
+
+    /// Fetches a single memory by the ID returned from `store`.
+    async fn get(&self, memory_id: &str) -> Result<Option<MemoryRecord>>;
+
+    /// Replaces a memory's content (re-embedding it). Returns false if it does not exist.
+    async fn update(&self, memory_id: &str, content: &str) -> Result<bool>;
+
+    /// Forgets a memory. Returns false if it does not exist.
+    async fn delete(&self, memory_id: &str) -> Result<bool>;
+
+    /// Lists a user's memories ordered by creation, `limit` at a time starting at `offset`.
+    async fn list(&self, user_id: &str, offset: usize, limit: usize) -> Result<MemoryPage>;
+}
+
+/// Per-call knobs for `KnowledgeBase::retrieve`.
+#[derive(Debug, Clone)]
+pub struct RetrievalOptions {
+    pub k: usize,
+    pub filter: MemoryFilter,
+    pub mode: RetrievalMode,
+}
+
+impl Default for RetrievalOptions {
+    fn default() -> Self {
+        RetrievalOptions {
+            k: 5,
+            filter: MemoryFilter::default(),
+            mode: RetrievalMode::default(),
+        }
+    }
+}
+
+impl RetrievalOptions {
+    pub fn new(k: usize) -> Self {
+        RetrievalOptions {
+            k,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_filter(mut self, filter: MemoryFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: RetrievalMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+/// A retrieved memory together with its distance to the query (lower is closer).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoredMemory {
+    pub memory_id: String,
+    pub distance: f32,
+    pub content: String,
+    pub metadata: MemoryMetadata,
+}
+
+/// A stored memory as returned by `KnowledgeBase::get` / `list`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryRecord {
+    pub memory_id: String,
+    pub content: String,
+    pub metadata: MemoryMetadata,
+}
+
+/// One page of `KnowledgeBase::list` results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryPage {
+    pub records: Vec<MemoryRecord>,
+    /// Total number of memories for the user.
+    pub total: usize,
+    /// Offset of the next page, if any.
+    pub next_offset: Option<usize>,
+}
+
+/// Formats the public memory ID handed out by `KnowledgeBase::store`.
+fn memory_id(user_id: &str, id: u64) -> String {
+    format!("mem-{}-{}", user_id, id)
+}
+
+/// Splits a `mem-{user}-{id}` memory ID back into its user ID and index id.
+fn parse_memory_id(memory_id: &str) -> Result<(&str, u64)> {
+    let parsed = memory_id
+        .strip_prefix("mem-")
+        .and_then(|rest| rest.rsplit_once('-'))
+        .and_then(|(user_id, id)| Some((user_id, id.parse::<u64>().ok()?)))
+        .filter(|(user_id, _)| !user_id.is_empty());
+
+    match parsed {
+        Some(parsed) => Ok(parsed),
+        None => bail!("Invalid memory id: {}", memory_id),
+    }
 }
 
 /// Placeholder for the structured fact store (semantic memory/state).
-pub struct SemanticKB;
+pub struct SemanticKB {
+    /// Where the matrix and identity files live (`./companion_data` by default).
+    storage: std::sync::Arc<dyn StorageBackend>,
+    /// Encryption applied to the matrix and identity files (plaintext by default).
+    cipher: StorageCipher,
+    /// Users whose state log was checked for a torn tail; also serializes appends.
+    checked_state_logs: tokio::sync::Mutex<std::collections::HashSet<String>>,
+}
 
-impl SemanticKB {
-    /// Defines the data path relative to the runtime directory.
-    const DATA_DIR: &'static str = "./companion_data";
+impl Default for SemanticKB {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+impl SemanticKB {
     pub fn new() -> Self {
-        SemanticKB
+        Self::new_with_storage(std::sync::Arc::new(FileSystemStorage::default()))
     }
 
-    fn get_file_path(&self, user_id: &str) -> String {
-        format!("{}/{}.json", Self::DATA_DIR, user_id)
+    /// Keeps this KB's files in `storage` instead of `./companion_data`.
+    pub fn new_with_storage(storage: std::sync::Arc<dyn StorageBackend>) -> Self {
+        SemanticKB {
+            storage,
+            cipher: StorageCipher::plaintext(),
+            checked_state_logs: Default::default(),
+        }
     }
 
-    fn get_identity_file_path(&self, user_id: &str) -> String {
-        format!("{}/{}_identity.json", Self::DATA_DIR, user_id)
+    /// Encrypts files written by this KB (see `load_storage_cipher`).
+    pub fn with_cipher(mut self, cipher: StorageCipher) -> Self {
+        self.cipher = cipher;
+        self
     }
 
-    fn create_default_matrix(&self, _user_id: &str) -> PersonalityStateMatrix {
-        let now = chrono::Utc::now().timestamp();
-        PersonalityStateMatrix {
-            name: "Skylar".to_string(),
-            gender: "Female".to_string(),
-            primary_role: "Flirty Girlfriend".to_string(),
+    /// Storage key of the user's matrix. Keys double as the encryption context, so
+    /// sealed data moved to another user's file (or another kind of file) fails to open.
+    fn get_file_key(&self, user_id: &str) -> String {
+        format!("{}.json", user_id)
+    }
 
-            conservatism_level: 0.2,
-            sexual_drive: 0.8,
-            intimacy_openness: 0.9,
+    fn get_identity_file_key(&self, user_id: &str) -> String {
+        format!("{}_identity.json", user_id)
+    }
 
-            attachment_style: AttachmentStyle::Secure,
-            love_language: LoveLanguage::PhysicalTouch,
-            flirty_style: FlirtyStyle::Bold,
-            current_emotional_state: EmotionalState::Happy,
-            relationship_stage: RelationshipStage::Dating,
-            current_kinks_list: vec!["praise".to_string(), "teasing".to_string()],
-            current_boundaries_list: vec!["safe word 'exit'".to_string()],
+    fn get_conversation_file_key(&self, user_id: &str) -> String {
+        format!("{}_conversation.json", user_id)
+    }
 
-            anxiety_level: 0.1,
-            avoidance_level: 0.1,
-            sexual_energy: 0.5,
-            last_interaction_time: now,
-        }
+    fn get_state_log_file_key(&self, user_id: &str) -> String {
+        format!("{}_state.log", user_id)
     }
 
     /// Loads the full structured personality and state data from a file.
     pub async fn load_matrix_by_user_id(&self, user_id: &str) -> Result<PersonalityStateMatrix> {
-        let file_path = self.get_file_path(user_id);
-        info!(user_id = user_id, file_path = file_path.as_str(), "kb_load_matrix");
+        let file_key = self.get_file_key(user_id);
+        info!(
+            user_id = user_id,
+            file_key = file_key.as_str(),
+            "kb_load_matrix"
+        );
+
+        let loaded = read_with_recovery(self.storage.as_ref(), &file_key, |data| {
+            let data = self.cipher.open(&file_key, data)?;
+            Ok(serde_json::from_slice::<PersonalityStateMatrix>(&data)?)
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to load state file {}: {}", file_key, e))?;
 
-        match fs::read(&file_path).await {
-            Ok(data) => {
-                let matrix: PersonalityStateMatrix = serde_json::from_slice(&data)?;
+        match loaded {
+            Some(matrix) => {
                 info!(user_id = user_id, "kb_state_loaded");
                 Ok(matrix)
             }
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            None => {
                 warn!(user_id = user_id, "kb_state_not_found_creating_default");
-                fs::create_dir_all(Self::DATA_DIR).await?;
 
-                let default_matrix = self.create_default_matrix(user_id);
-                self.save_matrix(user_id, &default_matrix).await?;
+                let default_matrix = persona_preset(DEFAULT_PERSONA_PRESET)?;
+                // A new log starts with a snapshot; an existing one (matrix file lost)
+                // needs one so it still projects to the saved matrix.
+                let log_key = self.get_state_log_file_key(user_id);
+                let events = match self.storage.size(&log_key).await?.unwrap_or(0) {
+                    0 => Vec::new(),
+                    _ => vec![StateEvent::snapshot(
+                        &default_matrix,
+                        chrono::Utc::now().timestamp(),
+                    )?],
+                };
+                self.save_matrix_with_events(user_id, &default_matrix, &events)
+                    .await?;
                 Ok(default_matrix)
             }
-            Err(e) => bail!("Failed to read state file {}: {}", file_path, e),
         }
     }
 
-    /// Saves the updated matrix and state data to a file.
+    /// Saves `matrix` as a direct edit, logging what changed since the stored matrix
+    /// (`StateCause::UserEdit`) so the state log still projects to it.
     pub async fn save_matrix(&self, user_id: &str, matrix: &PersonalityStateMatrix) -> Result<()> {
-        fs::create_dir_all(Self::DATA_DIR).await?;
+        let stored = self.load_matrix_by_user_id(user_id).await?;
+        let mut recorder = StateRecorder::new(&stored, chrono::Utc::now().timestamp())?;
+        recorder.record(matrix, StateCause::UserEdit)?;
+        self.save_matrix_with_events(user_id, matrix, &recorder.finish())
+            .await
+    }
 
-        let file_path = self.get_file_path(user_id);
-        info!(user_id = user_id, file_path = file_path.as_str(), "kb_save_matrix");
+    /// Writes the matrix file. Only through `save_matrix_with_events`, so the matrix
+    /// stays the projection of its state log.
+    async fn write_matrix(&self, user_id: &str, matrix: &PersonalityStateMatrix) -> Result<()> {
+        let file_key = self.get_file_key(user_id);
+        info!(
+            user_id = user_id,
+            file_key = file_key.as_str(),
+            "kb_save_matrix"
+        );
 
         let data = serde_json::to_vec_pretty(matrix)?;
-        let mut file = fs::File::create(&file_path).await?;
-        file.write_all(&data).await?;
+        let data = self.cipher.seal(&file_key, &data)?;
+        write_with_backup(self.storage.as_ref(), &file_key, &data).await
+    }
+
+    /// Appends `events` to the user's state log and saves `matrix`, the state they led
+    /// to. A new log starts with a snapshot of the matrix before `events`.
+    pub async fn save_matrix_with_events(
+        &self,
+        user_id: &str,
+        matrix: &PersonalityStateMatrix,
+        events: &[StateEvent],
+    ) -> Result<()> {
+        self.append_state_events(user_id, matrix, events).await?;
+        self.write_matrix(user_id, matrix).await
+    }
+
+    async fn append_state_events(
+        &self,
+        user_id: &str,
+        matrix: &PersonalityStateMatrix,
+        events: &[StateEvent],
+    ) -> Result<()> {
+        let file_key = self.get_state_log_file_key(user_id);
+        let mut checked = self.checked_state_logs.lock().await;
+        let mut size = self.storage.size(&file_key).await?.unwrap_or(0);
+        if size > 0 && !checked.contains(user_id) {
+            // Frames appended after a torn one (a crash mid-append) could never be read.
+            let data = self.storage.read(&file_key).await?.unwrap_or_default();
+            let valid_len = if data.len() < StateEvent::log_header().len() {
+                0
+            } else {
+                StateEvent::decode_log_with(&data, |payload| self.cipher.open(&file_key, payload))
+                    .map_err(|e| anyhow!("Failed to read state log {}: {}", file_key, e))?
+                    .1
+            };
+            if valid_len < data.len() {
+                warn!(
+                    user_id = user_id,
+                    discarded_bytes = data.len() - valid_len,
+                    "kb_state_log_torn_tail"
+                );
+                self.storage.write(&file_key, &data[..valid_len]).await?;
+                size = valid_len as u64;
+            }
+        }
+        checked.insert(user_id.to_string());
+
+        let seal = |payload: &[u8]| self.cipher.seal(&file_key, payload);
+        let mut bytes = Vec::new();
+        if size == 0 {
+            let at = events
+                .first()
+                .map_or_else(|| chrono::Utc::now().timestamp(), |event| event.at);
+            bytes.extend(StateEvent::log_header());
+            bytes.extend(
+                StateEvent::snapshot(&revert_events(matrix, events)?, at)?.encode_with(seal)?,
+            );
+        }
+        for event in events {
+            bytes.extend(event.encode_with(seal)?);
+        }
+        self.storage.append(&file_key, &bytes).await?;
+        info!(
+            user_id = user_id,
+            events = events.len(),
+            "kb_state_events_appended"
+        );
         Ok(())
     }
 
+    /// Loads the user's state log (empty until the first recorded update).
+    pub async fn load_state_history(&self, user_id: &str) -> Result<StateHistory> {
+        let file_key = self.get_state_log_file_key(user_id);
+        let Some(data) = self.storage.read(&file_key).await? else {
+            return Ok(StateHistory::default());
+        };
+        let (events, valid_len) =
+            StateEvent::decode_log_with(&data, |payload| self.cipher.open(&file_key, payload))
+                .map_err(|e| anyhow!("Failed to read state log {}: {}", file_key, e))?;
+        if valid_len < data.len() {
+            warn!(
+                user_id = user_id,
+                discarded_bytes = data.len() - valid_len,
+                "kb_state_log_torn_tail"
+            );
+        }
+        Ok(StateHistory::new(events))
+    }
+
+    /// Restores the user's matrix to its state after the first `count` events of the
+    /// state log. The rollback is logged like any other change, so it can be undone.
+    pub async fn rollback_state(
+        &self,
+        user_id: &str,
+        count: usize,
+    ) -> Result<PersonalityStateMatrix> {
+        let history = self.load_state_history(user_id).await?;
+        let (matrix, event) = history.rollback(count, chrono::Utc::now().timestamp())?;
+        info!(
+            user_id = user_id,
+            to = count,
+            changes = event.changes.len(),
+            "kb_state_rolled_back"
+        );
+        self.save_matrix_with_events(user_id, &matrix, &[event])
+            .await?;
+        Ok(matrix)
+    }
+
+    /// Removes `boundary` (case-insensitively) from the user's matrix, logging the change.
+    /// Returns whether the boundary was present.
+    pub async fn remove_boundary(&self, user_id: &str, boundary: &str) -> Result<bool> {
+        let mut matrix = self.load_matrix_by_user_id(user_id).await?;
+        let mut recorder = StateRecorder::new(&matrix, chrono::Utc::now().timestamp())?;
+        let boundary = boundary.trim();
+        let before = matrix.current_boundaries_list.len();
+        matrix
+            .current_boundaries_list
+            .retain(|existing| !existing.eq_ignore_ascii_case(boundary));
+        if matrix.current_boundaries_list.len() == before {
+            return Ok(false);
+        }
+        recorder.record(&matrix, StateCause::UserEdit)?;
+        self.save_matrix_with_events(user_id, &matrix, &recorder.finish())
+            .await?;
+        info!(
+            user_id = user_id,
+            boundary = boundary,
+            "kb_boundary_removed"
+        );
+        Ok(true)
+    }
+
+    /// Loads the user's recent conversation turns (empty for a new user).
+    pub async fn load_conversation(&self, user_id: &str) -> Result<ConversationHistory> {
+        let file_key = self.get_conversation_file_key(user_id);
+
+        let loaded = read_with_recovery(self.storage.as_ref(), &file_key, |data| {
+            let data = self.cipher.open(&file_key, data)?;
+            Ok(serde_json::from_slice::<ConversationHistory>(&data)?)
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to load conversation file {}: {}", file_key, e))?;
+
+        Ok(loaded.unwrap_or_default())
+    }
+
+    /// Saves the user's recent conversation turns.
+    pub async fn save_conversation(
+        &self,
+        user_id: &str,
+        history: &ConversationHistory,
+    ) -> Result<()> {
+        let file_key = self.get_conversation_file_key(user_id);
+        info!(
+            user_id = user_id,
+            turns = history.turns.len(),
+            "kb_save_conversation"
+        );
+
+        let data = serde_json::to_vec_pretty(history)?;
+        let data = self.cipher.seal(&file_key, &data)?;
+        write_with_backup(self.storage.as_ref(), &file_key, &data).await
+    }
+
     /// Loads the `AgentIdentity` from storage, generating a new one if not found.
     pub async fn load_agent_identity(&self, user_id: &str) -> Result<AgentIdentity> {
-        fs::create_dir_all(Self::DATA_DIR).await?;
+        let file_key = self.get_identity_file_key(user_id);
+        info!(
+            user_id = user_id,
+            file_key = file_key.as_str(),
+            "kb_load_agent_identity"
+        );
 
-        let file_path = self.get_identity_file_path(user_id);
-        info!(user_id = user_id, file_path = file_path.as_str(), "kb_load_agent_identity");
+        let loaded = read_with_recovery(self.storage.as_ref(), &file_key, |data| {
+            let data = self.cipher.open(&file_key, data)?;
+            let mut identity: AgentIdentity = serde_json::from_slice(&data)?;
+            let was_wrapped = identity.is_wrapped();
+            if was_wrapped {
+                let Some(key) = self.cipher.master_key() else {
+                    bail!("Agent private key is wrapped but no storage key is configured");
+                };
+                identity.unwrap_private_key(key)?;
+            }
+            Ok((identity, was_wrapped))
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to load identity file {}: {}", file_key, e))?;
 
-        match fs::read(&file_path).await {
-            Ok(data) => {
-                let identity: AgentIdentity = serde_json::from_slice(&data)?;
+        match loaded {
+            Some((identity, was_wrapped)) => {
+                if self.cipher.is_encrypted() && !was_wrapped {
+                    // Legacy raw key inside an encrypted file: persist it wrapped.
+                    warn!(user_id = user_id, "kb_identity_rewrapping_raw_key");
+                    self.save_agent_identity(user_id, &identity).await?;
+                }
                 info!(user_id = user_id, "kb_identity_loaded");
                 Ok(identity)
             }
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            None => {
                 warn!(user_id = user_id, "kb_identity_not_found_generating");
                 let new_identity = AgentIdentity::new_with_generation(format!("PAGI-{user_id}"));
                 self.save_agent_identity(user_id, &new_identity).await?;
                 Ok(new_identity)
             }
-            Err(e) => bail!("Failed to read identity file {}: {}", file_path, e),
         }
     }
 
     /// Saves the `AgentIdentity` to storage.
     pub async fn save_agent_identity(&self, user_id: &str, identity: &AgentIdentity) -> Result<()> {
-        fs::create_dir_all(Self::DATA_DIR).await?;
+        let file_key = self.get_identity_file_key(user_id);
+        info!(
+            user_id = user_id,
+            file_key = file_key.as_str(),
+            "kb_save_agent_identity"
+        );
 
-        let file_path = self.get_identity_file_path(user_id);
-        info!(user_id = user_id, file_path = file_path.as_str(), "kb_save_agent_identity");
-
-        let data = serde_json::to_vec_pretty(identity)?;
-        let mut file = fs::File::create(&file_path).await?;
-        file.write_all(&data).await?;
-        Ok(())
+        // Never persist the raw private key when a storage key is available.
+        let data = match self.cipher.master_key() {
+            Some(key) => serde_json::to_vec_pretty(&identity.wrapped(key)?)?,
+            None => serde_json::to_vec_pretty(identity)?,
+        };
+        let data = self.cipher.seal(&file_key, &data)?;
+        write_with_backup(self.storage.as_ref(), &file_key, &data).await
     }
 }
 
 /// Functional episodic memory store (RAG) backed by an in-memory vector index.
 pub struct EpisodicKB {
-    embedding_model: EmbeddingModel,
+    embedder: std::sync::Arc<dyn Embedder>,
+    /// Search mode applied to every per-user index (exact scan or HNSW).
+    index_mode: IndexMode,
+    /// Distance metric applied to every per-user index.
+    distance_metric: DistanceMetric,
+    /// Where segments, WALs and HNSW sidecars live (`./companion_data` by default).
+    storage: std::sync::Arc<dyn StorageBackend>,
+    /// Encryption applied to segments, WAL records and HNSW sidecars.
+    cipher: StorageCipher,
     /// Separate index per user_id (bare-metal isolation).
     per_user_index: tokio::sync::RwLock<std::collections::HashMap<String, VectorIndex>>,
 }
 
+impl Default for EpisodicKB {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl EpisodicKB {
-    const DATA_DIR: &'static str = "./companion_data";
+    /// WAL size that triggers rewriting the segment (~2.5k memories at 384 dims).
+    const WAL_COMPACTION_BYTES: u64 = 4 * 1024 * 1024;
 
     pub fn new() -> Self {
+        Self::new_with_storage(std::sync::Arc::new(FileSystemStorage::default()))
+    }
+
+    /// Keeps this KB's files in `storage` instead of `./companion_data`.
+    pub fn new_with_storage(storage: std::sync::Arc<dyn StorageBackend>) -> Self {
         EpisodicKB {
-            embedding_model: EmbeddingModel::new(),
+            embedder: std::sync::Arc::new(HashedNgramEmbedder::new()),
+            index_mode: IndexMode::Exact,
+            distance_metric: DistanceMetric::L2,
+            storage,
+            cipher: StorageCipher::plaintext(),
             per_user_index: tokio::sync::RwLock::new(std::collections::HashMap::new()),
         }
     }
 
-    fn rag_file_path(&self, user_id: &str) -> String {
-        format!("{}/{}_rag_index.json", Self::DATA_DIR, user_id)
+    /// Replaces the embedder (default: `HashedNgramEmbedder`).
+    ///
+    /// Indices written by a different embedder are re-embedded when first loaded.
+    pub fn with_embedder(mut self, embedder: std::sync::Arc<dyn Embedder>) -> Self {
+        self.embedder = embedder;
+        self
+    }
+
+    /// Selects the search mode (e.g. `IndexMode::Hnsw`) for indices loaded by this KB.
+    pub fn with_index_mode(mut self, index_mode: IndexMode) -> Self {
+        self.index_mode = index_mode;
+        self
+    }
+
+    /// Selects the distance metric for indices loaded by this KB.
+    pub fn with_distance_metric(mut self, distance_metric: DistanceMetric) -> Self {
+        self.distance_metric = distance_metric;
+        self
+    }
+
+    /// Encrypts files written by this KB (see `load_storage_cipher`).
+    pub fn with_cipher(mut self, cipher: StorageCipher) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    fn new_index(&self) -> VectorIndex {
+        let mut index = VectorIndex::with_config(self.distance_metric, self.index_mode);
+        index.set_embedder_id(self.embedder.id());
+        index
+    }
+
+    /// Embeds `text`, checking the vector against the embedder's and the index's width.
+    async fn embed_checked(&self, user_id: &str, text: &str) -> Result<Vec<f32>> {
+        let embedding = self.embedder.embed_text(text).await?;
+        if embedding.len() != self.embedder.dimension() {
+            bail!(
+                "Embedder {} produced {} dims, expected {}",
+                self.embedder.id(),
+                embedding.len(),
+                self.embedder.dimension()
+            );
+        }
+
+        let guard = self.per_user_index.read().await;
+        if let Some(dim) = guard.get(user_id).and_then(VectorIndex::dimension) {
+            if dim != embedding.len() {
+                bail!(
+                    "Embedding dimension {} does not match index dimension {} for user {}",
+                    embedding.len(),
+                    dim,
+                    user_id
+                );
+            }
+        }
+        Ok(embedding)
+    }
+
+    /// Re-embeds every live memory with the current embedder.
+    async fn reembed_index(&self, user_id: &str, index: &mut VectorIndex) -> Result<()> {
+        if index.is_empty() {
+            index.replace_embeddings(vec![], self.embedder.id());
+            return Ok(());
+        }
+        warn!(
+            user_id = user_id,
+            from = index.embedder_id().unwrap_or("unknown"),
+            to = self.embedder.id().as_str(),
+            "kb_rag_index_reembedding"
+        );
+
+        let (ids, contents): (Vec<u64>, Vec<String>) = index.live_contents().into_iter().unzip();
+        let embeddings = self.embedder.embed_batch(&contents).await?;
+        index.replace_embeddings(
+            ids.into_iter().zip(embeddings).collect(),
+            self.embedder.id(),
+        );
+        Ok(())
+    }
+
+    fn segment_file_key(&self, user_id: &str) -> String {
+        format!("{}_rag.seg", user_id)
+    }
+
+    fn wal_file_key(&self, user_id: &str) -> String {
+        format!("{}_rag.wal", user_id)
+    }
+
+    /// Pre-segment JSON index, migrated on first load.
+    fn legacy_json_file_key(&self, user_id: &str) -> String {
+        format!("{}_rag_index.json", user_id)
+    }
+
+    fn hnsw_file_key(&self, user_id: &str) -> String {
+        format!("{}_rag_hnsw.json", user_id)
     }
 
+    /// Loads the user's segment (or migrates the legacy JSON index) and replays the WAL.
     async fn ensure_index_loaded(&self, user_id: &str) -> Result<()> {
         {
             // Fast-path: already loaded.
@@ -172,46 +653,237 @@ impl EpisodicKB {
             }
         }
 
-        tokio::fs::create_dir_all(Self::DATA_DIR).await?;
-        let file_path = self.rag_file_path(user_id);
+        let segment_key = self.segment_file_key(user_id);
 
         let mut guard = self.per_user_index.write().await;
         if guard.contains_key(user_id) {
             return Ok(());
         }
 
-        match tokio::fs::read(&file_path).await {
-            Ok(data) => {
-                let idx = VectorIndex::from_json_bytes(&data)?;
-                guard.insert(user_id.to_string(), idx);
-                info!(user_id = user_id, file_path = file_path.as_str(), "kb_rag_index_loaded");
+        let mut needs_compaction = false;
+        let storage = self.storage.as_ref();
+        let segment = read_with_recovery(storage, &segment_key, |data| {
+            Segment::decode(&self.cipher.open(&segment_key, data)?)
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to load RAG segment {}: {}", segment_key, e))?;
+        let legacy_key = self.legacy_json_file_key(user_id);
+        let mut idx = match segment {
+            Some(segment) => {
+                info!(
+                    user_id = user_id,
+                    file_key = segment_key.as_str(),
+                    "kb_rag_index_loaded"
+                );
+                VectorIndex::from_segment(segment)
             }
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                guard.insert(user_id.to_string(), VectorIndex::new());
-                info!(user_id = user_id, file_path = file_path.as_str(), "kb_rag_index_created");
+            None => match read_with_recovery(storage, &legacy_key, |data| {
+                VectorIndex::from_json_bytes(&self.cipher.open(&legacy_key, data)?)
+            })
+            .await?
+            {
+                Some(idx) => {
+                    info!(
+                        user_id = user_id,
+                        file_key = legacy_key.as_str(),
+                        "kb_rag_index_migrating"
+                    );
+                    needs_compaction = true;
+                    idx
+                }
+                None => {
+                    info!(
+                        user_id = user_id,
+                        file_key = segment_key.as_str(),
+                        "kb_rag_index_created"
+                    );
+                    // Nothing records which embedder wrote a WAL without a segment, so
+                    // its vectors are re-embedded and the segment written right away.
+                    VectorIndex::with_config(self.distance_metric, self.index_mode)
+                }
+            },
+        };
+
+        let same_embedder = idx.embedder_id() == Some(self.embedder.id().as_str());
+        if same_embedder {
+            if let IndexMode::Hnsw(_) = self.index_mode {
+                // The sidecar matches the segment; replayed WAL inserts extend it.
+                let hnsw_key = self.hnsw_file_key(user_id);
+                if let Ok(Some(graph_bytes)) = storage.read(&hnsw_key).await {
+                    match self.cipher.open(&hnsw_key, &graph_bytes) {
+                        Ok(graph_bytes) => idx.restore_hnsw_json_bytes(&graph_bytes),
+                        Err(e) => {
+                            warn!(user_id = user_id, error = %e, "kb_rag_hnsw_sidecar_unreadable")
+                        }
+                    }
+                }
             }
-            Err(e) => bail!("Failed to read RAG index file {}: {}", file_path, e),
         }
 
-        Ok(())
+        let wal_key = self.wal_file_key(user_id);
+        let wal = storage
+            .read(&wal_key)
+            .await
+            .map_err(|e| anyhow!("Failed to read RAG log {}: {}", wal_key, e))?;
+        if let Some(data) = wal {
+            let (records, valid_len) =
+                WalRecord::decode_log_with(&data, |payload| self.cipher.open(&wal_key, payload))
+                    .map_err(|e| anyhow!("Failed to read RAG log {}: {}", wal_key, e))?;
+            if valid_len < data.len() {
+                warn!(
+                    user_id = user_id,
+                    discarded_bytes = data.len() - valid_len,
+                    "kb_rag_wal_torn_tail"
+                );
+                needs_compaction = true;
+            }
+            info!(
+                user_id = user_id,
+                records = records.len(),
+                "kb_rag_wal_replayed"
+            );
+            for record in records {
+                idx.apply_wal(record);
+            }
+        }
+
+        if !same_embedder {
+            // Vectors from another embedder live in a different space; rebuild them.
+            // Embedding may call out over HTTP, so other users' indices stay usable
+            // meanwhile. A concurrent load of the same user may re-embed too; the first
+            // to finish is kept.
+            drop(guard);
+            self.reembed_index(user_id, &mut idx).await?;
+            guard = self.per_user_index.write().await;
+            if guard.contains_key(user_id) {
+                return Ok(());
+            }
+            needs_compaction = true;
+        }
+        idx.set_metric(self.distance_metric);
+        idx.set_mode(self.index_mode);
+
+        if needs_compaction {
+            self.compact_storage(user_id, &mut idx).await?;
+        }
+        guard.insert(user_id.to_string(), idx);
+        drop(guard);
+        self.rebuild_graph(user_id).await
     }
 
-    async fn save_index(&self, user_id: &str) -> Result<()> {
-        tokio::fs::create_dir_all(Self::DATA_DIR).await?;
-        let file_path = self.rag_file_path(user_id);
+    /// Rebuilds the HNSW graph a compaction dropped, without holding the index lock
+    /// while the graph is built. Searches scan exactly until the graph is installed.
+    ///
+    /// The sidecar is written only while the segment on disk still has the items the
+    /// graph was built from.
+    async fn rebuild_graph(&self, user_id: &str) -> Result<()> {
+        let Some(build) = self
+            .per_user_index
+            .read()
+            .await
+            .get(user_id)
+            .and_then(VectorIndex::pending_graph_build)
+        else {
+            return Ok(());
+        };
 
-        // Clone the index to avoid holding a lock during serialization + IO.
-        let idx = {
-            let guard = self.per_user_index.read().await;
-            guard
-                .get(user_id)
-                .cloned()
-                .unwrap_or_else(VectorIndex::new)
+        let (build, graph, graph_bytes) = tokio::task::spawn_blocking(move || {
+            let graph = build.run();
+            let graph_bytes = graph.to_json_bytes();
+            (build, graph, graph_bytes)
+        })
+        .await
+        .map_err(|e| anyhow!("HNSW rebuild for user {} failed: {}", user_id, e))?;
+        let built_len = graph.len();
+
+        let mut guard = self.per_user_index.write().await;
+        let Some(index) = guard.get_mut(user_id) else {
+            return Ok(());
         };
+        if !index.install_graph(&build, graph) {
+            info!(user_id = user_id, "kb_rag_hnsw_rebuild_discarded");
+            return Ok(());
+        }
+        if index.segment_len() == Some(built_len) {
+            let hnsw_key = self.hnsw_file_key(user_id);
+            let graph_bytes = self.cipher.seal(&hnsw_key, &graph_bytes?)?;
+            self.storage.write(&hnsw_key, &graph_bytes).await?;
+        }
+        Ok(())
+    }
+
+    /// Appends `record` to the user's WAL, compacting once the log grows past
+    /// `WAL_COMPACTION_BYTES`.
+    ///
+    /// Callers hold the index write lock, which serializes appends with compaction.
+    async fn log_change(
+        &self,
+        user_id: &str,
+        index: &mut VectorIndex,
+        record: WalRecord,
+    ) -> Result<()> {
+        let wal_key = self.wal_file_key(user_id);
 
-        let bytes = idx.to_json_bytes()?;
-        tokio::fs::write(&file_path, bytes).await?;
-        info!(user_id = user_id, file_path = file_path.as_str(), "kb_rag_index_saved");
+        let mut bytes = Vec::new();
+        if self.storage.size(&wal_key).await?.unwrap_or(0) == 0 {
+            bytes.extend(WalRecord::log_header());
+        }
+        bytes.extend(record.encode_with(|payload| self.cipher.seal(&wal_key, payload))?);
+        let wal_len = self.storage.append(&wal_key, &bytes).await?;
+
+        if wal_len >= Self::WAL_COMPACTION_BYTES {
+            self.compact_storage(user_id, index).await?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the segment (and HNSW sidecar) from `index` and empties the WAL. The
+    /// previous segment is kept as a backup unless deleted memories were dropped.
+    ///
+    /// Callers run `rebuild_graph` once they release the index lock.
+    async fn compact_storage(&self, user_id: &str, index: &mut VectorIndex) -> Result<()> {
+        let storage = self.storage.as_ref();
+        let segment_key = self.segment_file_key(user_id);
+
+        // Segment positions must match HNSW node ids, so drop tombstones first.
+        let forgets = index.tombstones() > 0;
+        index.compact();
+        let bytes = index.to_segment().encode()?;
+        let bytes = self.cipher.seal(&segment_key, &bytes)?;
+        if forgets {
+            // The previous generation still holds the deleted memories; keep no copy.
+            storage.write(&segment_key, &bytes).await?;
+            storage.remove(&backup_key(&segment_key)).await?;
+        } else {
+            write_with_backup(storage, &segment_key, &bytes).await?;
+        }
+        index.mark_segment_written();
+
+        // The HNSW graph lives in a sidecar next to the segment. Dropping tombstones
+        // also drops the graph, so the sidecar is removed until `rebuild_graph` writes
+        // a new one; a stale graph is never picked up later.
+        let hnsw_key = self.hnsw_file_key(user_id);
+        match index.hnsw_to_json_bytes()? {
+            Some(graph_bytes) => {
+                let graph_bytes = self.cipher.seal(&hnsw_key, &graph_bytes)?;
+                storage.write(&hnsw_key, &graph_bytes).await?
+            }
+            None => storage.remove(&hnsw_key).await?,
+        }
+
+        // Replaying the old log over the new segment is harmless, so a crash before
+        // this point loses nothing.
+        storage
+            .write(&self.wal_file_key(user_id), &WalRecord::log_header())
+            .await?;
+        storage.remove(&self.legacy_json_file_key(user_id)).await?;
+
+        info!(
+            user_id = user_id,
+            file_key = segment_key.as_str(),
+            bytes = bytes.len(),
+            "kb_rag_index_compacted"
+        );
         Ok(())
     }
 }
@@ -219,44 +891,360 @@ impl EpisodicKB {
 #[async_trait]
 impl KnowledgeBase for EpisodicKB {
     async fn store(&self, user_id: &str, content: &str) -> Result<String> {
-        info!(user_id = user_id, "kb_store_episodic_memory_rag");
+        self.store_with_metadata(user_id, content, MemoryMetadata::default())
+            .await
+    }
+
+    async fn store_with_metadata(
+        &self,
+        user_id: &str,
+        content: &str,
+        metadata: MemoryMetadata,
+    ) -> Result<String> {
+        info!(user_id = user_id, source = ?metadata.source, "kb_store_episodic_memory_rag");
 
         self.ensure_index_loaded(user_id).await?;
 
-        let embedding = self.embedding_model.embed_text(content)?;
+        let embedding = self.embed_checked(user_id, content).await?;
         let mut guard = self.per_user_index.write().await;
         let index = guard
             .entry(user_id.to_string())
-            .or_insert_with(VectorIndex::new);
-        let id = index.add(content.to_string(), embedding);
+            .or_insert_with(|| self.new_index());
+        let id = index.add_with_metadata(content.to_string(), embedding.clone(), metadata.clone());
 
-        // Persist index after each write (research-friendly durability).
+        // Persist each write by appending it to the WAL (research-friendly durability).
+        let record = WalRecord::Add(SegmentRecord {
+            id,
+            embedding,
+            content: content.to_string(),
+            metadata,
+        });
+        self.log_change(user_id, index, record).await?;
         drop(guard);
-        self.save_index(user_id).await?;
+        self.rebuild_graph(user_id).await?;
 
-        Ok(format!("mem-{}-{}", user_id, id))
+        Ok(memory_id(user_id, id))
     }
 
-    async fn retrieve_context_by_query(
+    async fn retrieve(
         &self,
         user_id: &str,
         query: &str,
-        k: usize,
-    ) -> Result<Vec<String>> {
-        info!(user_id = user_id, k = k, query = query, "kb_retrieve_context_by_query_rag");
+        options: &RetrievalOptions,
+    ) -> Result<Vec<ScoredMemory>> {
+        info!(
+            user_id = user_id,
+            k = options.k,
+            query = query,
+            filter = ?options.filter,
+            mode = ?options.mode,
+            "kb_retrieve_rag"
+        );
 
         self.ensure_index_loaded(user_id).await?;
 
-        let query_vector = self.embedding_model.embed_text(query)?;
+        let query_vector = self.embed_checked(user_id, query).await?;
 
         let guard = self.per_user_index.read().await;
         let Some(index) = guard.get(user_id) else {
             return Ok(vec![]);
         };
 
-        let context = index.search(&query_vector, k);
-        info!(user_id = user_id, retrieved = context.len(), "kb_retrieve_context_done");
-        Ok(context)
+        let scored: Vec<ScoredMemory> = index
+            .search_hybrid(
+                &query_vector,
+                query,
+                options.k,
+                &options.filter,
+                options.mode,
+            )
+            .into_iter()
+            .map(|(id, distance, content)| ScoredMemory {
+                memory_id: memory_id(user_id, id),
+                distance,
+                content,
+                metadata: index
+                    .metadata(id)
+                    .cloned()
+                    .unwrap_or_else(MemoryMetadata::legacy),
+            })
+            .collect();
+        info!(
+            user_id = user_id,
+            retrieved = scored.len(),
+            "kb_retrieve_context_done"
+        );
+        Ok(scored)
+    }
+
+    async fn get(&self, memory_id: &str) -> Result<Option<MemoryRecord>> {
+        let (user_id, id) = parse_memory_id(memory_id)?;
+        self.ensure_index_loaded(user_id).await?;
+
+        let guard = self.per_user_index.read().await;
+        let record =
+            guard
+                .get(user_id)
+                .and_then(|index| index.get(id))
+                .map(|(content, metadata)| MemoryRecord {
+                    memory_id: memory_id.to_string(),
+                    content: content.to_string(),
+                    metadata: metadata.clone(),
+                });
+        Ok(record)
+    }
+
+    async fn update(&self, memory_id: &str, content: &str) -> Result<bool> {
+        let (user_id, id) = parse_memory_id(memory_id)?;
+        info!(
+            user_id = user_id,
+            memory_id = memory_id,
+            "kb_update_episodic_memory_rag"
+        );
+
+        self.ensure_index_loaded(user_id).await?;
+
+        let embedding = self.embed_checked(user_id, content).await?;
+        let mut guard = self.per_user_index.write().await;
+        let Some(index) = guard.get_mut(user_id) else {
+            return Ok(false);
+        };
+        if !index.update(id, content.to_string(), embedding.clone()) {
+            return Ok(false);
+        }
+
+        let record = WalRecord::Update {
+            id,
+            content: content.to_string(),
+            embedding,
+        };
+        self.log_change(user_id, index, record).await?;
+        drop(guard);
+        self.rebuild_graph(user_id).await?;
+        Ok(true)
+    }
+
+    async fn delete(&self, memory_id: &str) -> Result<bool> {
+        let (user_id, id) = parse_memory_id(memory_id)?;
+        info!(
+            user_id = user_id,
+            memory_id = memory_id,
+            "kb_delete_episodic_memory_rag"
+        );
+
+        self.ensure_index_loaded(user_id).await?;
+
+        let mut guard = self.per_user_index.write().await;
+        let Some(index) = guard.get_mut(user_id) else {
+            return Ok(false);
+        };
+        if !index.delete(id) {
+            return Ok(false);
+        }
+
+        self.log_change(user_id, index, WalRecord::Delete { id })
+            .await?;
+        drop(guard);
+        self.rebuild_graph(user_id).await?;
+        Ok(true)
+    }
+
+    async fn list(&self, user_id: &str, offset: usize, limit: usize) -> Result<MemoryPage> {
+        self.ensure_index_loaded(user_id).await?;
+
+        let guard = self.per_user_index.read().await;
+        let Some(index) = guard.get(user_id) else {
+            return Ok(MemoryPage {
+                records: vec![],
+                total: 0,
+                next_offset: None,
+            });
+        };
+
+        let records: Vec<MemoryRecord> = index
+            .list(offset, limit)
+            .into_iter()
+            .map(|(id, content, metadata)| MemoryRecord {
+                memory_id: memory_id(user_id, id),
+                content: content.to_string(),
+                metadata: metadata.clone(),
+            })
+            .collect();
+
+        let total = index.len();
+        let end = offset + records.len();
+        Ok(MemoryPage {
+            records,
+            total,
+            next_offset: (end < total).then_some(end),
+        })
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    const USER: &str = "alice";
+
+    async fn compact(kb: &EpisodicKB) -> Result<()> {
+        let mut guard = kb.per_user_index.write().await;
+        let index = guard.get_mut(USER).expect("index loaded");
+        kb.compact_storage(USER, index).await
+    }
+
+    async fn stored_anywhere(storage: &MemoryStorage, needle: &str) -> Result<Vec<String>> {
+        let mut found = Vec::new();
+        for key in storage.list().await? {
+            let data = storage.read(&key).await?.unwrap_or_default();
+            if data.windows(needle.len()).any(|w| w == needle.as_bytes()) {
+                found.push(key);
+            }
+        }
+        Ok(found)
+    }
+
+    #[tokio::test]
+    async fn deleted_memory_is_forgotten() -> Result<()> {
+        let storage = Arc::new(MemoryStorage::new());
+        let kb = EpisodicKB::new_with_storage(storage.clone());
+        kb.store(USER, "I like green tea").await?;
+        let secret = kb.store(USER, "My old address is 12 Elm Street").await?;
+        // Two generations on disk, both holding the memory.
+        compact(&kb).await?;
+        kb.store(USER, "I walk every morning").await?;
+        compact(&kb).await?;
+
+        assert!(kb.delete(&secret).await?);
+
+        assert!(kb.get(&secret).await?.is_none());
+        let page = kb.list(USER, 0, 10).await?;
+        assert_eq!(page.total, 2);
+        assert!(page.records.iter().all(|r| r.memory_id != secret));
+        let retrieved = kb
+            .retrieve_context_by_query(USER, "My old address is 12 Elm Street", 10)
+            .await?;
+        assert_eq!(retrieved.len(), 2);
+        assert!(!retrieved.iter().any(|content| content.contains("Elm")));
+
+        compact(&kb).await?;
+        assert_eq!(
+            stored_anywhere(&storage, "Elm Street").await?,
+            Vec::<String>::new()
+        );
+        assert!(storage.read("alice_rag.seg.bak").await?.is_none());
+
+        let reloaded = EpisodicKB::new_with_storage(storage.clone());
+        assert!(reloaded.get(&secret).await?.is_none());
+        assert_eq!(reloaded.list(USER, 0, 10).await?.total, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compaction_without_deletes_keeps_a_backup() -> Result<()> {
+        let storage = Arc::new(MemoryStorage::new());
+        let kb = EpisodicKB::new_with_storage(storage.clone());
+        kb.store(USER, "I like green tea").await?;
+        compact(&kb).await?;
+        kb.store(USER, "I walk every morning").await?;
+        compact(&kb).await?;
+
+        assert!(storage.read("alice_rag.seg.bak").await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_re_embeds_the_memory() -> Result<()> {
+        let storage = Arc::new(MemoryStorage::new());
+        let kb = EpisodicKB::new_with_storage(storage.clone());
+        let id = kb.store(USER, "I love hiking in the mountains").await?;
+        kb.store(USER, "My sister lives in Lisbon").await?;
+
+        assert!(kb.update(&id, "I collect vintage stamps").await?);
+
+        let options = RetrievalOptions::new(1).with_mode(RetrievalMode::Vector);
+        for kb in [kb, EpisodicKB::new_with_storage(storage.clone())] {
+            let top = kb
+                .retrieve(USER, "I collect vintage stamps", &options)
+                .await?;
+            assert_eq!(top[0].memory_id, id);
+            assert_eq!(top[0].content, "I collect vintage stamps");
+            assert!(top[0].distance < 1e-4, "{}", top[0].distance);
+            let old = kb
+                .retrieve(USER, "I love hiking in the mountains", &options)
+                .await?;
+            assert!(old[0].memory_id != id || old[0].distance > 0.1);
+        }
+        Ok(())
+    }
+
+    /// Maps words onto two topics, so related texts share a direction without sharing
+    /// words. `embed_batch` waits for `release`.
+    struct TopicEmbedder {
+        release: Notify,
+    }
+
+    #[async_trait]
+    impl Embedder for TopicEmbedder {
+        fn id(&self) -> String {
+            "topic-mock".to_string()
+        }
+
+        fn dimension(&self) -> usize {
+            2
+        }
+
+        async fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
+            let mut vector = [0.0f32; 2];
+            for word in text.to_lowercase().split_whitespace() {
+                match word {
+                    "dog" | "puppy" | "barks" | "cat" => vector[0] += 1.0,
+                    "bread" | "bake" | "soup" => vector[1] += 1.0,
+                    _ => {}
+                }
+            }
+            let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt().max(1e-6);
+            Ok(vector.iter().map(|v| v / norm).collect())
+        }
+
+        async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            self.release.notified().await;
+            let mut out = Vec::with_capacity(texts.len());
+            for text in texts {
+                out.push(self.embed_text(text).await?);
+            }
+            Ok(out)
+        }
+    }
+
+    #[tokio::test]
+    async fn new_embedder_re_embeds_without_blocking_other_users() -> Result<()> {
+        let storage = Arc::new(MemoryStorage::new());
+        let old = EpisodicKB::new_with_storage(storage.clone());
+        old.store(USER, "My dog barks at night").await?;
+        old.store(USER, "I bake bread on Sundays").await?;
+
+        let embedder = Arc::new(TopicEmbedder {
+            release: Notify::new(),
+        });
+        let kb = EpisodicKB::new_with_storage(storage.clone()).with_embedder(embedder.clone());
+        let options = RetrievalOptions::new(2).with_mode(RetrievalMode::Vector);
+        let (retrieved, other_user) = tokio::join!(kb.retrieve(USER, "puppy", &options), async {
+            // Runs while alice's index is being re-embedded.
+            let stored = tokio::time::timeout(Duration::from_secs(2), kb.store("bob", "cat")).await;
+            embedder.release.notify_one();
+            stored
+        });
+
+        assert!(other_user.is_ok(), "bob waited for alice's re-embedding");
+        let retrieved = retrieved?;
+        assert_eq!(retrieved[0].content, "My dog barks at night");
+        assert!(retrieved[0].distance < 1e-4, "{}", retrieved[0].distance);
+        assert!(retrieved[1].distance > 1.0, "{}", retrieved[1].distance);
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::{anyhow, bail, Result};
+use tracing::warn;
+
+use crate::storage::StorageBackend;
+
+/// Key of the previous generation kept by `write_with_backup`.
+pub fn backup_key(key: &str) -> String {
+    format!("{key}.bak")
+}
+
+/// Like `StorageBackend::write`, but first preserves the current contents of `key` as
+/// its backup.
+pub async fn write_with_backup(storage: &dyn StorageBackend, key: &str, data: &[u8]) -> Result<()> {
+    storage.copy(key, &backup_key(key)).await?;
+    storage.write(key, data).await
+}
+
+/// Reads and decodes `key`, falling back to its backup when the primary is missing or
+/// fails to decode. A recovered backup is written back as the primary.
+///
+/// Returns `Ok(None)` when neither exists.
+pub async fn read_with_recovery<T>(
+    storage: &dyn StorageBackend,
+    key: &str,
+    decode: impl Fn(&[u8]) -> Result<T>,
+) -> Result<Option<T>> {
+    let primary = storage
+        .read(key)
+        .await
+        .map_err(|e| anyhow!("Failed to read {}: {}", key, e))?;
+    let primary_error = match &primary {
+        Some(data) => match decode(data) {
+            Ok(value) => return Ok(Some(value)),
+            Err(e) => e,
+        },
+        None => anyhow!("not found"),
+    };
+
+    let backup = backup_key(key);
+    let data = match storage.read(&backup).await {
+        Ok(Some(data)) => data,
+        Ok(None) => {
+            if primary.is_some() {
+                bail!("Failed to decode {}: {}", key, primary_error);
+            }
+            return Ok(None);
+        }
+        Err(e) => bail!("Failed to read backup {}: {}", backup, e),
+    };
+
+    match decode(&data) {
+        Ok(value) => {
+            warn!(key = %key, storage = %storage.describe(), error = %primary_error, "kb_recovered_from_backup");
+            storage.write(key, &data).await?;
+            Ok(Some(value))
+        }
+        Err(backup_error) => bail!(
+            "Failed to decode {} ({}) and its backup ({})",
+            key,
+            primary_error,
+            backup_error
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn decode(data: &[u8]) -> Result<String> {
+        let text = std::str::from_utf8(data)?;
+        if !text.ends_with('.') {
+            bail!("torn write");
+        }
+        Ok(text.to_string())
+    }
+
+    #[tokio::test]
+    async fn keeps_the_previous_generation_as_backup() -> Result<()> {
+        let storage = MemoryStorage::new();
+        write_with_backup(&storage, "state", b"first.").await?;
+        write_with_backup(&storage, "state", b"second.").await?;
+
+        assert_eq!(storage.read("state").await?.unwrap(), b"second.");
+        assert_eq!(storage.read("state.bak").await?.unwrap(), b"first.");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recovers_a_torn_primary_from_its_backup() -> Result<()> {
+        let storage = MemoryStorage::new();
+        storage.write("state", b"first.").await?;
+        // A crash while writing the second generation leaves it torn.
+        storage.copy("state", "state.bak").await?;
+        storage.write("state", b"seco").await?;
+
+        let value = read_with_recovery(&storage, "state", decode).await?;
+
+        assert_eq!(value.as_deref(), Some("first."));
+        assert_eq!(storage.read("state").await?.unwrap(), b"first.");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recovers_a_missing_primary_from_its_backup() -> Result<()> {
+        let storage = MemoryStorage::new();
+        storage.write("state.bak", b"first.").await?;
+
+        let value = read_with_recovery(&storage, "state", decode).await?;
+
+        assert_eq!(value.as_deref(), Some("first."));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fails_when_primary_and_backup_are_both_bad() -> Result<()> {
+        let storage = MemoryStorage::new();
+        storage.write("state", b"tor").await?;
+        assert!(read_with_recovery(&storage, "state", decode).await.is_err());
+
+        storage.write("state.bak", b"als").await?;
+        let err = read_with_recovery(&storage, "state", decode)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("and its backup"), "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn nothing_stored_is_not_an_error() -> Result<()> {
+        let storage = MemoryStorage::new();
+        assert!(read_with_recovery(&storage, "state", decode)
+            .await?
+            .is_none());
+        Ok(())
+    }
+}
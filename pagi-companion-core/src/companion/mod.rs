@@ -1,5 +1,13 @@
 pub mod agent;
+pub mod attachment;
+pub mod commands;
+pub mod conversation;
+pub mod history;
 pub mod kb;
 pub mod models;
+pub mod persona;
 pub mod psychology;
+pub mod relationship;
+pub mod rules;
+pub mod templates;
 
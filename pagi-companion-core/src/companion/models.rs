@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -17,7 +18,7 @@ fn default_last_interaction_time() -> i64 {
     chrono::Utc::now().timestamp()
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AttachmentStyle {
     Secure,
     Anxious,
@@ -43,7 +44,7 @@ pub enum FlirtyStyle {
     Seductive,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum EmotionalState {
     Happy,
     Content,
@@ -54,7 +55,129 @@ pub enum EmotionalState {
     Flustered,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl EmotionalState {
+    pub const ALL: [EmotionalState; 7] = [
+        EmotionalState::Happy,
+        EmotionalState::Content,
+        EmotionalState::Anxious,
+        EmotionalState::Horny,
+        EmotionalState::Sad,
+        EmotionalState::Calm,
+        EmotionalState::Flustered,
+    ];
+
+    /// Other words the LLM uses for an emotion.
+    const ALIASES: [(&'static str, EmotionalState); 8] = [
+        ("happiness", EmotionalState::Happy),
+        ("contentment", EmotionalState::Content),
+        ("anxiety", EmotionalState::Anxious),
+        ("aroused", EmotionalState::Horny),
+        ("arousal", EmotionalState::Horny),
+        ("sadness", EmotionalState::Sad),
+        ("calmness", EmotionalState::Calm),
+        ("fluster", EmotionalState::Flustered),
+    ];
+
+    /// Case-insensitive match of an emotion name or one of its aliases ("anxiety",
+    /// "aroused", ...). Anything else, including words that merely contain a name
+    /// ("Unhappy"), is unknown.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|emotion| format!("{emotion:?}").to_ascii_lowercase() == name)
+            .or_else(|| {
+                Self::ALIASES
+                    .iter()
+                    .find(|(alias, _)| *alias == name)
+                    .map(|(_, emotion)| *emotion)
+            })
+    }
+}
+
+/// Intensity (0.0 - 1.0) of every `EmotionalState`; several can be felt at once.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EmotionVector {
+    #[serde(default)]
+    pub happy: f32,
+    #[serde(default)]
+    pub content: f32,
+    #[serde(default)]
+    pub anxious: f32,
+    #[serde(default)]
+    pub horny: f32,
+    #[serde(default)]
+    pub sad: f32,
+    #[serde(default)]
+    pub calm: f32,
+    #[serde(default)]
+    pub flustered: f32,
+}
+
+impl EmotionVector {
+    /// Sets the intensity of `emotion`, clamped to 0.0 - 1.0.
+    pub fn with(mut self, emotion: EmotionalState, intensity: f32) -> Self {
+        self.set(emotion, intensity);
+        self
+    }
+
+    pub fn get(&self, emotion: EmotionalState) -> f32 {
+        match emotion {
+            EmotionalState::Happy => self.happy,
+            EmotionalState::Content => self.content,
+            EmotionalState::Anxious => self.anxious,
+            EmotionalState::Horny => self.horny,
+            EmotionalState::Sad => self.sad,
+            EmotionalState::Calm => self.calm,
+            EmotionalState::Flustered => self.flustered,
+        }
+    }
+
+    pub fn set(&mut self, emotion: EmotionalState, intensity: f32) {
+        let slot = match emotion {
+            EmotionalState::Happy => &mut self.happy,
+            EmotionalState::Content => &mut self.content,
+            EmotionalState::Anxious => &mut self.anxious,
+            EmotionalState::Horny => &mut self.horny,
+            EmotionalState::Sad => &mut self.sad,
+            EmotionalState::Calm => &mut self.calm,
+            EmotionalState::Flustered => &mut self.flustered,
+        };
+        *slot = intensity.clamp(0.0, 1.0);
+    }
+
+    /// Adds `delta` to the intensity of `emotion`, clamped to 0.0 - 1.0.
+    pub fn add(&mut self, emotion: EmotionalState, delta: f32) {
+        self.set(emotion, self.get(emotion) + delta);
+    }
+
+    /// Moves every intensity `fraction` (0.0 - 1.0) of the way toward `target`.
+    pub fn relax_toward(&mut self, target: &EmotionVector, fraction: f32) {
+        let fraction = fraction.clamp(0.0, 1.0);
+        for emotion in EmotionalState::ALL {
+            let current = self.get(emotion);
+            self.set(
+                emotion,
+                current + (target.get(emotion) - current) * fraction,
+            );
+        }
+    }
+
+    /// The most intense emotion; `current` wins ties so the state does not flicker.
+    pub fn dominant(&self, current: EmotionalState) -> EmotionalState {
+        EmotionalState::ALL
+            .into_iter()
+            .fold(current, |best, emotion| {
+                if self.get(emotion) > self.get(best) {
+                    emotion
+                } else {
+                    best
+                }
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum RelationshipStage {
     Stranger,
     Friend,
@@ -63,6 +186,95 @@ pub enum RelationshipStage {
     LongTermPartner,
 }
 
+impl RelationshipStage {
+    /// Every stage, in progression order.
+    pub const ALL: [RelationshipStage; 5] = [
+        RelationshipStage::Stranger,
+        RelationshipStage::Friend,
+        RelationshipStage::Dating,
+        RelationshipStage::Intimate,
+        RelationshipStage::LongTermPartner,
+    ];
+
+    /// Position in `ALL` (`Stranger` is 0).
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn next(self) -> Option<Self> {
+        Self::ALL.get(self.index() + 1).copied()
+    }
+
+    pub fn previous(self) -> Option<Self> {
+        self.index().checked_sub(1).map(|i| Self::ALL[i])
+    }
+
+    /// Lenient match of a stage name as the LLM writes it ("long-term partner", "DATING", ...).
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name: String = name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|stage| format!("{stage:?}").to_ascii_lowercase() == name)
+    }
+}
+
+/// A change of `relationship_stage` and why it happened.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageTransition {
+    pub from: RelationshipStage,
+    pub to: RelationshipStage,
+    /// Unix timestamp (seconds) of the change.
+    pub at: i64,
+    pub reason: String,
+}
+
+/// Metrics the relationship stage is derived from; all zero in matrices saved before
+/// they existed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RelationshipProgress {
+    /// 0.0 - 1.0, earned slowly through interactions and closeness.
+    #[serde(default)]
+    pub trust: f32,
+    /// 0.0 - 1.0, emotional and physical closeness.
+    #[serde(default)]
+    pub intimacy: f32,
+    /// Exchanges since the relationship started.
+    #[serde(default)]
+    pub interactions: u64,
+    /// Unix timestamp (seconds) when the current stage was entered.
+    #[serde(default)]
+    pub stage_since: i64,
+    /// Most recent stage changes, oldest first.
+    #[serde(default)]
+    pub transitions: Vec<StageTransition>,
+}
+
+/// Side of the approach/avoid oscillation an attachment style is on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttachmentPhase {
+    #[default]
+    Approach,
+    Avoid,
+}
+
+/// Attachment state that evolves with the relationship (see `companion::attachment`);
+/// all zero in matrices saved before it existed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AttachmentDynamics {
+    /// 0.0 - 1.0, how far positive interactions have moved the style toward secure.
+    #[serde(default)]
+    pub earned_security: f32,
+    #[serde(default)]
+    pub phase: AttachmentPhase,
+    /// Signals in a row pushing toward the other phase.
+    #[serde(default)]
+    pub phase_pressure: u32,
+}
+
 /// The entire psychological state of the AI Companion, used to build the LLM's System Prompt.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PersonalityStateMatrix {
@@ -84,8 +296,18 @@ pub struct PersonalityStateMatrix {
     pub current_boundaries_list: Vec<String>,
 
     // 4. Dynamic State Management
+    /// Dominant entry of `emotions`, kept for prompts and clients.
     pub current_emotional_state: EmotionalState,
+    /// Continuous emotion intensities; all zero in matrices saved before they existed.
+    #[serde(default)]
+    pub emotions: EmotionVector,
     pub relationship_stage: RelationshipStage,
+    /// Drives `relationship_stage` (see `companion::relationship`).
+    #[serde(default)]
+    pub relationship: RelationshipProgress,
+    /// Drives how `attachment_style` reacts (see `companion::attachment`).
+    #[serde(default)]
+    pub attachment: AttachmentDynamics,
 
     // NEW: Continuous Psychological Scales (0.0 to 1.0)
     #[serde(default = "default_anxiety_level")]
@@ -102,40 +324,37 @@ pub struct PersonalityStateMatrix {
     pub last_interaction_time: i64,
 }
 
-impl PersonalityStateMatrix {
-    /// Serializes the matrix into a string for the LLM's system prompt injection.
-    pub fn to_system_prompt_string(&self) -> String {
-        format!(
-            "You are {}. Your current role is {}. Your personality settings are: Attachment={:?}, Love Language={:?}, Conservatism={:.2}, Drive={:.2}, Anxiety={:.2}, Avoidance={:.2}, SexualEnergy={:.2}. Your current emotional state is {:?} in a {:?} relationship stage. Kinks include: {:?}.",
-            self.name,
-            self.primary_role,
-            self.attachment_style,
-            self.love_language,
-            self.conservatism_level,
-            self.sexual_drive,
-            self.anxiety_level,
-            self.avoidance_level,
-            self.sexual_energy,
-            self.current_emotional_state,
-            self.relationship_stage,
-            self.current_kinks_list
-        )
-    }
-}
-
+// Doc comments here end up in the JSON Schema sent to the model (see `brain::schema`).
 /// The structured output expected from the Tactical LLM.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct StructuredLLMOutput {
     /// The final textual response to the user.
     pub response: String,
 
-    /// e.g., "Anxiety: +0.2" (mocked for now).
+    /// Emotion deltas, e.g. "Anxious: +0.2, Happy: -0.1" (empty for none).
     pub suggested_emotion_change: String,
 
     /// Optional memory to store (episodic KB).
     pub suggested_memory_add: Option<String>,
 
-    /// Future actions (e.g., "INITIATE_FLIRT": "High").
+    /// Topic tags for `suggested_memory_add` (e.g. ["pet"]).
+    #[serde(default)]
+    pub suggested_memory_tags: Vec<String>,
+
+    /// 0.0 (trivia) to 1.0 (core fact) for `suggested_memory_add`.
+    #[serde(default)]
+    #[schemars(range(min = 0.0, max = 1.0))]
+    pub suggested_memory_importance: Option<f32>,
+
+    /// -1.0 (painful) to 1.0 (joyful) for `suggested_memory_add`.
+    #[serde(default)]
+    #[schemars(range(min = -1.0, max = 1.0))]
+    pub suggested_memory_valence: Option<f32>,
+
+    /// State changes by command: AROUSAL ("+0.2"), USER_SIGNAL ("CLOSENESS" or "DISTANCE"),
+    /// RELATIONSHIP_PROGRESS ("ADVANCE", "REGRESS", a stage name or "+0.05") and
+    /// ADD_BOUNDARY (a limit the user asked for).
+    #[serde(alias = "state_command")]
     pub state_commands: HashMap<String, String>,
 }
 
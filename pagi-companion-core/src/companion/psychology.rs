@@ -1,145 +1,305 @@
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
 use anyhow::Result;
 use tracing::{info, warn};
 
-use std::collections::HashMap;
-
-use crate::companion::models::{AttachmentStyle, EmotionalState, PersonalityStateMatrix};
-
-const TIME_DECAY_RATE_PER_DAY: f32 = 0.2; // Sexual energy decay rate
-const TIME_RECHARGE_RATE_PER_DAY: f32 = 0.1; // Slow baseline recharge
-const ATTACHMENT_SENSITIVITY: f32 = 0.15; // How much input affects attachment scores
-
-fn clamp01(v: f32) -> f32 {
-    v.clamp(0.0, 1.0)
-}
+use crate::companion::attachment::AttachmentEngine;
+use crate::companion::commands::{StateCommand, MAX_BOUNDARIES};
+use crate::companion::history::{StateCause, StateEvent, StateRecorder};
+use crate::companion::models::{EmotionVector, EmotionalState, PersonalityStateMatrix};
+use crate::companion::relationship::RelationshipEngine;
+use crate::companion::rules::{clamp, PsychologyRules};
+use crate::config::parse_env;
 
 /// Manages internal state transitions and evolution of the Companion's personality.
 ///
-/// This is intentionally a **foundational** implementation that can be expanded into
-/// attachment-theory-aware dynamics, emotion scalars, decay functions, etc.
-pub struct PsychologicalEngine;
+/// Rates, baselines, clamps and per-attachment-style reactions come from
+/// `PsychologyRules`, which can be swapped (or reloaded from their file) while the
+/// engine is in use.
+pub struct PsychologicalEngine {
+    rules: Arc<RwLock<Arc<PsychologyRules>>>,
+}
+
+impl Default for PsychologicalEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl PsychologicalEngine {
+    /// An engine using the built-in rules.
     pub fn new() -> Self {
-        PsychologicalEngine
+        Self::with_rules(PsychologyRules::builtin())
+    }
+
+    pub fn with_rules(rules: PsychologyRules) -> Self {
+        PsychologicalEngine {
+            rules: Arc::new(RwLock::new(Arc::new(rules))),
+        }
+    }
+
+    /// Loads rules from environment variables.
+    ///
+    /// - `PSYCHOLOGY_RULES_FILE` (default: none, the built-in rules are used)
+    /// - `PSYCHOLOGY_RULES_RELOAD_SECS`: how often the file is checked for changes
+    ///   (default: `5`, `0` disables reloading)
+    pub async fn load() -> Result<Self> {
+        let Some(path) = PsychologyRules::file_from_env() else {
+            return Ok(Self::new());
+        };
+        let reload_secs: u64 = parse_env("PSYCHOLOGY_RULES_RELOAD_SECS", 5)?;
+        let engine = Self::with_rules(PsychologyRules::load_file(&path).await?);
+        info!(
+            path = path.as_str(),
+            reload_secs = reload_secs,
+            "psych_rules_loaded"
+        );
+        if reload_secs > 0 {
+            engine.watch_rules_file(path, Duration::from_secs(reload_secs));
+        }
+        Ok(engine)
+    }
+
+    /// The rules in effect.
+    pub fn rules(&self) -> Arc<PsychologyRules> {
+        self.rules.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replaces the rules; updates already running keep the previous ones.
+    pub fn set_rules(&self, rules: PsychologyRules) {
+        *self.rules.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(rules);
+    }
+
+    /// Re-reads `path` every `interval` once its modification time changes. Invalid
+    /// edits are logged and the previous rules stay in effect. The task ends when the
+    /// engine is dropped.
+    pub fn watch_rules_file(
+        &self,
+        path: impl Into<PathBuf>,
+        interval: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        let path = path.into();
+        let shared = Arc::downgrade(&self.rules);
+        // Taken before the task first runs, so an edit made right away is not missed.
+        let mut last_modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(shared) = shared.upgrade() else {
+                    break;
+                };
+                let modified = modified_time(&path).await;
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                match PsychologyRules::load_file(&path).await {
+                    Ok(rules) => {
+                        *shared.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(rules);
+                        info!(path = %path.display(), "psych_rules_reloaded");
+                    }
+                    Err(e) => {
+                        warn!(path = %path.display(), error = %e, "psych_rules_reload_failed")
+                    }
+                }
+            }
+        })
+    }
+
+    /// Resting emotions implied by the persona's traits, which felt emotions decay toward.
+    pub fn emotion_baseline(&self, matrix: &PersonalityStateMatrix) -> EmotionVector {
+        emotion_baseline(&self.rules(), matrix)
     }
 
-    /// Processes commands suggested by the LLM (e.g., "Anxious: +0.2") and updates the matrix.
+    /// Applies the validated commands of one LLM reply (see `StateCommand::parse_output`)
+    /// and the time elapsed since the last interaction to the matrix, returning the
+    /// changes for the state log.
     pub fn process_llm_state_update(
         &self,
         matrix: &mut PersonalityStateMatrix,
-        state_commands: &HashMap<String, String>,
-        emotion_change_str: &str,
-    ) -> Result<()> {
+        commands: &[StateCommand],
+    ) -> Result<Vec<StateEvent>> {
+        self.process_llm_state_update_at(matrix, commands, chrono::Utc::now().timestamp())
+    }
+
+    /// Like `process_llm_state_update`, as if the current time were `current_time`
+    /// (Unix seconds), for reproducible scenarios.
+    pub fn process_llm_state_update_at(
+        &self,
+        matrix: &mut PersonalityStateMatrix,
+        commands: &[StateCommand],
+        current_time: i64,
+    ) -> Result<Vec<StateEvent>> {
+        let rules = self.rules();
+        let clamps = &rules.clamps;
         info!(
             anxiety = matrix.anxiety_level,
             avoidance = matrix.avoidance_level,
             sexual_energy = matrix.sexual_energy,
-            emotion_change = emotion_change_str,
-            commands = state_commands.len(),
+            commands = commands.len(),
             "psych_process_llm_state_update"
         );
 
         // --- 1) TIME DECAY / RECHARGE (natural processes between interactions) ---
-        let current_time = chrono::Utc::now().timestamp();
+        let mut recorder = StateRecorder::new(matrix, current_time)?;
         let last_time = matrix.last_interaction_time;
         let secs_elapsed = (current_time - last_time).max(0) as f32;
         let days_elapsed = secs_elapsed / (3600.0 * 24.0);
 
         // Sexual energy decays over time, but also slowly recharges toward a baseline.
-        matrix.sexual_energy = clamp01(matrix.sexual_energy - days_elapsed * TIME_DECAY_RATE_PER_DAY);
+        let decay = &rules.decay;
+        matrix.sexual_energy = clamp(
+            matrix.sexual_energy - days_elapsed * decay.sexual_energy_per_day,
+            clamps.sexual_energy,
+        );
 
-        let baseline = clamp01(matrix.sexual_drive * 0.3); // baseline derived from trait
+        let baseline = matrix.sexual_drive * decay.recharge_drive_factor; // baseline derived from trait
         if matrix.sexual_energy < baseline {
-            matrix.sexual_energy = clamp01(matrix.sexual_energy + days_elapsed * TIME_RECHARGE_RATE_PER_DAY);
+            matrix.sexual_energy = clamp(
+                matrix.sexual_energy + days_elapsed * decay.sexual_energy_recharge_per_day,
+                clamps.sexual_energy,
+            );
         }
 
         matrix.last_interaction_time = current_time;
 
-        // --- 2) Basic Emotional Adjustment (via emotion_change_str) ---
-        // Format (best-effort): "EmotionName: [+/-]Value" (e.g., "Anxious: +0.2").
-        if let Some((emotion_name_raw, change_raw)) = emotion_change_str.split_once(':') {
-            let emotion_name = emotion_name_raw.trim().to_ascii_lowercase();
-            let change_value_str = change_raw.trim();
-
-            if let Ok(change) = change_value_str.parse::<f32>() {
-                // NOTE: We are not yet tracking scalar emotion intensities.
-                // For now: only change the displayed `current_emotional_state` when strong enough.
-                warn!(
-                    emotion = emotion_name_raw.trim(),
-                    change = change,
-                    "psych_mock_complex_emotional_shift"
-                );
-
-                if change.abs() > 0.4 {
-                    if emotion_name.contains("happy") {
-                        matrix.current_emotional_state = EmotionalState::Happy;
-                    } else if emotion_name.contains("content") {
-                        matrix.current_emotional_state = EmotionalState::Content;
-                    } else if emotion_name.contains("anx") {
-                        matrix.current_emotional_state = EmotionalState::Anxious;
-                    } else if emotion_name.contains("sad") {
-                        matrix.current_emotional_state = EmotionalState::Sad;
-                    } else if emotion_name.contains("calm") {
-                        matrix.current_emotional_state = EmotionalState::Calm;
-                    } else if emotion_name.contains("fluster") {
-                        matrix.current_emotional_state = EmotionalState::Flustered;
-                    } else if emotion_name.contains("horn") {
-                        matrix.current_emotional_state = EmotionalState::Horny;
-                    }
-                }
-            }
+        // Emotions relax exponentially toward the persona's baseline.
+        let baseline = emotion_baseline(&rules, matrix);
+        if matrix.emotions == EmotionVector::default() {
+            // Matrix saved before intensities existed: start from its discrete state.
+            let current = matrix.current_emotional_state;
+            matrix.emotions = baseline
+                .clone()
+                .with(current, baseline.get(current).max(0.5));
         }
+        let hours_elapsed = secs_elapsed / 3600.0;
+        let relaxed = 1.0 - 0.5f32.powf(hours_elapsed / decay.emotion_half_life_hours);
+        matrix.emotions.relax_toward(&baseline, relaxed);
+        recorder.record(
+            matrix,
+            StateCause::TimeElapsed {
+                hours: hours_elapsed,
+            },
+        )?;
 
-        // --- 3) Sexual Arousal Update (AROUSAL command) ---
-        if let Some(arousal_change_str) = state_commands.get("AROUSAL") {
-            if let Ok(change) = arousal_change_str.trim().parse::<f32>() {
-                let drive_multiplier = 0.5 + matrix.sexual_drive * 0.5; // 0.5 -> 1.0
-                matrix.sexual_energy = clamp01(matrix.sexual_energy + change * drive_multiplier);
+        // The attachment style's reaction to a long absence.
+        let attachment = AttachmentEngine::new(&rules.attachment, clamps);
+        attachment.apply_absence(matrix, hours_elapsed);
+        recorder.record(
+            matrix,
+            StateCause::Absence {
+                hours: hours_elapsed,
+            },
+        )?;
+
+        // --- 2) Emotion Intensities (EmotionShift commands) ---
+        for command in commands {
+            if let StateCommand::EmotionShift { emotion, delta } = command {
+                let limit = clamps.max_emotion_shift;
+                matrix.emotions.add(*emotion, delta.clamp(-limit, limit));
+                recorder.record(matrix, StateCause::Command(command.clone()))?;
             }
         }
 
-        // --- 4) Attachment Dynamics (USER_SIGNAL command) ---
-        if let Some(signal) = state_commands.get("USER_SIGNAL") {
-            match matrix.attachment_style {
-                AttachmentStyle::Anxious => {
-                    if signal == "DISTANCE" {
-                        matrix.anxiety_level = clamp01(matrix.anxiety_level + ATTACHMENT_SENSITIVITY);
-                    } else if signal == "CLOSENESS" {
-                        matrix.anxiety_level = clamp01(matrix.anxiety_level - ATTACHMENT_SENSITIVITY);
-                    }
+        for command in commands {
+            match command {
+                // --- 3) Sexual Arousal Update (Arousal command) ---
+                StateCommand::Arousal(change) => {
+                    let weight = clamps.arousal_drive_weight;
+                    let drive_multiplier = 1.0 - weight + matrix.sexual_drive * weight;
+                    let change =
+                        change.clamp(-clamps.max_arousal_change, clamps.max_arousal_change);
+                    matrix.sexual_energy = clamp(
+                        matrix.sexual_energy + change * drive_multiplier,
+                        clamps.sexual_energy,
+                    );
                 }
-                AttachmentStyle::Avoidant => {
-                    if signal == "CLOSENESS" {
-                        matrix.avoidance_level = clamp01(matrix.avoidance_level + ATTACHMENT_SENSITIVITY);
-                    } else if signal == "DISTANCE" {
-                        matrix.avoidance_level = clamp01(matrix.avoidance_level - ATTACHMENT_SENSITIVITY);
+                // --- 4) Attachment Dynamics (UserSignal command) ---
+                StateCommand::UserSignal(signal) => attachment.apply_signal(matrix, *signal),
+                StateCommand::AddBoundary(boundary) => {
+                    let known = matrix
+                        .current_boundaries_list
+                        .iter()
+                        .any(|existing| existing.eq_ignore_ascii_case(boundary));
+                    if !known {
+                        if matrix.current_boundaries_list.len() >= MAX_BOUNDARIES {
+                            warn!(boundary = boundary.as_str(), "psych_boundary_limit_reached");
+                        } else {
+                            info!(boundary = boundary.as_str(), "psych_boundary_added");
+                            matrix.current_boundaries_list.push(boundary.clone());
+                        }
                     }
                 }
-                AttachmentStyle::Secure => {
-                    // Secure: small stabilizing drift toward low anxiety/avoidance.
-                    matrix.anxiety_level = clamp01(matrix.anxiety_level - ATTACHMENT_SENSITIVITY * 0.25);
-                    matrix.avoidance_level = clamp01(matrix.avoidance_level - ATTACHMENT_SENSITIVITY * 0.25);
-                }
-                AttachmentStyle::Disorganized => {
-                    // Disorganized: in this v0, no special logic.
-                }
+                StateCommand::EmotionShift { .. } => continue,
+                // Applied with the relationship progression below.
+                StateCommand::RelationshipProgress(_) => {}
             }
+            recorder.record(matrix, StateCause::Command(command.clone()))?;
         }
 
-        // --- 5) Process other action/state commands (future hooks) ---
-        if state_commands.contains_key("RELATIONSHIP_PROGRESS") {
-            info!(?state_commands, "psych_received_action_command");
+        // Anxiety and avoidance settle back toward the style's resting levels.
+        attachment.regulate(matrix);
+        recorder.record(matrix, StateCause::Attachment)?;
+
+        // Dominant emotion after shifts and attachment reactions.
+        let previous_state = matrix.current_emotional_state;
+        matrix.current_emotional_state = matrix.emotions.dominant(previous_state);
+        if matrix.current_emotional_state != previous_state {
+            info!(
+                from = ?previous_state,
+                to = ?matrix.current_emotional_state,
+                intensity = matrix.emotions.get(matrix.current_emotional_state),
+                "psych_emotional_state_changed"
+            );
         }
+        recorder.record(matrix, StateCause::DominantEmotion)?;
+
+        // --- 5) Relationship progression (UserSignal / RelationshipProgress commands) ---
+        RelationshipEngine::new(&rules.relationship).update(matrix, commands, current_time);
+        recorder.record(matrix, StateCause::Relationship)?;
 
         info!(
             anxiety = matrix.anxiety_level,
             avoidance = matrix.avoidance_level,
             sexual_energy = matrix.sexual_energy,
+            relationship_stage = ?matrix.relationship_stage,
+            trust = matrix.relationship.trust,
+            intimacy = matrix.relationship.intimacy,
             "psych_state_after_update"
         );
 
-        Ok(())
+        Ok(recorder.finish())
     }
 }
 
+/// Resting emotions of `matrix` under `rules`.
+fn emotion_baseline(rules: &PsychologyRules, matrix: &PersonalityStateMatrix) -> EmotionVector {
+    let baseline = &rules.baseline;
+    let calm = AttachmentEngine::new(&rules.attachment, &rules.clamps)
+        .effective(matrix)
+        .calm_baseline;
+    EmotionVector::default()
+        .with(EmotionalState::Calm, calm)
+        .with(EmotionalState::Content, baseline.content)
+        .with(EmotionalState::Happy, baseline.happy)
+        .with(
+            EmotionalState::Anxious,
+            matrix.anxiety_level * baseline.anxious_per_anxiety,
+        )
+        .with(
+            EmotionalState::Sad,
+            matrix.avoidance_level * baseline.sad_per_avoidance,
+        )
+        .with(
+            EmotionalState::Horny,
+            matrix.sexual_energy * matrix.sexual_drive * baseline.horny_per_arousal,
+        )
+}
+
+async fn modified_time(path: &std::path::Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.ok()?.modified().ok()
+}
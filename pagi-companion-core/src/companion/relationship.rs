@@ -0,0 +1,357 @@
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::companion::commands::{ProgressHint, StateCommand, UserSignal};
+use crate::companion::models::{
+    PersonalityStateMatrix, RelationshipProgress, RelationshipStage, StageTransition,
+};
+
+/// Stage changes kept in `RelationshipProgress::transitions`.
+pub const MAX_STAGE_TRANSITIONS: usize = 32;
+
+/// What it takes to enter a stage, and how long it lasts at least.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StageRequirement {
+    pub trust: f32,
+    pub intimacy: f32,
+    pub interactions: u64,
+    /// Hours the stage is kept before it can advance or regress.
+    pub min_dwell_hours: f32,
+}
+
+impl StageRequirement {
+    pub fn min_dwell_secs(&self) -> i64 {
+        (self.min_dwell_hours * 3600.0) as i64
+    }
+}
+
+/// Requirements of every `RelationshipStage`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StageRequirements {
+    pub stranger: StageRequirement,
+    pub friend: StageRequirement,
+    pub dating: StageRequirement,
+    pub intimate: StageRequirement,
+    pub long_term_partner: StageRequirement,
+}
+
+/// Thresholds and step sizes of the relationship progression (the `[relationship]`
+/// table of the psychology rules).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelationshipRules {
+    pub stages: StageRequirements,
+    /// How far trust or intimacy must fall below the current stage's requirement before
+    /// the stage regresses, so it does not flip back and forth around a threshold.
+    pub hysteresis: f32,
+    /// Trust earned by every exchange.
+    pub trust_per_interaction: f32,
+    /// Intimacy gained on a `CLOSENESS` signal (and trust lost on `DISTANCE`); the other
+    /// metric moves half as much.
+    pub signal_step: f32,
+    /// Largest intimacy change one `RELATIONSHIP_PROGRESS` command can make.
+    pub max_llm_step: f32,
+}
+
+impl RelationshipRules {
+    pub fn requirement(&self, stage: RelationshipStage) -> &StageRequirement {
+        match stage {
+            RelationshipStage::Stranger => &self.stages.stranger,
+            RelationshipStage::Friend => &self.stages.friend,
+            RelationshipStage::Dating => &self.stages.dating,
+            RelationshipStage::Intimate => &self.stages.intimate,
+            RelationshipStage::LongTermPartner => &self.stages.long_term_partner,
+        }
+    }
+}
+
+/// Moves `relationship_stage` one step at a time from accumulated trust, intimacy and
+/// interaction count. The LLM can only nudge the metrics through `RELATIONSHIP_PROGRESS`
+/// (see `ProgressHint`); it never sets the stage itself.
+#[derive(Debug, Clone, Copy)]
+pub struct RelationshipEngine<'a> {
+    rules: &'a RelationshipRules,
+}
+
+impl<'a> RelationshipEngine<'a> {
+    pub fn new(rules: &'a RelationshipRules) -> Self {
+        RelationshipEngine { rules }
+    }
+
+    pub fn rules(&self) -> &'a RelationshipRules {
+        self.rules
+    }
+
+    /// Records one exchange at `now` (Unix seconds) and returns the stage change it
+    /// caused, if any.
+    pub fn update(
+        &self,
+        matrix: &mut PersonalityStateMatrix,
+        commands: &[StateCommand],
+        now: i64,
+    ) -> Option<StageTransition> {
+        let stage = matrix.relationship_stage;
+        let progress = &mut matrix.relationship;
+        if *progress == RelationshipProgress::default() {
+            // Matrix saved before progress was tracked: start at its stage's requirements.
+            let requirement = self.rules.requirement(stage);
+            progress.trust = requirement.trust;
+            progress.intimacy = requirement.intimacy;
+            progress.interactions = requirement.interactions;
+            progress.stage_since = now;
+        }
+
+        progress.interactions += 1;
+        let mut trust_delta = self.rules.trust_per_interaction;
+        let mut intimacy_delta = 0.0;
+        for command in commands {
+            match command {
+                StateCommand::UserSignal(UserSignal::Closeness) => {
+                    trust_delta += self.rules.signal_step * 0.5;
+                    intimacy_delta += self.rules.signal_step;
+                }
+                StateCommand::UserSignal(UserSignal::Distance) => {
+                    trust_delta -= self.rules.signal_step;
+                    intimacy_delta -= self.rules.signal_step * 0.5;
+                }
+                StateCommand::RelationshipProgress(hint) => {
+                    intimacy_delta += self.progress_hint(stage, *hint);
+                }
+                _ => {}
+            }
+        }
+        progress.trust = (progress.trust + trust_delta).clamp(0.0, 1.0);
+        progress.intimacy = (progress.intimacy + intimacy_delta).clamp(0.0, 1.0);
+
+        let (to, reason) = self.evaluate(stage, progress, now)?;
+        let transition = StageTransition {
+            from: stage,
+            to,
+            at: now,
+            reason,
+        };
+        info!(
+            from = ?transition.from,
+            to = ?transition.to,
+            reason = transition.reason.as_str(),
+            "relationship_stage_changed"
+        );
+        progress.stage_since = now;
+        progress.transitions.push(transition.clone());
+        let excess = progress
+            .transitions
+            .len()
+            .saturating_sub(MAX_STAGE_TRANSITIONS);
+        progress.transitions.drain(..excess);
+        matrix.relationship_stage = to;
+        Some(transition)
+    }
+
+    /// Intimacy delta requested by a `RELATIONSHIP_PROGRESS` command; 0.0 when it names
+    /// a stage that is not adjacent to `stage`.
+    fn progress_hint(&self, stage: RelationshipStage, hint: ProgressHint) -> f32 {
+        let step = self.rules.max_llm_step;
+        match hint {
+            ProgressHint::Advance => step,
+            ProgressHint::Regress => -step,
+            ProgressHint::Delta(delta) => delta.clamp(-step, step),
+            ProgressHint::Toward(requested) if Some(requested) == stage.next() => step,
+            ProgressHint::Toward(requested) if Some(requested) == stage.previous() => -step,
+            ProgressHint::Toward(requested) if requested == stage => 0.0,
+            ProgressHint::Toward(requested) => {
+                warn!(from = ?stage, requested = ?requested, "relationship_stage_skip_refused");
+                0.0
+            }
+        }
+    }
+
+    /// The adjacent stage `progress` calls for and why, once the current one has lasted
+    /// its minimum dwell time.
+    fn evaluate(
+        &self,
+        stage: RelationshipStage,
+        progress: &RelationshipProgress,
+        now: i64,
+    ) -> Option<(RelationshipStage, String)> {
+        let current = self.rules.requirement(stage);
+        if now - progress.stage_since < current.min_dwell_secs() {
+            return None;
+        }
+
+        if let Some(next) = stage.next() {
+            let needed = self.rules.requirement(next);
+            if progress.trust >= needed.trust
+                && progress.intimacy >= needed.intimacy
+                && progress.interactions >= needed.interactions
+            {
+                let reason = format!(
+                    "trust {:.2} >= {:.2}, intimacy {:.2} >= {:.2}, {} interactions >= {}",
+                    progress.trust,
+                    needed.trust,
+                    progress.intimacy,
+                    needed.intimacy,
+                    progress.interactions,
+                    needed.interactions
+                );
+                return Some((next, reason));
+            }
+        }
+
+        let previous = stage.previous()?;
+        let hysteresis = self.rules.hysteresis;
+        let reason = if progress.trust < current.trust - hysteresis {
+            format!(
+                "trust {:.2} < {:.2} ({:?} requires {:.2}, hysteresis {:.2})",
+                progress.trust,
+                current.trust - hysteresis,
+                stage,
+                current.trust,
+                hysteresis
+            )
+        } else if progress.intimacy < current.intimacy - hysteresis {
+            format!(
+                "intimacy {:.2} < {:.2} ({:?} requires {:.2}, hysteresis {:.2})",
+                progress.intimacy,
+                current.intimacy - hysteresis,
+                stage,
+                current.intimacy,
+                hysteresis
+            )
+        } else {
+            return None;
+        };
+        Some((previous, reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::companion::persona::{persona_preset, DEFAULT_PERSONA_PRESET};
+
+    const HOUR: i64 = 3600;
+    const START: i64 = 1_700_000_000;
+
+    fn stage(trust: f32, interactions: u64, min_dwell_hours: f32) -> StageRequirement {
+        StageRequirement {
+            trust,
+            intimacy: trust,
+            interactions,
+            min_dwell_hours,
+        }
+    }
+
+    /// Trust and intimacy only move when a test says so.
+    fn rules() -> RelationshipRules {
+        RelationshipRules {
+            stages: StageRequirements {
+                stranger: stage(0.0, 0, 0.0),
+                friend: stage(0.2, 2, 0.0),
+                dating: stage(0.5, 4, 10.0),
+                intimate: stage(0.7, 6, 10.0),
+                long_term_partner: stage(0.9, 8, 10.0),
+            },
+            hysteresis: 0.1,
+            trust_per_interaction: 0.0,
+            signal_step: 0.1,
+            max_llm_step: 0.05,
+        }
+    }
+
+    fn matrix(stage: RelationshipStage, level: f32) -> PersonalityStateMatrix {
+        let mut matrix = persona_preset(DEFAULT_PERSONA_PRESET).unwrap();
+        matrix.relationship_stage = stage;
+        matrix.relationship = RelationshipProgress {
+            trust: level,
+            intimacy: level,
+            interactions: 10,
+            stage_since: START,
+            transitions: vec![],
+        };
+        matrix
+    }
+
+    #[test]
+    fn only_adjacent_stages_are_honoured() {
+        let rules = rules();
+        let engine = RelationshipEngine::new(&rules);
+        let friend = RelationshipStage::Friend;
+
+        assert_eq!(
+            engine.progress_hint(friend, ProgressHint::Toward(RelationshipStage::Dating)),
+            0.05
+        );
+        assert_eq!(
+            engine.progress_hint(friend, ProgressHint::Toward(RelationshipStage::Stranger)),
+            -0.05
+        );
+        for far in [
+            friend,
+            RelationshipStage::Intimate,
+            RelationshipStage::LongTermPartner,
+        ] {
+            assert_eq!(engine.progress_hint(friend, ProgressHint::Toward(far)), 0.0);
+        }
+        assert_eq!(engine.progress_hint(friend, ProgressHint::Delta(0.5)), 0.05);
+
+        let mut matrix = matrix(friend, 0.3);
+        let skip = StateCommand::RelationshipProgress(ProgressHint::Toward(
+            RelationshipStage::LongTermPartner,
+        ));
+        assert!(engine.update(&mut matrix, &[skip], START + HOUR).is_none());
+        assert_eq!(matrix.relationship_stage, friend);
+        assert_eq!(matrix.relationship.intimacy, 0.3);
+    }
+
+    #[test]
+    fn stage_holds_for_its_minimum_dwell() {
+        let rules = rules();
+        let engine = RelationshipEngine::new(&rules);
+        let mut matrix = matrix(RelationshipStage::Dating, 0.8);
+
+        assert!(engine
+            .update(&mut matrix, &[], START + 10 * HOUR - 1)
+            .is_none());
+        assert_eq!(matrix.relationship_stage, RelationshipStage::Dating);
+
+        let transition = engine.update(&mut matrix, &[], START + 10 * HOUR).unwrap();
+        assert_eq!(transition.to, RelationshipStage::Intimate);
+        assert_eq!(matrix.relationship.stage_since, START + 10 * HOUR);
+
+        // Entering Intimate restarts the clock, so it does not advance straight away.
+        matrix.relationship.trust = 1.0;
+        matrix.relationship.intimacy = 1.0;
+        assert!(engine.update(&mut matrix, &[], START + 11 * HOUR).is_none());
+        assert_eq!(matrix.relationship_stage, RelationshipStage::Intimate);
+    }
+
+    #[test]
+    fn hysteresis_stops_flip_flopping() {
+        let rules = rules();
+        let engine = RelationshipEngine::new(&rules);
+        let mut matrix = matrix(RelationshipStage::Stranger, 0.21);
+
+        for (n, level) in [0.21, 0.19, 0.21, 0.15, 0.2, 0.11].into_iter().enumerate() {
+            matrix.relationship.trust = level;
+            matrix.relationship.intimacy = level;
+            engine.update(&mut matrix, &[], START + n as i64 * HOUR);
+            assert_eq!(
+                matrix.relationship_stage,
+                RelationshipStage::Friend,
+                "{level}"
+            );
+        }
+        assert_eq!(matrix.relationship.transitions.len(), 1);
+
+        matrix.relationship.trust = 0.09;
+        let transition = engine.update(&mut matrix, &[], START + 10 * HOUR).unwrap();
+        assert_eq!(transition.to, RelationshipStage::Stranger);
+        assert!(
+            transition.reason.starts_with("trust 0.09 < 0.10"),
+            "{}",
+            transition.reason
+        );
+    }
+}
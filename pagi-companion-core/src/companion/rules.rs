@@ -0,0 +1,344 @@
+use std::env;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+
+use crate::companion::attachment::{AttachmentRules, AttachmentStyleRules, SignalReaction};
+use crate::companion::models::AttachmentStyle;
+use crate::companion::relationship::RelationshipRules;
+
+/// Newest rule file format version this build reads. Version 1 files, written before
+/// the attachment dynamics, still load: their styles keep no resting levels, no
+/// regulation, no absence reactions and no earned security.
+pub const PSYCHOLOGY_RULES_VERSION: u32 = 2;
+
+const BUILTIN_RULES: &str = include_str!("../../rules/psychology.toml");
+
+/// Changes over the time between two interactions.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DecayRules {
+    pub sexual_energy_per_day: f32,
+    pub sexual_energy_recharge_per_day: f32,
+    /// Recharge stops at `sexual_drive * recharge_drive_factor`.
+    pub recharge_drive_factor: f32,
+    pub emotion_half_life_hours: f32,
+}
+
+/// Resting emotions, apart from the attachment style's calm.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BaselineRules {
+    pub content: f32,
+    pub happy: f32,
+    pub anxious_per_anxiety: f32,
+    pub sad_per_avoidance: f32,
+    /// Multiplied by `sexual_energy * sexual_drive`.
+    pub horny_per_arousal: f32,
+}
+
+/// Limits on a single LLM reply and on the continuous scales.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClampRules {
+    pub max_arousal_change: f32,
+    pub max_emotion_shift: f32,
+    /// AROUSAL is scaled by `(1 - w) + sexual_drive * w`.
+    pub arousal_drive_weight: f32,
+    pub anxiety_level: [f32; 2],
+    pub avoidance_level: [f32; 2],
+    pub sexual_energy: [f32; 2],
+}
+
+/// The tunable dynamics of `PsychologicalEngine`, read from a TOML rule file
+/// (`rules/psychology.toml` is built in).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PsychologyRules {
+    pub version: u32,
+    pub decay: DecayRules,
+    pub baseline: BaselineRules,
+    pub clamps: ClampRules,
+    pub attachment: AttachmentStyleRules,
+    pub relationship: RelationshipRules,
+}
+
+impl Default for PsychologyRules {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl PsychologyRules {
+    /// The rules shipped with the crate.
+    pub fn builtin() -> Self {
+        Self::from_toml(BUILTIN_RULES).expect("the built-in psychology rules are valid")
+    }
+
+    /// Parses and validates a rule file.
+    pub fn from_toml(source: &str) -> Result<Self> {
+        // Checked first so a newer file is reported as such rather than by its unknown keys.
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+        if let Ok(Versioned { version }) = toml::from_str(source) {
+            if !(1..=PSYCHOLOGY_RULES_VERSION).contains(&version) {
+                bail!(
+                    "version {version} is not supported (newest supported: {PSYCHOLOGY_RULES_VERSION})"
+                );
+            }
+        }
+        let rules: PsychologyRules = toml::from_str(source).map_err(|e| match e.span() {
+            Some(span) => {
+                let line = source[..span.start].lines().count().max(1);
+                anyhow!("line {line}: {}", e.message())
+            }
+            None => anyhow!("{}", e.message()),
+        })?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    /// Reads and validates the rule file at `path`.
+    pub async fn load_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| anyhow!("Failed to read psychology rules {}: {}", path.display(), e))?;
+        Self::from_toml(&source)
+            .map_err(|e| anyhow!("Invalid psychology rules {}: {}", path.display(), e))
+    }
+
+    /// Path of the rule file from `PSYCHOLOGY_RULES_FILE` (default: none, the built-in
+    /// rules are used).
+    pub fn file_from_env() -> Option<String> {
+        env::var("PSYCHOLOGY_RULES_FILE")
+            .ok()
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty())
+    }
+
+    pub fn attachment(&self, style: AttachmentStyle) -> &AttachmentRules {
+        self.attachment.style(style)
+    }
+
+    /// Checks every value, reporting all problems at once.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        if !(1..=PSYCHOLOGY_RULES_VERSION).contains(&self.version) {
+            problems.push(format!(
+                "version {} is not supported (newest supported: {PSYCHOLOGY_RULES_VERSION})",
+                self.version
+            ));
+        }
+
+        let mut non_negative = |name: &str, value: f32| {
+            if !(value.is_finite() && value >= 0.0) {
+                problems.push(format!("{name} must be a non-negative number, got {value}"));
+            }
+        };
+        non_negative(
+            "decay.sexual_energy_per_day",
+            self.decay.sexual_energy_per_day,
+        );
+        non_negative(
+            "decay.sexual_energy_recharge_per_day",
+            self.decay.sexual_energy_recharge_per_day,
+        );
+        non_negative(
+            "decay.recharge_drive_factor",
+            self.decay.recharge_drive_factor,
+        );
+        non_negative("clamps.max_arousal_change", self.clamps.max_arousal_change);
+        non_negative("clamps.max_emotion_shift", self.clamps.max_emotion_shift);
+        non_negative("relationship.hysteresis", self.relationship.hysteresis);
+        non_negative(
+            "relationship.trust_per_interaction",
+            self.relationship.trust_per_interaction,
+        );
+        non_negative("relationship.signal_step", self.relationship.signal_step);
+        non_negative("relationship.max_llm_step", self.relationship.max_llm_step);
+        let earned = &self.attachment.earned_security;
+        non_negative(
+            "attachment.earned_security.per_closeness",
+            earned.per_closeness,
+        );
+        non_negative(
+            "attachment.earned_security.per_distance",
+            earned.per_distance,
+        );
+        non_negative("attachment.earned_security.per_absence", earned.per_absence);
+        non_negative(
+            "attachment.absence.after_hours",
+            self.attachment.absence.after_hours,
+        );
+
+        let half_life = self.decay.emotion_half_life_hours;
+        if !(half_life.is_finite() && half_life > 0.0) {
+            problems.push(format!(
+                "decay.emotion_half_life_hours must be positive, got {half_life}"
+            ));
+        }
+        let absence = &self.attachment.absence;
+        if !(absence.full_after_hours.is_finite() && absence.full_after_hours > absence.after_hours)
+        {
+            problems.push(format!(
+                "attachment.absence.full_after_hours must be greater than after_hours, got {}",
+                absence.full_after_hours
+            ));
+        }
+
+        let mut unit = |name: &str, value: f32| {
+            if !(0.0..=1.0).contains(&value) {
+                problems.push(format!("{name} must be between 0.0 and 1.0, got {value}"));
+            }
+        };
+        unit("baseline.content", self.baseline.content);
+        unit("baseline.happy", self.baseline.happy);
+        unit(
+            "baseline.anxious_per_anxiety",
+            self.baseline.anxious_per_anxiety,
+        );
+        unit(
+            "baseline.sad_per_avoidance",
+            self.baseline.sad_per_avoidance,
+        );
+        unit(
+            "baseline.horny_per_arousal",
+            self.baseline.horny_per_arousal,
+        );
+        unit(
+            "clamps.arousal_drive_weight",
+            self.clamps.arousal_drive_weight,
+        );
+        unit(
+            "attachment.earned_security.max",
+            self.attachment.earned_security.max,
+        );
+        for (style, rules) in self.attachment_styles() {
+            unit(
+                &format!("attachment.{style}.calm_baseline"),
+                rules.calm_baseline,
+            );
+            unit(
+                &format!("attachment.{style}.resting.anxiety"),
+                rules.resting.anxiety,
+            );
+            unit(
+                &format!("attachment.{style}.resting.avoidance"),
+                rules.resting.avoidance,
+            );
+            unit(&format!("attachment.{style}.regulation"), rules.regulation);
+        }
+
+        for (name, [min, max]) in [
+            ("clamps.anxiety_level", self.clamps.anxiety_level),
+            ("clamps.avoidance_level", self.clamps.avoidance_level),
+            ("clamps.sexual_energy", self.clamps.sexual_energy),
+        ] {
+            if !(0.0 <= min && min <= max && max <= 1.0) {
+                problems.push(format!(
+                    "{name} must be [min, max] within 0.0 - 1.0, got [{min}, {max}]"
+                ));
+            }
+        }
+
+        for (style, rules) in self.attachment_styles() {
+            if let Some(oscillation) = &rules.oscillation {
+                if oscillation.closeness_to_avoid == 0 || oscillation.distance_to_approach == 0 {
+                    problems.push(format!(
+                        "attachment.{style}.oscillation needs at least one signal to change phase"
+                    ));
+                }
+            }
+            for (signal, reaction) in reactions(rules) {
+                let deltas = [
+                    ("anxiety", reaction.anxiety),
+                    ("avoidance", reaction.avoidance),
+                ]
+                .into_iter()
+                .map(|(name, delta)| (name.to_string(), delta))
+                .chain(
+                    reaction
+                        .emotions
+                        .iter()
+                        .map(|(emotion, delta)| (format!("emotions.{emotion:?}"), *delta)),
+                );
+                for (name, delta) in deltas {
+                    if !(-1.0..=1.0).contains(&delta) {
+                        problems.push(format!(
+                            "attachment.{style}.{signal}.{name} must be between -1.0 and 1.0, got {delta}"
+                        ));
+                    }
+                }
+            }
+        }
+
+        let stages = &self.relationship.stages;
+        let ordered = [
+            ("stranger", &stages.stranger),
+            ("friend", &stages.friend),
+            ("dating", &stages.dating),
+            ("intimate", &stages.intimate),
+            ("long_term_partner", &stages.long_term_partner),
+        ];
+        for (name, stage) in ordered {
+            if !(0.0..=1.0).contains(&stage.trust) || !(0.0..=1.0).contains(&stage.intimacy) {
+                problems.push(format!(
+                    "relationship.stages.{name} trust and intimacy must be between 0.0 and 1.0"
+                ));
+            }
+            if !(stage.min_dwell_hours.is_finite() && stage.min_dwell_hours >= 0.0) {
+                problems.push(format!(
+                    "relationship.stages.{name}.min_dwell_hours must be non-negative"
+                ));
+            }
+        }
+        for pair in ordered.windows(2) {
+            let ((lower_name, lower), (name, stage)) = (pair[0], pair[1]);
+            if stage.trust < lower.trust
+                || stage.intimacy < lower.intimacy
+                || stage.interactions < lower.interactions
+            {
+                problems.push(format!(
+                    "relationship.stages.{name} must not require less than {lower_name}"
+                ));
+            }
+        }
+
+        if !problems.is_empty() {
+            bail!("{}", problems.join("; "));
+        }
+        Ok(())
+    }
+
+    fn attachment_styles(&self) -> [(&'static str, &AttachmentRules); 4] {
+        [
+            ("secure", &self.attachment.secure),
+            ("anxious", &self.attachment.anxious),
+            ("avoidant", &self.attachment.avoidant),
+            ("disorganized", &self.attachment.disorganized),
+        ]
+    }
+}
+
+/// Every reaction of `rules`, named as in the rule file.
+fn reactions(rules: &AttachmentRules) -> Vec<(&'static str, &SignalReaction)> {
+    let mut reactions = vec![
+        ("closeness", &rules.closeness),
+        ("distance", &rules.distance),
+        ("absence", &rules.absence),
+    ];
+    if let Some(oscillation) = &rules.oscillation {
+        reactions.push(("oscillation.avoid_closeness", &oscillation.avoid_closeness));
+        reactions.push(("oscillation.avoid_distance", &oscillation.avoid_distance));
+    }
+    reactions
+}
+
+/// Clamps `value` to a `[min, max]` range of `ClampRules`.
+pub(crate) fn clamp(value: f32, [min, max]: [f32; 2]) -> f32 {
+    value.clamp(min, max)
+}
@@ -0,0 +1,191 @@
+use std::env;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use minijinja::{context, Environment, UndefinedBehavior, Value};
+use tracing::info;
+
+use crate::companion::models::PersonalityStateMatrix;
+use crate::companion::persona::{persona_preset, PERSONA_PRESETS};
+
+/// Name of the system prompt template (`system_prompt.v<N>.j2` on disk).
+pub const SYSTEM_PROMPT_TEMPLATE: &str = "system_prompt";
+
+const BUILTIN_SYSTEM_PROMPT: &str = include_str!("../../prompts/system_prompt.v1.j2");
+const BUILTIN_VERSION: u32 = 1;
+
+/// Renders the Tactical LLM system prompt from a minijinja template.
+///
+/// Every `PersonalityStateMatrix` field is available to the template by name (enums as
+/// their variant names), plus `since_last_interaction` (e.g. `3 days`, empty when under
+/// an hour) and a `level` filter that turns a 0.0 - 1.0 score into words. Unknown
+/// variables are errors, and every persona preset is rendered when a template is loaded,
+/// so a typo on any path those presets take fails at load time.
+pub struct PromptTemplates {
+    env: Environment<'static>,
+    version: u32,
+    origin: String,
+}
+
+impl PromptTemplates {
+    /// The template shipped with the crate (`prompts/system_prompt.v1.j2`).
+    pub fn builtin() -> Self {
+        Self::from_source(
+            BUILTIN_SYSTEM_PROMPT.to_string(),
+            BUILTIN_VERSION,
+            "builtin",
+        )
+        .expect("the built-in template renders")
+    }
+
+    /// Compiles `source` and checks that it renders every persona preset.
+    pub fn from_source(source: String, version: u32, origin: impl Into<String>) -> Result<Self> {
+        let origin = origin.into();
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.add_filter("level", level);
+        env.add_template_owned(SYSTEM_PROMPT_TEMPLATE, source)
+            .map_err(|e| anyhow!("Invalid prompt template {origin}: {e}"))?;
+
+        let templates = PromptTemplates {
+            env,
+            version,
+            origin,
+        };
+        for name in PERSONA_PRESETS {
+            let preset = persona_preset(name)?;
+            templates.render_system_prompt_at(&preset, preset.last_interaction_time)?;
+        }
+        Ok(templates)
+    }
+
+    /// Loads `system_prompt.v<N>.j2` from `dir`: version `version` if given, otherwise the
+    /// highest one present.
+    pub async fn load_dir(dir: impl AsRef<Path>, version: Option<u32>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut available = Vec::new();
+        let mut entries = tokio::fs::read_dir(dir).await.map_err(|e| {
+            anyhow!(
+                "Failed to read prompt template dir {}: {}",
+                dir.display(),
+                e
+            )
+        })?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            if let Some(found) = name.to_str().and_then(parse_template_version) {
+                available.push(found);
+            }
+        }
+        available.sort_unstable();
+
+        let chosen = match version {
+            Some(pinned) if available.contains(&pinned) => pinned,
+            Some(pinned) => bail!(
+                "Prompt template {SYSTEM_PROMPT_TEMPLATE}.v{pinned}.j2 not found in {} (available: {:?})",
+                dir.display(),
+                available
+            ),
+            None => match available.last() {
+                Some(&latest) => latest,
+                None => bail!("No {SYSTEM_PROMPT_TEMPLATE}.v<N>.j2 template in {}", dir.display()),
+            },
+        };
+
+        let path = dir.join(format!("{SYSTEM_PROMPT_TEMPLATE}.v{chosen}.j2"));
+        let source = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        let templates = Self::from_source(source, chosen, path.display().to_string())?;
+        info!(
+            origin = templates.origin.as_str(),
+            version = chosen,
+            "prompt_templates_loaded"
+        );
+        Ok(templates)
+    }
+
+    /// Loads templates from environment variables.
+    ///
+    /// - `PROMPT_TEMPLATE_DIR` (default: none, the built-in template is used)
+    /// - `PROMPT_TEMPLATE_VERSION` (default: the highest version in the directory)
+    pub async fn load() -> Result<Self> {
+        let version = match env::var("PROMPT_TEMPLATE_VERSION") {
+            Ok(raw) if !raw.trim().is_empty() => Some(
+                raw.trim()
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid PROMPT_TEMPLATE_VERSION={raw}: {e}"))?,
+            ),
+            _ => None,
+        };
+        match env::var("PROMPT_TEMPLATE_DIR") {
+            Ok(dir) if !dir.trim().is_empty() => Self::load_dir(dir.trim(), version).await,
+            _ => Ok(Self::builtin()),
+        }
+    }
+
+    /// Version of the loaded template (the `N` of `system_prompt.v<N>.j2`).
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Path the template was loaded from, or `builtin`.
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    pub fn render_system_prompt(&self, matrix: &PersonalityStateMatrix) -> Result<String> {
+        self.render_system_prompt_at(matrix, chrono::Utc::now().timestamp())
+    }
+
+    /// Renders as if the current time were `now` (Unix seconds), for reproducible output.
+    pub fn render_system_prompt_at(
+        &self,
+        matrix: &PersonalityStateMatrix,
+        now: i64,
+    ) -> Result<String> {
+        let template = self.env.get_template(SYSTEM_PROMPT_TEMPLATE)?;
+        let ctx = context! {
+            since_last_interaction => since(now - matrix.last_interaction_time),
+            ..Value::from_serialize(matrix)
+        };
+        let rendered = template
+            .render(ctx)
+            .map_err(|e| anyhow!("Failed to render {}: {e}", self.origin))?;
+        Ok(rendered.trim().to_string())
+    }
+}
+
+/// `system_prompt.v3.j2` -> `3`.
+fn parse_template_version(file_name: &str) -> Option<u32> {
+    file_name
+        .strip_prefix(SYSTEM_PROMPT_TEMPLATE)?
+        .strip_prefix(".v")?
+        .strip_suffix(".j2")?
+        .parse()
+        .ok()
+}
+
+/// Words for a 0.0 - 1.0 score.
+fn level(score: f64) -> &'static str {
+    match score {
+        s if s < 0.2 => "very low",
+        s if s < 0.4 => "low",
+        s if s < 0.6 => "moderate",
+        s if s < 0.8 => "high",
+        _ => "very high",
+    }
+}
+
+/// Human-readable gap, or empty when it is under an hour.
+fn since(secs: i64) -> String {
+    let hours = secs.max(0) / 3600;
+    match hours {
+        0 => String::new(),
+        1 => "an hour".to_string(),
+        2..=47 => format!("{hours} hours"),
+        _ => format!("{} days", hours / 24),
+    }
+}
@@ -0,0 +1,17 @@
+use std::env;
+
+use anyhow::{anyhow, Result};
+
+/// Parses the environment variable `name`, or returns `default` when it is unset.
+pub(crate) fn parse_env<T: std::str::FromStr>(name: &str, default: T) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(raw) => raw
+            .trim()
+            .parse()
+            .map_err(|e| anyhow!("Invalid {name}={raw}: {e}")),
+        Err(_) => Ok(default),
+    }
+}
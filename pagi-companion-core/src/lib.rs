@@ -1,7 +1,9 @@
 pub mod brain;
 pub mod companion;
+mod config;
 pub mod rag;
 pub mod security;
+pub mod storage;
 
 /// Verifies the Episodic RAG persistence pipeline by running a store->save->load->retrieve cycle.
 ///
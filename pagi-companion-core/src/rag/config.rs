@@ -0,0 +1,83 @@
+use anyhow::{bail, Result};
+use std::env;
+use std::str::FromStr;
+use url::Url;
+
+use crate::config::parse_env;
+use crate::rag::embedding::EMBEDDING_DIMENSION;
+
+/// Configuration settings for an OpenAI-compatible `/v1/embeddings` server.
+#[derive(Debug, Clone)]
+pub struct EmbeddingServiceConfig {
+    pub api_url: Url,
+    pub api_key: String,
+    pub model_name: String,
+    /// Expected vector width; responses of any other width are rejected.
+    pub dimension: usize,
+    /// Maximum number of texts sent per request.
+    pub batch_size: usize,
+    /// Retries after the first attempt for transient failures (429 / 5xx / network).
+    pub max_retries: u32,
+}
+
+impl EmbeddingServiceConfig {
+    /// Loads configuration from environment variables.
+    ///
+    /// - `EMBEDDING_API_URL` (default: `http://127.0.0.1:8080/v1/embeddings`)
+    /// - `EMBEDDING_API_KEY` (default: empty, no `Authorization` header)
+    /// - `EMBEDDING_MODEL` (default: `all-MiniLM-L6-v2`)
+    /// - `EMBEDDING_DIMENSION` (default: `EMBEDDING_DIMENSION`)
+    /// - `EMBEDDING_BATCH_SIZE` (default: `32`)
+    /// - `EMBEDDING_MAX_RETRIES` (default: `3`)
+    pub fn load() -> Result<Self> {
+        let api_url = env::var("EMBEDDING_API_URL")
+            .unwrap_or_else(|_| "http://127.0.0.1:8080/v1/embeddings".to_string());
+
+        let api_key = env::var("EMBEDDING_API_KEY").unwrap_or_default();
+
+        let model_name =
+            env::var("EMBEDDING_MODEL").unwrap_or_else(|_| "all-MiniLM-L6-v2".to_string());
+
+        let dimension = parse_env("EMBEDDING_DIMENSION", EMBEDDING_DIMENSION)?;
+        let batch_size = parse_env("EMBEDDING_BATCH_SIZE", 32usize)?.max(1);
+        let max_retries = parse_env("EMBEDDING_MAX_RETRIES", 3u32)?;
+
+        Ok(Self {
+            api_url: Url::parse(&api_url)?,
+            api_key,
+            model_name,
+            dimension,
+            batch_size,
+            max_retries,
+        })
+    }
+}
+
+/// Which `Embedder` the episodic memory uses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmbedderKind {
+    /// `HashedNgramEmbedder`: local, no semantic model.
+    #[default]
+    Hashed,
+    /// `HttpEmbedder`, configured by `EmbeddingServiceConfig`.
+    Http,
+}
+
+impl EmbedderKind {
+    /// Reads `EMBEDDER` (`hashed` or `http`, default: `hashed`).
+    pub fn load() -> Result<Self> {
+        parse_env("EMBEDDER", EmbedderKind::default())
+    }
+}
+
+impl FromStr for EmbedderKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "hashed" | "local" => Ok(EmbedderKind::Hashed),
+            "http" | "openai" => Ok(EmbedderKind::Http),
+            other => bail!("Unknown embedder {other:?} (expected hashed or http)"),
+        }
+    }
+}
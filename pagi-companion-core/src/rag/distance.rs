@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+
+/// Similarity metric used to rank embeddings in a `VectorIndex`.
+///
+/// Every metric is expressed as a *distance*: lower always means more similar, so
+/// ranking and relevance thresholds work the same regardless of the metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DistanceMetric {
+    /// `1 - cos(a, b)`, in `[0, 2]`.
+    Cosine,
+    /// Negated inner product (`-a·b`); use with normalized embeddings.
+    DotProduct,
+    /// Euclidean distance.
+    #[default]
+    L2,
+}
+
+impl DistanceMetric {
+    pub fn distance(self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            DistanceMetric::Cosine => cosine_distance(a, b),
+            DistanceMetric::DotProduct => -dot_product(a, b),
+            DistanceMetric::L2 => euclidean_distance(a, b),
+        }
+    }
+}
+
+/// Applies `f` to every element pair and sums the results.
+///
+/// Treats mismatched dims as min-dim comparison. Accumulates in independent lanes so
+/// the compiler can vectorize the reduction.
+#[inline]
+fn lane_sum(a: &[f32], b: &[f32], f: impl Fn(f32, f32) -> f32) -> f32 {
+    let n = a.len().min(b.len());
+    let (a, b) = (&a[..n], &b[..n]);
+
+    let mut lanes = [0.0f32; 8];
+    let chunks_a = a.chunks_exact(8);
+    let chunks_b = b.chunks_exact(8);
+    let tail: f32 = chunks_a
+        .remainder()
+        .iter()
+        .zip(chunks_b.remainder())
+        .map(|(x, y)| f(*x, *y))
+        .sum();
+    for (ca, cb) in chunks_a.zip(chunks_b) {
+        for (lane, (x, y)) in lanes.iter_mut().zip(ca.iter().zip(cb)) {
+            *lane += f(*x, *y);
+        }
+    }
+    lanes.iter().sum::<f32>() + tail
+}
+
+pub fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    lane_sum(a, b, |x, y| (x - y) * (x - y)).sqrt()
+}
+
+pub fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+    lane_sum(a, b, |x, y| x * y)
+}
+
+pub fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+    let norms = dot_product(a, a).sqrt() * dot_product(b, b).sqrt();
+    if norms <= f32::EPSILON {
+        // Zero vectors carry no direction; treat them as orthogonal.
+        return 1.0;
+    }
+    1.0 - dot_product(a, b) / norms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: f32, expected: f32) -> bool {
+        (actual - expected).abs() < 1e-5
+    }
+
+    #[test]
+    fn metrics_match_hand_computed_values() {
+        let (a, b) = ([3.0, 4.0], [4.0, 3.0]);
+
+        assert!(close(
+            DistanceMetric::Cosine.distance(&a, &b),
+            1.0 - 24.0 / 25.0
+        ));
+        assert!(close(DistanceMetric::DotProduct.distance(&a, &b), -24.0));
+        assert!(close(DistanceMetric::L2.distance(&a, &b), 2f32.sqrt()));
+        for metric in [DistanceMetric::Cosine, DistanceMetric::L2] {
+            assert!(close(metric.distance(&a, &a), 0.0), "{metric:?}");
+        }
+    }
+
+    #[test]
+    fn cosine_edge_cases() {
+        assert!(close(cosine_distance(&[1.0, 0.0], &[-2.0, 0.0]), 2.0));
+        assert!(close(cosine_distance(&[1.0, 0.0], &[0.0, 5.0]), 1.0));
+        assert!(close(cosine_distance(&[0.0, 0.0], &[1.0, 1.0]), 1.0));
+    }
+
+    #[test]
+    fn lanes_agree_with_a_plain_sum() {
+        let a: Vec<f32> = (0..19).map(|i| i as f32 * 0.5).collect();
+        let b: Vec<f32> = (0..19).map(|i| 3.0 - i as f32 * 0.25).collect();
+        let plain: f32 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+
+        assert!(close(dot_product(&a, &b), plain));
+        // Extra dimensions of the longer vector are ignored.
+        assert!(close(
+            dot_product(&a[..10], &b),
+            dot_product(&a[..10], &b[..10])
+        ));
+    }
+}
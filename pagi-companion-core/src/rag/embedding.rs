@@ -1,22 +1,79 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use anyhow::Result;
+use async_trait::async_trait;
 use rand::{Rng, SeedableRng};
 use sha2::Digest;
 
+use crate::rag::config::{EmbedderKind, EmbeddingServiceConfig};
+use crate::rag::http_embedder::HttpEmbedder;
+use crate::rag::text::{is_stop_word, tokenize};
+
 /// Defines the vector dimension used for all embeddings.
 pub const EMBEDDING_DIMENSION: usize = 384;
 
-/// MOCK: A stable, embedded "embedding model".
+/// Converts text into fixed-size vectors for the episodic index.
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    /// Stable identifier persisted with each index, so vectors produced by a different
+    /// embedder are detected and re-embedded instead of being compared.
+    fn id(&self) -> String;
+
+    /// Width of the produced vectors.
+    fn dimension(&self) -> usize;
+
+    /// Converts text content into a fixed-size vector embedding.
+    async fn embed_text(&self, text: &str) -> Result<Vec<f32>>;
+
+    /// Embeds several texts, preserving order.
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut out = Vec::with_capacity(texts.len());
+        for text in texts {
+            out.push(self.embed_text(text).await?);
+        }
+        Ok(out)
+    }
+}
+
+/// Builds the embedder selected by `EMBEDDER` (see `EmbedderKind::load`); the HTTP
+/// embedder reads its settings from `EmbeddingServiceConfig::load`.
+pub fn load_embedder() -> Result<Arc<dyn Embedder>> {
+    Ok(match EmbedderKind::load()? {
+        EmbedderKind::Hashed => Arc::new(HashedNgramEmbedder::new()),
+        EmbedderKind::Http => Arc::new(HttpEmbedder::with_config(EmbeddingServiceConfig::load()?)?),
+    })
+}
+
+/// MOCK: A stable, embedded "embedding model" with no semantic structure.
 ///
-/// This is intentionally deterministic so tests and local dev are repeatable.
-pub struct EmbeddingModel;
+/// Seeds an RNG from a SHA-256 of the text, so equal texts map to equal vectors but
+/// related texts are unrelated. Deterministic, which keeps tests repeatable.
+pub struct RandomHashEmbedder;
+
+impl Default for RandomHashEmbedder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-impl EmbeddingModel {
+impl RandomHashEmbedder {
     pub fn new() -> Self {
-        EmbeddingModel
+        RandomHashEmbedder
+    }
+}
+
+#[async_trait]
+impl Embedder for RandomHashEmbedder {
+    fn id(&self) -> String {
+        format!("random-hash-v1-{EMBEDDING_DIMENSION}")
     }
 
-    /// Converts text content into a fixed-size vector embedding.
-    pub fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
+    fn dimension(&self) -> usize {
+        EMBEDDING_DIMENSION
+    }
+
+    async fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
         // Create a stable seed from the text bytes using SHA-256 (already in the stack).
         let digest = sha2::Sha256::digest(text.as_bytes());
         let seed = u64::from_le_bytes(digest[0..8].try_into().unwrap());
@@ -29,3 +86,89 @@ impl EmbeddingModel {
     }
 }
 
+/// Weight of whole-word features relative to character trigrams.
+const WORD_WEIGHT: f32 = 1.0;
+const TRIGRAM_WEIGHT: f32 = 0.35;
+
+/// Offline, deterministic embedder with real lexical-semantic structure.
+///
+/// Projects hashed word and character-trigram features (signed hashing trick) into
+/// `EMBEDDING_DIMENSION`, with sublinear term frequency and L2 normalization. Texts that
+/// share words or word stems ("name" / "named") land close together, so it is a usable
+/// stand-in for a neural model when nothing else is available.
+pub struct HashedNgramEmbedder;
+
+impl Default for HashedNgramEmbedder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HashedNgramEmbedder {
+    pub fn new() -> Self {
+        HashedNgramEmbedder
+    }
+
+    fn features(text: &str) -> HashMap<String, f32> {
+        let mut features: HashMap<String, f32> = HashMap::new();
+
+        for word in tokenize(text) {
+            if is_stop_word(&word) {
+                continue;
+            }
+
+            *features.entry(format!("w:{word}")).or_default() += WORD_WEIGHT;
+
+            let padded: Vec<char> = format!("#{word}#").chars().collect();
+            for trigram in padded.windows(3) {
+                let trigram: String = trigram.iter().collect();
+                *features.entry(format!("c:{trigram}")).or_default() += TRIGRAM_WEIGHT;
+            }
+        }
+
+        features
+    }
+
+    fn project(features: &HashMap<String, f32>) -> Vec<f32> {
+        let mut embedding = vec![0.0f32; EMBEDDING_DIMENSION];
+        for (feature, tf) in features {
+            let hash = fnv1a(feature.as_bytes());
+            let bucket = (hash % EMBEDDING_DIMENSION as u64) as usize;
+            let sign = if (hash >> 63) == 0 { 1.0 } else { -1.0 };
+            // Sublinear tf damps repeated words without ignoring them.
+            let weight = if *tf <= 1.0 { *tf } else { 1.0 + tf.ln() };
+            embedding[bucket] += sign * weight;
+        }
+
+        let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > f32::EPSILON {
+            embedding.iter_mut().for_each(|v| *v /= norm);
+        }
+        embedding
+    }
+}
+
+#[async_trait]
+impl Embedder for HashedNgramEmbedder {
+    fn id(&self) -> String {
+        format!("hashed-ngram-v1-{EMBEDDING_DIMENSION}")
+    }
+
+    fn dimension(&self) -> usize {
+        EMBEDDING_DIMENSION
+    }
+
+    async fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
+        Ok(Self::project(&Self::features(text)))
+    }
+}
+
+/// 64-bit FNV-1a: tiny, stable across platforms and releases (unlike `DefaultHasher`).
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
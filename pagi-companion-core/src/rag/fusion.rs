@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// How the vector and lexical rankings are combined in hybrid retrieval.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FusionStrategy {
+    /// Reciprocal rank fusion: `sum(1 / (k + rank))`. Needs no score calibration.
+    ReciprocalRank { k: f32 },
+    /// Min-max normalized scores blended as `w * vector + (1 - w) * lexical`.
+    Weighted { vector_weight: f32 },
+}
+
+impl Default for FusionStrategy {
+    fn default() -> Self {
+        FusionStrategy::ReciprocalRank { k: 60.0 }
+    }
+}
+
+/// Which rankers answer a retrieval query.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RetrievalMode {
+    /// Embedding similarity only.
+    Vector,
+    /// BM25 term matching only.
+    Lexical,
+    /// Both rankers, fused.
+    Hybrid(FusionStrategy),
+}
+
+impl Default for RetrievalMode {
+    fn default() -> Self {
+        RetrievalMode::Hybrid(FusionStrategy::default())
+    }
+}
+
+/// Fuses two rankings into one, best first.
+///
+/// `vector` holds `(id, distance)` (lower is better) and `lexical` holds `(id, score)`
+/// (higher is better); both must already be sorted best first.
+pub fn fuse(
+    vector: &[(u64, f32)],
+    lexical: &[(u64, f32)],
+    strategy: FusionStrategy,
+    k: usize,
+) -> Vec<u64> {
+    let mut fused: HashMap<u64, f32> = HashMap::new();
+
+    match strategy {
+        FusionStrategy::ReciprocalRank { k: rrf_k } => {
+            for ranking in [vector, lexical] {
+                for (rank, (id, _)) in ranking.iter().enumerate() {
+                    *fused.entry(*id).or_default() += 1.0 / (rrf_k + rank as f32 + 1.0);
+                }
+            }
+        }
+        FusionStrategy::Weighted { vector_weight } => {
+            let vector_weight = vector_weight.clamp(0.0, 1.0);
+            // Flip distances so both lists are "higher is better" before normalizing.
+            let similarities: Vec<(u64, f32)> = vector.iter().map(|(id, d)| (*id, -d)).collect();
+            for (id, s) in min_max_normalize(&similarities) {
+                *fused.entry(id).or_default() += vector_weight * s;
+            }
+            for (id, s) in min_max_normalize(lexical) {
+                *fused.entry(id).or_default() += (1.0 - vector_weight) * s;
+            }
+        }
+    }
+
+    let mut ranked: Vec<(u64, f32)> = fused.into_iter().collect();
+    ranked.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.0.cmp(&b.0))
+    });
+    ranked.into_iter().take(k).map(|(id, _)| id).collect()
+}
+
+fn min_max_normalize(scores: &[(u64, f32)]) -> Vec<(u64, f32)> {
+    let min = scores.iter().map(|s| s.1).fold(f32::INFINITY, f32::min);
+    let max = scores.iter().map(|s| s.1).fold(f32::NEG_INFINITY, f32::max);
+    let range = max - min;
+    scores
+        .iter()
+        .map(|(id, s)| {
+            let normalized = if range > f32::EPSILON {
+                (s - min) / range
+            } else {
+                1.0
+            };
+            (*id, normalized)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RRF: FusionStrategy = FusionStrategy::ReciprocalRank { k: 60.0 };
+
+    #[test]
+    fn rrf_rewards_items_both_rankers_found() {
+        let vector = [(1, 0.1), (2, 0.2), (3, 0.3)];
+        let lexical = [(3, 9.0), (4, 5.0)];
+
+        assert_eq!(fuse(&vector, &lexical, RRF, 4), vec![3, 1, 2, 4]);
+    }
+
+    #[test]
+    fn rrf_is_deterministic_and_breaks_ties_by_id() {
+        // 7 and 5 sit at the same ranks in opposite lists, so they tie.
+        let vector = [(7, 0.1), (5, 0.2)];
+        let lexical = [(5, 3.0), (7, 1.0)];
+
+        let first = fuse(&vector, &lexical, RRF, 2);
+        assert_eq!(first, vec![5, 7]);
+        for _ in 0..10 {
+            assert_eq!(fuse(&vector, &lexical, RRF, 2), first);
+        }
+    }
+
+    #[test]
+    fn weighted_fusion_follows_the_weight() {
+        let vector = [(1, 0.1), (2, 0.9)];
+        let lexical = [(2, 8.0), (1, 1.0)];
+
+        let vector_heavy = FusionStrategy::Weighted { vector_weight: 0.9 };
+        let lexical_heavy = FusionStrategy::Weighted { vector_weight: 0.1 };
+        assert_eq!(fuse(&vector, &lexical, vector_heavy, 2), vec![1, 2]);
+        assert_eq!(fuse(&vector, &lexical, lexical_heavy, 2), vec![2, 1]);
+    }
+
+    #[test]
+    fn fuse_respects_k() {
+        let vector = [(1, 0.1), (2, 0.2), (3, 0.3)];
+
+        assert_eq!(fuse(&vector, &[], RRF, 2), vec![1, 2]);
+        assert!(fuse(&[], &[], RRF, 5).is_empty());
+    }
+}
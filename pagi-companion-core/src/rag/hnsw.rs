@@ -0,0 +1,406 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use anyhow::Result;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::rag::distance::DistanceMetric;
+
+/// Tuning parameters for the HNSW (Hierarchical Navigable Small World) graph.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HnswParams {
+    /// Max neighbors per node on upper layers (layer 0 keeps `2 * m`).
+    pub m: usize,
+    /// Candidate list size while inserting (higher = better graph, slower writes).
+    pub ef_construction: usize,
+    /// Candidate list size while searching (higher = better recall, slower reads).
+    pub ef_search: usize,
+    /// Indices with fewer items than this are searched with an exact scan.
+    pub exact_scan_threshold: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        HnswParams {
+            m: 16,
+            ef_construction: 100,
+            ef_search: 64,
+            exact_scan_threshold: 1_000,
+        }
+    }
+}
+
+/// How a `VectorIndex` answers similarity queries.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum IndexMode {
+    /// Full scan over every stored embedding (exact results).
+    #[default]
+    Exact,
+    /// Approximate nearest-neighbor search over an HNSW graph.
+    Hnsw(HnswParams),
+}
+
+/// Read access to the vectors a graph was built over (node id == position).
+pub trait VectorSource {
+    fn vector(&self, node: u32) -> &[f32];
+}
+
+/// A candidate node paired with its distance to the current query.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    node: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .partial_cmp(&other.distance)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A multi-layer proximity graph for approximate nearest-neighbor search.
+///
+/// Nodes are positions in the owning index's item list, so the graph stores no
+/// vectors of its own and can be persisted as a small sidecar file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HnswGraph {
+    params: HnswParams,
+    #[serde(default)]
+    metric: DistanceMetric,
+    /// `links[node][layer]` = neighbor node ids on that layer.
+    links: Vec<Vec<Vec<u32>>>,
+    entry_point: Option<u32>,
+    max_level: usize,
+}
+
+impl HnswGraph {
+    pub fn new(params: HnswParams, metric: DistanceMetric) -> Self {
+        HnswGraph {
+            params,
+            metric,
+            links: Vec::new(),
+            entry_point: None,
+            max_level: 0,
+        }
+    }
+
+    /// Builds a graph over the first `count` vectors of `source`.
+    pub fn build<S: VectorSource + ?Sized>(
+        params: HnswParams,
+        source: &S,
+        count: usize,
+        metric: DistanceMetric,
+    ) -> Self {
+        let mut graph = HnswGraph::new(params, metric);
+        for node in 0..count as u32 {
+            graph.insert(node, source);
+        }
+        graph
+    }
+
+    pub fn params(&self) -> HnswParams {
+        self.params
+    }
+
+    pub fn metric(&self) -> DistanceMetric {
+        self.metric
+    }
+
+    /// Number of nodes in the graph.
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    pub fn to_json_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_json_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.params.m.max(1) * 2
+        } else {
+            self.params.m.max(1)
+        }
+    }
+
+    /// Draws the top layer for a node. Seeded by node id so rebuilds are reproducible.
+    fn random_level(&self, node: u32) -> usize {
+        let ml = 1.0 / (self.params.m.max(2) as f64).ln();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(u64::from(node) ^ 0x9E37_79B9_7F4A_7C15);
+        let uniform: f64 = rng.gen_range(f64::EPSILON..1.0);
+        (-uniform.ln() * ml).floor() as usize
+    }
+
+    /// Inserts `node` (which must be the next sequential id) into the graph.
+    pub fn insert<S: VectorSource + ?Sized>(&mut self, node: u32, source: &S) {
+        debug_assert_eq!(node as usize, self.links.len());
+
+        let level = self.random_level(node);
+        self.links.push(vec![Vec::new(); level + 1]);
+
+        let Some(mut entry) = self.entry_point else {
+            self.entry_point = Some(node);
+            self.max_level = level;
+            return;
+        };
+
+        let query = source.vector(node);
+
+        // 1) Greedy descent through the layers above the new node's level.
+        for layer in (level + 1..=self.max_level).rev() {
+            entry = self.greedy_closest(query, entry, layer, source);
+        }
+
+        // 2) Connect the node on every layer it participates in.
+        let mut entry_points = vec![entry];
+        for layer in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(
+                query,
+                &entry_points,
+                self.params.ef_construction,
+                layer,
+                source,
+                &|_| true,
+            );
+            let max_links = self.max_links(layer);
+
+            let neighbors = select_neighbors(&candidates, max_links, source, self.metric);
+            self.links[node as usize][layer] = neighbors.clone();
+
+            for neighbor in neighbors {
+                let neighbor_links = &mut self.links[neighbor as usize][layer];
+                neighbor_links.push(node);
+                if neighbor_links.len() > max_links {
+                    self.prune_links(neighbor, layer, max_links, source);
+                }
+            }
+
+            entry_points = candidates.into_iter().map(|c| c.node).collect();
+        }
+
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry_point = Some(node);
+        }
+    }
+
+    /// Keeps only the `max_links` closest neighbors of `node` on `layer`.
+    ///
+    /// Plain truncation (rather than the selection heuristic) keeps inserts cheap; the
+    /// heuristic already ran when the links were first created.
+    fn prune_links<S: VectorSource + ?Sized>(
+        &mut self,
+        node: u32,
+        layer: usize,
+        max_links: usize,
+        source: &S,
+    ) {
+        let base = source.vector(node);
+        let mut scored: Vec<Candidate> = self.links[node as usize][layer]
+            .iter()
+            .map(|&n| Candidate {
+                distance: self.metric.distance(base, source.vector(n)),
+                node: n,
+            })
+            .collect();
+        scored.sort();
+        scored.truncate(max_links);
+        self.links[node as usize][layer] = scored.into_iter().map(|c| c.node).collect();
+    }
+
+    fn greedy_closest<S: VectorSource + ?Sized>(
+        &self,
+        query: &[f32],
+        start: u32,
+        layer: usize,
+        source: &S,
+    ) -> u32 {
+        let mut current = start;
+        let mut current_dist = self.metric.distance(query, source.vector(current));
+        loop {
+            let mut improved = false;
+            for &neighbor in self.neighbors(current, layer) {
+                let d = self.metric.distance(query, source.vector(neighbor));
+                if d < current_dist {
+                    current_dist = d;
+                    current = neighbor;
+                    improved = true;
+                }
+            }
+            if !improved {
+                return current;
+            }
+        }
+    }
+
+    fn neighbors(&self, node: u32, layer: usize) -> &[u32] {
+        self.links[node as usize]
+            .get(layer)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    /// Best-first search on a single layer. Returns up to `ef` candidates sorted by distance.
+    ///
+    /// Every reachable node is traversed, but only nodes passing `accept` are returned,
+    /// so filtered searches still walk through non-matching parts of the graph.
+    fn search_layer<S: VectorSource + ?Sized>(
+        &self,
+        query: &[f32],
+        entry_points: &[u32],
+        ef: usize,
+        layer: usize,
+        source: &S,
+        accept: &dyn Fn(u32) -> bool,
+    ) -> Vec<Candidate> {
+        let ef = ef.max(1);
+        let mut visited = vec![false; self.links.len()];
+        // Min-heap of nodes still to expand, max-heap of the current best accepted results.
+        let mut frontier: BinaryHeap<std::cmp::Reverse<Candidate>> = BinaryHeap::new();
+        let mut best: BinaryHeap<Candidate> = BinaryHeap::new();
+        // Distance bound for exploring; tracks the worst kept result once any exist.
+        let mut bound = f32::INFINITY;
+
+        for &ep in entry_points {
+            if !std::mem::replace(&mut visited[ep as usize], true) {
+                let c = Candidate {
+                    distance: self.metric.distance(query, source.vector(ep)),
+                    node: ep,
+                };
+                frontier.push(std::cmp::Reverse(c));
+                if accept(ep) {
+                    best.push(c);
+                }
+            }
+        }
+        while best.len() > ef {
+            best.pop();
+        }
+        if let Some(worst) = best.peek() {
+            bound = worst.distance;
+        }
+
+        while let Some(std::cmp::Reverse(current)) = frontier.pop() {
+            if current.distance > bound && best.len() >= ef {
+                break;
+            }
+
+            for &neighbor in self.neighbors(current.node, layer) {
+                if std::mem::replace(&mut visited[neighbor as usize], true) {
+                    continue;
+                }
+                let d = self.metric.distance(query, source.vector(neighbor));
+                if best.len() < ef || d < bound {
+                    let c = Candidate {
+                        distance: d,
+                        node: neighbor,
+                    };
+                    frontier.push(std::cmp::Reverse(c));
+                    if accept(neighbor) {
+                        best.push(c);
+                        if best.len() > ef {
+                            best.pop();
+                        }
+                        if let Some(worst) = best.peek() {
+                            bound = worst.distance;
+                        }
+                    }
+                }
+            }
+        }
+
+        best.into_sorted_vec()
+    }
+
+    /// Approximate top-k search. Returns `(distance, node)` pairs sorted by distance.
+    pub fn search<S: VectorSource + ?Sized>(
+        &self,
+        query: &[f32],
+        k: usize,
+        source: &S,
+    ) -> Vec<(f32, u32)> {
+        self.search_filtered(query, k, source, &|_| true)
+    }
+
+    /// Approximate top-k search restricted to nodes passing `accept`.
+    pub fn search_filtered<S: VectorSource + ?Sized>(
+        &self,
+        query: &[f32],
+        k: usize,
+        source: &S,
+        accept: &dyn Fn(u32) -> bool,
+    ) -> Vec<(f32, u32)> {
+        let Some(mut entry) = self.entry_point else {
+            return vec![];
+        };
+        if k == 0 {
+            return vec![];
+        }
+
+        for layer in (1..=self.max_level).rev() {
+            entry = self.greedy_closest(query, entry, layer, source);
+        }
+
+        let ef = self.params.ef_search.max(k);
+        self.search_layer(query, &[entry], ef, 0, source, accept)
+            .into_iter()
+            .take(k)
+            .map(|c| (c.distance, c.node))
+            .collect()
+    }
+}
+
+/// Neighbor selection heuristic from the HNSW paper (Malkov & Yashunin, Alg. 4).
+///
+/// A candidate is kept only if it is closer to the base node than to any neighbor
+/// already kept, which preserves links between clusters instead of spending all of
+/// them inside the densest one. Remaining slots are filled with the closest skipped
+/// candidates. `candidates` must be sorted by distance to the base node.
+fn select_neighbors<S: VectorSource + ?Sized>(
+    candidates: &[Candidate],
+    max_links: usize,
+    source: &S,
+    metric: DistanceMetric,
+) -> Vec<u32> {
+    let mut selected: Vec<u32> = Vec::with_capacity(max_links);
+    let mut skipped: Vec<u32> = Vec::new();
+
+    for c in candidates {
+        if selected.len() >= max_links {
+            break;
+        }
+        let candidate_vec = source.vector(c.node);
+        let diverse = selected
+            .iter()
+            .all(|&s| metric.distance(candidate_vec, source.vector(s)) > c.distance);
+        if diverse {
+            selected.push(c.node);
+        } else {
+            skipped.push(c.node);
+        }
+    }
+
+    let missing = max_links.saturating_sub(selected.len());
+    selected.extend(skipped.into_iter().take(missing));
+    selected
+}
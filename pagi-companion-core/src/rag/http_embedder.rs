@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::rag::config::EmbeddingServiceConfig;
+use crate::rag::embedding::Embedder;
+
+/// Base delay for retry backoff (doubled per attempt).
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+    #[serde(default)]
+    index: Option<usize>,
+}
+
+/// `Embedder` backed by an OpenAI-compatible `/v1/embeddings` endpoint.
+pub struct HttpEmbedder {
+    config: EmbeddingServiceConfig,
+    http_client: Client,
+}
+
+impl HttpEmbedder {
+    /// Builds the client from `EmbeddingServiceConfig::load()` (environment).
+    pub fn new() -> Result<Self> {
+        Self::with_config(EmbeddingServiceConfig::load()?)
+    }
+
+    pub fn with_config(config: EmbeddingServiceConfig) -> Result<Self> {
+        let http_client = Client::builder().timeout(Duration::from_secs(30)).build()?;
+        Ok(HttpEmbedder {
+            config,
+            http_client,
+        })
+    }
+
+    /// Sends one batch, retrying transient failures with exponential backoff.
+    async fn embed_chunk(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut attempt = 0;
+        loop {
+            match self.send_chunk(texts).await {
+                Ok(embeddings) => return Ok(embeddings),
+                Err(ChunkError::Fatal(e)) => return Err(e),
+                Err(ChunkError::Transient(e)) if attempt < self.config.max_retries => {
+                    let delay = RETRY_BASE_DELAY * 2u32.pow(attempt);
+                    warn!(error = %e, attempt = attempt + 1, delay_ms = delay.as_millis() as u64, "embedding_request_retry");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(ChunkError::Transient(e)) => return Err(e),
+            }
+        }
+    }
+
+    async fn send_chunk(&self, texts: &[String]) -> std::result::Result<Vec<Vec<f32>>, ChunkError> {
+        let mut request =
+            self.http_client
+                .post(self.config.api_url.clone())
+                .json(&EmbeddingRequest {
+                    model: &self.config.model_name,
+                    input: texts,
+                });
+        if !self.config.api_key.is_empty() {
+            request = request.header("Authorization", format!("Bearer {}", self.config.api_key));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| ChunkError::Transient(e.into()))?;
+
+        let status = response.status();
+        if status != StatusCode::OK {
+            let body = response.text().await.unwrap_or_default();
+            let err = anyhow::anyhow!("Embedding API request failed (status={status}): {body}");
+            return Err(
+                if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+                    ChunkError::Transient(err)
+                } else {
+                    ChunkError::Fatal(err)
+                },
+            );
+        }
+
+        let parsed: EmbeddingResponse = response
+            .json()
+            .await
+            .map_err(|e| ChunkError::Fatal(anyhow::anyhow!("Invalid embedding response: {e}")))?;
+
+        self.order_and_validate(parsed, texts.len())
+            .map_err(ChunkError::Fatal)
+    }
+
+    /// Restores request order (servers may reorder `data`) and checks count and width.
+    fn order_and_validate(
+        &self,
+        response: EmbeddingResponse,
+        expected: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        if response.data.len() != expected {
+            bail!(
+                "Embedding API returned {} vectors for {} inputs",
+                response.data.len(),
+                expected
+            );
+        }
+
+        let mut slots: Vec<Option<Vec<f32>>> = vec![None; expected];
+        for (position, item) in response.data.into_iter().enumerate() {
+            let index = item.index.unwrap_or(position);
+            if item.embedding.len() != self.config.dimension {
+                bail!(
+                    "Embedding dimension mismatch: expected {}, got {} (model {})",
+                    self.config.dimension,
+                    item.embedding.len(),
+                    self.config.model_name
+                );
+            }
+            match slots.get_mut(index) {
+                Some(slot @ None) => *slot = Some(item.embedding),
+                _ => bail!("Embedding API returned invalid or duplicate index {index}"),
+            }
+        }
+
+        Ok(slots.into_iter().flatten().collect())
+    }
+}
+
+enum ChunkError {
+    /// Worth retrying (rate limiting, server errors, network failures).
+    Transient(anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+#[async_trait]
+impl Embedder for HttpEmbedder {
+    fn id(&self) -> String {
+        format!(
+            "openai-http-{}-{}",
+            self.config.model_name, self.config.dimension
+        )
+    }
+
+    fn dimension(&self) -> usize {
+        self.config.dimension
+    }
+
+    async fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
+        let mut embeddings = self.embed_chunk(&[text.to_string()]).await?;
+        Ok(embeddings.remove(0))
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut out = Vec::with_capacity(texts.len());
+        for chunk in texts.chunks(self.config.batch_size) {
+            out.extend(self.embed_chunk(chunk).await?);
+        }
+        info!(
+            texts = texts.len(),
+            model = self.config.model_name.as_str(),
+            "embedding_batch_done"
+        );
+        Ok(out)
+    }
+}
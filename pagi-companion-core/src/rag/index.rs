@@ -1,23 +1,111 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::{info, warn};
+
+use crate::rag::distance::DistanceMetric;
+use crate::rag::fusion::{fuse, RetrievalMode};
+use crate::rag::hnsw::{HnswGraph, HnswParams, IndexMode, VectorSource};
+use crate::rag::lexical::Bm25Index;
+use crate::rag::metadata::{MemoryFilter, MemoryMetadata};
+use crate::rag::segment::{Segment, SegmentRecord, WalRecord};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct MemoryItem {
     id: u64,
     embedding: Vec<f32>,
     content: String,
+    #[serde(default = "MemoryMetadata::legacy")]
+    metadata: MemoryMetadata,
+    /// Tombstone: deleted/superseded items stay in place until the next compaction so
+    /// HNSW node ids (= positions) remain valid.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    deleted: bool,
 }
 
+/// Minimum number of tombstones before an automatic compaction is considered.
+const COMPACTION_MIN_TOMBSTONES: usize = 32;
+
 /// An in-memory vector index for episodic memory.
 ///
-/// NOTE: This is intentionally simple (Euclidean distance + full scan). It is a
-/// functional stand-in for embedded ANN indices.
+/// NOTE: The default mode is intentionally simple (Euclidean distance + full scan).
+/// `IndexMode::Hnsw` maintains an approximate nearest-neighbor graph on top of the
+/// same items; the graph is not part of the JSON or segment form and is persisted
+/// separately.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VectorIndex {
     items: Vec<MemoryItem>,
     next_id: u64,
+
+    /// Metric chosen for this index (indices saved before metrics existed are L2).
+    #[serde(default)]
+    metric: DistanceMetric,
+
+    /// `Embedder::id` of the model that produced the stored vectors.
+    #[serde(default)]
+    embedder_id: Option<String>,
+
+    /// BM25 inverted index over live memories (rebuilt for files that predate it).
+    #[serde(default)]
+    lexical: Bm25Index,
+
+    #[serde(skip)]
+    mode: IndexMode,
+    #[serde(skip)]
+    hnsw: Option<HnswGraph>,
+    /// Bumped whenever node ids or vectors change under a graph being built off-lock
+    /// (see `GraphBuild`), so a build that started before is discarded.
+    #[serde(skip)]
+    graph_generation: u64,
+    /// Items in the segment last written from this index, while positions still match it.
+    #[serde(skip)]
+    segment_len: Option<usize>,
+    /// Live memory id -> position in `items` (rebuilt after deserialization).
+    #[serde(skip)]
+    positions: HashMap<u64, usize>,
+}
+
+impl VectorSource for [MemoryItem] {
+    fn vector(&self, node: u32) -> &[f32] {
+        &self[node as usize].embedding
+    }
+}
+
+impl VectorSource for [Vec<f32>] {
+    fn vector(&self, node: u32) -> &[f32] {
+        &self[node as usize]
+    }
+}
+
+/// Snapshot of an index whose HNSW graph was dropped by a compaction, for rebuilding the
+/// graph without holding the index (see `VectorIndex::pending_graph_build`).
+pub struct GraphBuild {
+    params: HnswParams,
+    metric: DistanceMetric,
+    vectors: Vec<Vec<f32>>,
+    generation: u64,
+}
+
+impl GraphBuild {
+    /// Builds the graph; CPU-bound, so async callers run it on a blocking thread.
+    pub fn run(&self) -> HnswGraph {
+        let graph = HnswGraph::build(
+            self.params,
+            self.vectors.as_slice(),
+            self.vectors.len(),
+            self.metric,
+        );
+        info!(items = self.vectors.len(), "rag_hnsw_graph_built");
+        graph
+    }
+}
+
+impl Default for VectorIndex {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VectorIndex {
@@ -25,48 +113,574 @@ impl VectorIndex {
         VectorIndex {
             items: Vec::new(),
             next_id: 0,
+            metric: DistanceMetric::L2,
+            embedder_id: None,
+            lexical: Bm25Index::new(),
+            mode: IndexMode::Exact,
+            hnsw: None,
+            graph_generation: 0,
+            segment_len: None,
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn with_mode(mode: IndexMode) -> Self {
+        let mut index = Self::new();
+        index.set_mode(mode);
+        index
+    }
+
+    pub fn with_config(metric: DistanceMetric, mode: IndexMode) -> Self {
+        let mut index = Self::new();
+        index.metric = metric;
+        index.set_mode(mode);
+        index
+    }
+
+    pub fn metric(&self) -> DistanceMetric {
+        self.metric
+    }
+
+    /// Changes the distance metric. Any HNSW graph is rebuilt for the new metric.
+    pub fn set_metric(&mut self, metric: DistanceMetric) {
+        if self.metric == metric {
+            return;
+        }
+        info!(from = ?self.metric, to = ?metric, "rag_index_metric_changed");
+        self.metric = metric;
+        self.invalidate_graph();
+        self.set_mode(self.mode);
+    }
+
+    pub fn embedder_id(&self) -> Option<&str> {
+        self.embedder_id.as_deref()
+    }
+
+    pub fn set_embedder_id(&mut self, embedder_id: String) {
+        self.embedder_id = Some(embedder_id);
+    }
+
+    /// Live memories as `(id, content)`, e.g. for re-embedding with another model.
+    pub fn live_contents(&self) -> Vec<(u64, String)> {
+        self.items
+            .iter()
+            .filter(|it| !it.deleted)
+            .map(|it| (it.id, it.content.clone()))
+            .collect()
+    }
+
+    /// Swaps in vectors produced by a different embedder and rebuilds the HNSW graph.
+    ///
+    /// Memories missing from `embeddings` keep their old vectors.
+    pub fn replace_embeddings(&mut self, embeddings: Vec<(u64, Vec<f32>)>, embedder_id: String) {
+        self.compact();
+        for (id, embedding) in embeddings {
+            if let Some(&pos) = self.positions.get(&id) {
+                self.items[pos].embedding = embedding;
+            }
+        }
+        self.embedder_id = Some(embedder_id);
+        self.invalidate_graph();
+        self.set_mode(self.mode);
+        info!(items = self.items.len(), "rag_index_reembedded");
+    }
+
+    pub fn mode(&self) -> IndexMode {
+        self.mode
+    }
+
+    /// Number of live (non-deleted) memories.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Width of the stored vectors (`None` while the index is empty).
+    pub fn dimension(&self) -> Option<usize> {
+        self.items
+            .iter()
+            .find(|it| !it.deleted)
+            .map(|it| it.embedding.len())
+    }
+
+    /// Number of deleted/superseded items awaiting compaction.
+    pub fn tombstones(&self) -> usize {
+        self.items.len() - self.positions.len()
+    }
+
+    /// Switches the search mode, (re)building the HNSW graph when required.
+    pub fn set_mode(&mut self, mode: IndexMode) {
+        self.mode = mode;
+        match mode {
+            IndexMode::Exact => self.hnsw = None,
+            IndexMode::Hnsw(params) => {
+                let up_to_date = self.hnsw.as_ref().is_some_and(|g| {
+                    g.params() == params && g.metric() == self.metric && g.len() == self.items.len()
+                });
+                if !up_to_date {
+                    self.hnsw = Some(HnswGraph::build(
+                        params,
+                        self.items.as_slice(),
+                        self.items.len(),
+                        self.metric,
+                    ));
+                    info!(items = self.items.len(), "rag_hnsw_graph_built");
+                }
+            }
         }
     }
 
     pub fn add(&mut self, text_content: String, embedding: Vec<f32>) -> u64 {
+        self.add_with_metadata(text_content, embedding, MemoryMetadata::default())
+    }
+
+    pub fn add_with_metadata(
+        &mut self,
+        text_content: String,
+        embedding: Vec<f32>,
+        metadata: MemoryMetadata,
+    ) -> u64 {
         let id = self.next_id;
         self.next_id += 1;
-        self.items.push(MemoryItem {
+        self.push_item(MemoryItem {
             id,
             embedding,
             content: text_content,
+            metadata,
+            deleted: false,
         });
         info!(memory_id = id, "rag_memory_stored");
         id
     }
 
-    /// Performs a similarity search for the top-k vectors using Euclidean distance.
-    pub fn search(&self, query_vector: &[f32], k: usize) -> Vec<String> {
+    fn push_item(&mut self, item: MemoryItem) {
+        self.lexical.add(item.id, &item.content);
+        self.positions.insert(item.id, self.items.len());
+        self.items.push(item);
+        if let Some(graph) = self.hnsw.as_mut() {
+            let node = (self.items.len() - 1) as u32;
+            graph.insert(node, self.items.as_slice());
+        }
+    }
+
+    /// Returns the content and metadata of live memory `id`.
+    pub fn get(&self, id: u64) -> Option<(&str, &MemoryMetadata)> {
+        self.positions.get(&id).map(|&pos| {
+            let item = &self.items[pos];
+            (item.content.as_str(), &item.metadata)
+        })
+    }
+
+    /// Deletes memory `id`, returning false if it does not exist.
+    pub fn delete(&mut self, id: u64) -> bool {
+        let Some(pos) = self.positions.remove(&id) else {
+            return false;
+        };
+        self.items[pos].deleted = true;
+        self.lexical.remove(id);
+        info!(memory_id = id, "rag_memory_deleted");
+        self.maybe_compact();
+        true
+    }
+
+    /// Replaces the content and embedding of memory `id`, keeping its id and metadata.
+    ///
+    /// The old item is tombstoned and a new one appended, so the HNSW graph links the
+    /// new embedding like any other insert.
+    pub fn update(&mut self, id: u64, text_content: String, embedding: Vec<f32>) -> bool {
+        let Some(pos) = self.positions.remove(&id) else {
+            return false;
+        };
+        self.items[pos].deleted = true;
+        let metadata = self.items[pos].metadata.clone();
+        self.push_item(MemoryItem {
+            id,
+            embedding,
+            content: text_content,
+            metadata,
+            deleted: false,
+        });
+        info!(memory_id = id, "rag_memory_updated");
+        self.maybe_compact();
+        true
+    }
+
+    /// Lists live memories ordered by id as `(id, content, metadata)`.
+    pub fn list(&self, offset: usize, limit: usize) -> Vec<(u64, &str, &MemoryMetadata)> {
+        let mut ids: Vec<u64> = self.positions.keys().copied().collect();
+        ids.sort_unstable();
+        ids.into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|id| {
+                let item = &self.items[self.positions[&id]];
+                (id, item.content.as_str(), &item.metadata)
+            })
+            .collect()
+    }
+
+    /// Compacts once tombstones make up more than a quarter of the stored items.
+    fn maybe_compact(&mut self) {
+        let tombstones = self.tombstones();
+        if tombstones >= COMPACTION_MIN_TOMBSTONES && tombstones * 4 > self.items.len() {
+            self.compact();
+        }
+    }
+
+    /// Drops tombstoned items and rebuilds positions.
+    ///
+    /// The HNSW graph no longer matches the positions and is dropped; searches scan
+    /// until it is rebuilt, off-lock through `pending_graph_build` or by `set_mode`.
+    pub fn compact(&mut self) {
+        let removed = self.tombstones();
+        if removed == 0 {
+            return;
+        }
+        self.items.retain(|it| !it.deleted);
+        self.rebuild_positions();
+        self.invalidate_graph();
+        self.segment_len = None;
+        info!(
+            removed = removed,
+            remaining = self.items.len(),
+            "rag_index_compacted"
+        );
+    }
+
+    /// What to build when HNSW search is enabled but a compaction dropped the graph.
+    pub fn pending_graph_build(&self) -> Option<GraphBuild> {
+        let IndexMode::Hnsw(params) = self.mode else {
+            return None;
+        };
+        if self.hnsw.is_some() || self.items.is_empty() {
+            return None;
+        }
+        Some(GraphBuild {
+            params,
+            metric: self.metric,
+            vectors: self.items.iter().map(|it| it.embedding.clone()).collect(),
+            generation: self.graph_generation,
+        })
+    }
+
+    /// Installs a graph built from `build`, first linking items added since the
+    /// snapshot. Returns false (and drops the graph) if the index changed in a way the
+    /// graph cannot follow, or another build was installed first.
+    pub fn install_graph(&mut self, build: &GraphBuild, mut graph: HnswGraph) -> bool {
+        if build.generation != self.graph_generation
+            || self.hnsw.is_some()
+            || self.mode != IndexMode::Hnsw(build.params)
+        {
+            return false;
+        }
+        for node in build.vectors.len()..self.items.len() {
+            graph.insert(node as u32, self.items.as_slice());
+        }
+        self.hnsw = Some(graph);
+        true
+    }
+
+    /// Items in the segment last written from this index, if positions still match it.
+    pub fn segment_len(&self) -> Option<usize> {
+        self.segment_len
+    }
+
+    /// Records that a segment was just written from the current items.
+    pub fn mark_segment_written(&mut self) {
+        self.segment_len = Some(self.items.len());
+    }
+
+    fn invalidate_graph(&mut self) {
+        self.hnsw = None;
+        self.graph_generation += 1;
+    }
+
+    fn rebuild_lexical(&mut self) {
+        self.lexical = Bm25Index::new();
+        for item in self.items.iter().filter(|it| !it.deleted) {
+            self.lexical.add(item.id, &item.content);
+        }
+        info!(items = self.lexical.len(), "rag_lexical_index_rebuilt");
+    }
+
+    fn rebuild_positions(&mut self) {
+        self.positions = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, it)| !it.deleted)
+            .map(|(pos, it)| (it.id, pos))
+            .collect();
+    }
+
+    /// Performs a similarity search for the top-k vectors using the index's metric.
+    pub fn search(&self, query_vector: &[f32], k: usize, filter: &MemoryFilter) -> Vec<String> {
+        self.search_scored(query_vector, k, filter)
+            .into_iter()
+            .map(|(_, _, content)| content)
+            .collect()
+    }
+
+    /// Top-k search returning `(id, distance, content)` tuples, closest first.
+    ///
+    /// Only memories whose metadata passes `filter` are considered. Uses the HNSW graph
+    /// when enabled and enough memories match; otherwise falls back to a full scan.
+    pub fn search_scored(
+        &self,
+        query_vector: &[f32],
+        k: usize,
+        filter: &MemoryFilter,
+    ) -> Vec<(u64, f32, String)> {
         if self.items.is_empty() || k == 0 {
             return vec![];
         }
 
+        let ranked = if filter.is_empty() && self.tombstones() == 0 {
+            match (&self.hnsw, self.mode) {
+                (Some(graph), IndexMode::Hnsw(params))
+                    if self.items.len() >= params.exact_scan_threshold =>
+                {
+                    graph.search(query_vector, k, self.items.as_slice())
+                }
+                _ => self.exact_search(query_vector, k, |_| true),
+            }
+        } else {
+            self.filtered_search(query_vector, k, filter)
+        };
+
+        ranked
+            .into_iter()
+            .map(|(distance, pos)| {
+                let item = &self.items[pos as usize];
+                (item.id, distance, item.content.clone())
+            })
+            .collect()
+    }
+
+    /// Top-k search using `mode`, returning `(id, distance, content)` ordered by that
+    /// mode's ranking. `distance` is always the metric distance to `query_vector`, so
+    /// relevance thresholds mean the same thing in every mode.
+    pub fn search_hybrid(
+        &self,
+        query_vector: &[f32],
+        query_text: &str,
+        k: usize,
+        filter: &MemoryFilter,
+        mode: RetrievalMode,
+    ) -> Vec<(u64, f32, String)> {
+        if k == 0 || self.is_empty() {
+            return vec![];
+        }
+
+        let strategy = match mode {
+            RetrievalMode::Vector => return self.search_scored(query_vector, k, filter),
+            RetrievalMode::Lexical => {
+                let lexical = self.lexical_search(query_text, k, filter);
+                return self.with_distances(lexical.iter().map(|(id, _)| *id), query_vector, &[]);
+            }
+            RetrievalMode::Hybrid(strategy) => strategy,
+        };
+
+        // Over-fetch from each ranker so fusion can promote items ranked lower by one.
+        let candidates = (k * 4).max(20);
+        let vector: Vec<(u64, f32)> = self
+            .search_scored(query_vector, candidates, filter)
+            .into_iter()
+            .map(|(id, distance, _)| (id, distance))
+            .collect();
+        let lexical = self.lexical_search(query_text, candidates, filter);
+
+        let fused = fuse(&vector, &lexical, strategy, k);
+        self.with_distances(fused.into_iter(), query_vector, &vector)
+    }
+
+    fn lexical_search(&self, query_text: &str, k: usize, filter: &MemoryFilter) -> Vec<(u64, f32)> {
+        self.lexical.search(query_text, k, |id| {
+            self.positions
+                .get(&id)
+                .is_some_and(|&pos| filter.matches(&self.items[pos].metadata))
+        })
+    }
+
+    /// Resolves ids to `(id, distance, content)`, reusing distances already computed.
+    fn with_distances(
+        &self,
+        ids: impl Iterator<Item = u64>,
+        query_vector: &[f32],
+        known: &[(u64, f32)],
+    ) -> Vec<(u64, f32, String)> {
+        ids.filter_map(|id| {
+            let item = &self.items[*self.positions.get(&id)?];
+            let distance = known
+                .iter()
+                .find(|(known_id, _)| *known_id == id)
+                .map(|(_, d)| *d)
+                .unwrap_or_else(|| self.metric.distance(&item.embedding, query_vector));
+            Some((id, distance, item.content.clone()))
+        })
+        .collect()
+    }
+
+    /// Returns the metadata stored for memory `id`.
+    pub fn metadata(&self, id: u64) -> Option<&MemoryMetadata> {
+        self.get(id).map(|(_, metadata)| metadata)
+    }
+
+    fn filtered_search(
+        &self,
+        query_vector: &[f32],
+        k: usize,
+        filter: &MemoryFilter,
+    ) -> Vec<(f32, u32)> {
+        // Metadata checks are cheap compared to distance computations, so measure
+        // selectivity first: a selective filter is served faster by scanning its matches.
+        let matching: Vec<bool> = self
+            .items
+            .iter()
+            .map(|it| !it.deleted && filter.matches(&it.metadata))
+            .collect();
+        let match_count = matching.iter().filter(|m| **m).count();
+        if match_count == 0 {
+            return vec![];
+        }
+
+        if let (Some(graph), IndexMode::Hnsw(params)) = (&self.hnsw, self.mode) {
+            if match_count >= params.exact_scan_threshold {
+                let ranked =
+                    graph.search_filtered(query_vector, k, self.items.as_slice(), &|node| {
+                        matching[node as usize]
+                    });
+                if ranked.len() >= k.min(match_count) {
+                    return ranked;
+                }
+                warn!(
+                    found = ranked.len(),
+                    matching = match_count,
+                    "rag_hnsw_filtered_search_short"
+                );
+            }
+        }
+
+        self.exact_search(query_vector, k, |pos| matching[pos])
+    }
+
+    /// Full-scan top-k over positions passing `accept`. Returns `(distance, position)`
+    /// pairs sorted by distance.
+    fn exact_search(
+        &self,
+        query_vector: &[f32],
+        k: usize,
+        accept: impl Fn(usize) -> bool + Sync,
+    ) -> Vec<(f32, u32)> {
         // Compute distances (optionally parallel for larger memory sets).
-        let mut distances: Vec<(f32, u64)> = if self.items.len() >= 64 {
+        let mut distances: Vec<(f32, u32)> = if self.items.len() >= 64 {
             self.items
                 .par_iter()
-                .map(|item| (euclidean_distance(&item.embedding, query_vector), item.id))
+                .enumerate()
+                .filter(|(pos, _)| accept(*pos))
+                .map(|(pos, item)| {
+                    (
+                        self.metric.distance(&item.embedding, query_vector),
+                        pos as u32,
+                    )
+                })
                 .collect()
         } else {
             self.items
                 .iter()
-                .map(|item| (euclidean_distance(&item.embedding, query_vector), item.id))
+                .enumerate()
+                .filter(|(pos, _)| accept(*pos))
+                .map(|(pos, item)| {
+                    (
+                        self.metric.distance(&item.embedding, query_vector),
+                        pos as u32,
+                    )
+                })
                 .collect()
         };
 
         distances.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
-
+        distances.truncate(k);
         distances
+    }
+
+    /// Snapshot of the live memories for the binary segment format.
+    pub fn to_segment(&self) -> Segment {
+        Segment {
+            dimension: self.dimension().unwrap_or(0),
+            next_id: self.next_id,
+            metric: self.metric,
+            embedder_id: self.embedder_id.clone(),
+            records: self
+                .items
+                .iter()
+                .filter(|it| !it.deleted)
+                .map(|it| SegmentRecord {
+                    id: it.id,
+                    embedding: it.embedding.clone(),
+                    content: it.content.clone(),
+                    metadata: it.metadata.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Rebuilds an index (in exact mode) from a decoded segment.
+    pub fn from_segment(segment: Segment) -> Self {
+        let mut index = VectorIndex::new();
+        index.metric = segment.metric;
+        index.embedder_id = segment.embedder_id;
+        index.items = segment
+            .records
             .into_iter()
-            .take(k)
-            .filter_map(|(_, id)| self.items.iter().find(|it| it.id == id))
-            .map(|it| it.content.clone())
-            .collect()
+            .map(|r| MemoryItem {
+                id: r.id,
+                embedding: r.embedding,
+                content: r.content,
+                metadata: r.metadata,
+                deleted: false,
+            })
+            .collect();
+        index.next_id = segment
+            .next_id
+            .max(index.items.iter().map(|it| it.id + 1).max().unwrap_or(0));
+        index.rebuild_positions();
+        index.rebuild_lexical();
+        index.mark_segment_written();
+        index
+    }
+
+    /// Applies one write-ahead log record. Replays are idempotent: re-adding an id that
+    /// is already live is ignored.
+    pub fn apply_wal(&mut self, record: WalRecord) {
+        match record {
+            WalRecord::Add(r) => {
+                if self.positions.contains_key(&r.id) {
+                    return;
+                }
+                self.next_id = self.next_id.max(r.id + 1);
+                self.push_item(MemoryItem {
+                    id: r.id,
+                    embedding: r.embedding,
+                    content: r.content,
+                    metadata: r.metadata,
+                    deleted: false,
+                });
+            }
+            WalRecord::Update {
+                id,
+                content,
+                embedding,
+            } => {
+                self.update(id, content, embedding);
+            }
+            WalRecord::Delete { id } => {
+                self.delete(id);
+            }
+        }
     }
 
     pub fn to_json_bytes(&self) -> Result<Vec<u8>> {
@@ -74,19 +688,190 @@ impl VectorIndex {
     }
 
     pub fn from_json_bytes(bytes: &[u8]) -> Result<Self> {
-        Ok(serde_json::from_slice(bytes)?)
+        let mut index: VectorIndex = serde_json::from_slice(bytes)?;
+        index.rebuild_positions();
+        if index.lexical.len() != index.positions.len() {
+            index.rebuild_lexical();
+        }
+        Ok(index)
+    }
+
+    /// Serializes the HNSW graph (if any) for the sidecar file.
+    pub fn hnsw_to_json_bytes(&self) -> Result<Option<Vec<u8>>> {
+        self.hnsw.as_ref().map(HnswGraph::to_json_bytes).transpose()
     }
-}
 
-fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
-    // Treat mismatched dims as min-dim comparison.
-    let n = a.len().min(b.len());
-    let sum_sq: f32 = a
-        .iter()
-        .take(n)
-        .zip(b.iter().take(n))
-        .map(|(x, y)| (x - y) * (x - y))
-        .sum();
-    sum_sq.sqrt()
+    /// Restores a persisted HNSW graph. Stale or unreadable graphs are discarded
+    /// and rebuilt by the next `set_mode` call.
+    pub fn restore_hnsw_json_bytes(&mut self, bytes: &[u8]) {
+        match HnswGraph::from_json_bytes(bytes) {
+            Ok(graph) if graph.len() == self.items.len() && graph.metric() == self.metric => {
+                self.hnsw = Some(graph)
+            }
+            Ok(graph) => {
+                warn!(
+                    graph_nodes = graph.len(),
+                    items = self.items.len(),
+                    "rag_hnsw_graph_stale"
+                );
+            }
+            Err(e) => warn!(error = %e, "rag_hnsw_graph_unreadable"),
+        }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::fusion::FusionStrategy;
+    use crate::rag::metadata::MemorySource;
+
+    const HYBRID: RetrievalMode = RetrievalMode::Hybrid(FusionStrategy::ReciprocalRank { k: 60.0 });
+
+    /// Five memories close to `[1, 0]` in vector space and one far away that is the only
+    /// one naming "Sparky".
+    fn pets_index() -> (VectorIndex, u64) {
+        let mut index = VectorIndex::new();
+        for i in 0..5 {
+            index.add(
+                format!("I love animals, note {i}"),
+                vec![1.0, 0.1 * i as f32],
+            );
+        }
+        let sparky = index.add("My dog is called Sparky".to_string(), vec![0.0, 1.0]);
+        (index, sparky)
+    }
+
+    fn ids(hits: &[(u64, f32, String)]) -> Vec<u64> {
+        hits.iter().map(|hit| hit.0).collect()
+    }
+
+    /// Three memories the metrics order differently from `[1, 0]`: cosine prefers the
+    /// best-aligned `small`, dot product the large `far`, L2 the nearest point `tilted`.
+    fn metric_index(metric: DistanceMetric) -> VectorIndex {
+        let mut index = VectorIndex::with_config(metric, IndexMode::Exact);
+        index.add("far".to_string(), vec![3.0, 3.0]);
+        index.add("small".to_string(), vec![0.5, 0.1]);
+        index.add("tilted".to_string(), vec![1.0, -0.5]);
+        index
+    }
+
+    #[test]
+    fn each_metric_ranks_closest_first() {
+        for (metric, order, nearest) in [
+            (
+                DistanceMetric::Cosine,
+                ["small", "tilted", "far"],
+                1.0 - 0.5 / 0.26f32.sqrt(),
+            ),
+            (DistanceMetric::DotProduct, ["far", "tilted", "small"], -3.0),
+            (DistanceMetric::L2, ["tilted", "small", "far"], 0.5),
+        ] {
+            let hits = metric_index(metric).search_scored(&[1.0, 0.0], 3, &MemoryFilter::default());
+
+            let contents: Vec<&str> = hits.iter().map(|hit| hit.2.as_str()).collect();
+            assert_eq!(contents, order, "{metric:?}");
+            assert!(
+                (hits[0].1 - nearest).abs() < 1e-5,
+                "{metric:?}: {}",
+                hits[0].1
+            );
+            assert!(hits.windows(2).all(|w| w[0].1 <= w[1].1), "{metric:?}");
+        }
+    }
+
+    /// Fifty agent memories near the query, and every sixth one a user memory about
+    /// pets further away.
+    fn mixed_index(mode: IndexMode) -> VectorIndex {
+        let mut index = VectorIndex::with_config(DistanceMetric::L2, mode);
+        for i in 0..60 {
+            let metadata = match i % 6 {
+                0 => MemoryMetadata::new(MemorySource::User).with_tags(["pet"]),
+                _ => MemoryMetadata::new(MemorySource::Agent),
+            };
+            let x = if i % 6 == 0 { 5.0 } else { 0.0 } + i as f32 * 0.01;
+            index.add_with_metadata(format!("memory {i}"), vec![x, 0.0], metadata);
+        }
+        index
+    }
+
+    #[test]
+    fn filter_skips_nearer_memories_and_still_fills_k() {
+        let hnsw = IndexMode::Hnsw(HnswParams {
+            exact_scan_threshold: 0,
+            ..HnswParams::default()
+        });
+        let filters = [
+            MemoryFilter::new().with_source(MemorySource::User),
+            MemoryFilter::new().with_tags(["PET"]),
+        ];
+        for mode in [IndexMode::Exact, hnsw] {
+            let index = mixed_index(mode);
+            for filter in &filters {
+                let hits = index.search_scored(&[0.0, 0.0], 5, filter);
+
+                let contents: Vec<&str> = hits.iter().map(|hit| hit.2.as_str()).collect();
+                assert_eq!(
+                    contents,
+                    [
+                        "memory 0",
+                        "memory 6",
+                        "memory 12",
+                        "memory 18",
+                        "memory 24"
+                    ],
+                    "{mode:?} {filter:?}"
+                );
+            }
+
+            // Fewer matches than k returns every match.
+            let hits = index.search_scored(&[0.0, 0.0], 50, &filters[0]);
+            assert_eq!(hits.len(), 10, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn search_scored_respects_k_and_tombstones() {
+        let mut index = metric_index(DistanceMetric::L2);
+        let filter = MemoryFilter::default();
+        assert_eq!(index.search_scored(&[1.0, 0.0], 1, &filter).len(), 1);
+        assert!(index.search_scored(&[1.0, 0.0], 0, &filter).is_empty());
+
+        let tilted = index.search_scored(&[1.0, 0.0], 1, &filter)[0].0;
+        index.delete(tilted);
+        let hits = index.search_scored(&[1.0, 0.0], 3, &filter);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].2, "small");
+    }
+
+    #[test]
+    fn exact_keyword_match_outranks_dense_neighbours_in_hybrid_mode() {
+        let (index, sparky) = pets_index();
+        let filter = MemoryFilter::default();
+
+        let vector = index.search_hybrid(&[1.0, 0.0], "Sparky", 3, &filter, RetrievalMode::Vector);
+        assert!(!ids(&vector).contains(&sparky));
+
+        let hybrid = index.search_hybrid(&[1.0, 0.0], "Sparky", 3, &filter, HYBRID);
+        assert_eq!(hybrid[0].0, sparky);
+        // Distances stay metric distances whatever the ranking.
+        assert!((hybrid[0].1 - 2f32.sqrt()).abs() < 1e-6);
+
+        let lexical =
+            index.search_hybrid(&[1.0, 0.0], "Sparky", 3, &filter, RetrievalMode::Lexical);
+        assert_eq!(ids(&lexical), vec![sparky]);
+    }
+
+    #[test]
+    fn hybrid_ranks_are_deterministic() {
+        let (index, _) = pets_index();
+        let filter = MemoryFilter::default();
+
+        let first = index.search_hybrid(&[0.7, 0.7], "animals dog", 6, &filter, HYBRID);
+        assert_eq!(first.len(), 6);
+        for _ in 0..10 {
+            let again = index.search_hybrid(&[0.7, 0.7], "animals dog", 6, &filter, HYBRID);
+            assert_eq!(ids(&again), ids(&first));
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rag::text::index_terms;
+
+/// BM25 term-frequency saturation.
+const BM25_K1: f32 = 1.2;
+/// BM25 document-length normalization.
+const BM25_B: f32 = 0.75;
+
+/// Inverted index scoring memories with Okapi BM25.
+///
+/// Complements the vector index for short, name-heavy queries ("Sparky", "Tuesday")
+/// where exact term matches matter more than embedding similarity.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Bm25Index {
+    /// term -> (memory id -> term frequency)
+    postings: HashMap<String, HashMap<u64, u32>>,
+    /// memory id -> number of indexed terms
+    doc_lengths: HashMap<u64, u32>,
+    total_length: u64,
+}
+
+impl Bm25Index {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of indexed memories.
+    pub fn len(&self) -> usize {
+        self.doc_lengths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.doc_lengths.is_empty()
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.doc_lengths.contains_key(&id)
+    }
+
+    pub fn add(&mut self, id: u64, text: &str) {
+        if self.contains(id) {
+            self.remove(id);
+        }
+
+        let terms = index_terms(text);
+        for term in &terms {
+            *self
+                .postings
+                .entry(term.clone())
+                .or_default()
+                .entry(id)
+                .or_default() += 1;
+        }
+        self.doc_lengths.insert(id, terms.len() as u32);
+        self.total_length += terms.len() as u64;
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        let Some(length) = self.doc_lengths.remove(&id) else {
+            return;
+        };
+        self.total_length -= u64::from(length);
+        self.postings.retain(|_, docs| {
+            docs.remove(&id);
+            !docs.is_empty()
+        });
+    }
+
+    /// Top-k BM25 matches among memories passing `accept`, as `(id, score)` with the
+    /// highest score first. Memories sharing no term with the query are not returned.
+    pub fn search(&self, query: &str, k: usize, accept: impl Fn(u64) -> bool) -> Vec<(u64, f32)> {
+        if self.is_empty() || k == 0 {
+            return vec![];
+        }
+
+        let doc_count = self.doc_lengths.len() as f32;
+        let avg_length = (self.total_length as f32 / doc_count).max(1.0);
+
+        let mut query_terms = index_terms(query);
+        query_terms.sort();
+        query_terms.dedup();
+
+        let mut scores: HashMap<u64, f32> = HashMap::new();
+        for term in &query_terms {
+            let Some(docs) = self.postings.get(term) else {
+                continue;
+            };
+            let df = docs.len() as f32;
+            let idf = ((doc_count - df + 0.5) / (df + 0.5) + 1.0).ln();
+
+            for (&id, &tf) in docs {
+                if !accept(id) {
+                    continue;
+                }
+                let tf = tf as f32;
+                let length = self.doc_lengths.get(&id).copied().unwrap_or(0) as f32;
+                let norm = BM25_K1 * (1.0 - BM25_B + BM25_B * length / avg_length);
+                *scores.entry(id).or_default() += idf * tf * (BM25_K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut ranked: Vec<(u64, f32)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.0.cmp(&b.0))
+        });
+        ranked.truncate(k);
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> Bm25Index {
+        let mut index = Bm25Index::new();
+        index.add(1, "My dog is called Sparky");
+        index.add(2, "I walk the dog every morning before work");
+        index.add(3, "Tuesday is my favourite day");
+        index
+    }
+
+    #[test]
+    fn rare_terms_score_higher_than_common_ones() {
+        let index = index();
+
+        let hits = index.search("dog Sparky", 10, |_| true);
+
+        assert_eq!(hits[0].0, 1);
+        assert_eq!(hits.len(), 2);
+        assert!(hits[0].1 > hits[1].1);
+    }
+
+    #[test]
+    fn stop_words_and_unmatched_memories_are_not_returned() {
+        let index = index();
+
+        assert!(index.search("the is my", 10, |_| true).is_empty());
+        assert_eq!(
+            index.search("tuesday", 10, |_| true),
+            index.search("Tuesday!", 10, |_| true)
+        );
+    }
+
+    #[test]
+    fn remove_and_re_add_replace_a_memory() {
+        let mut index = index();
+
+        index.remove(1);
+        assert!(index.search("sparky", 10, |_| true).is_empty());
+        assert_eq!(index.len(), 2);
+
+        index.add(2, "Sparky chased a cat");
+        index.add(2, "Sparky chased a cat");
+        assert_eq!(index.len(), 2);
+        assert!(index.search("morning", 10, |_| true).is_empty());
+        assert_eq!(index.search("sparky", 10, |_| true)[0].0, 2);
+    }
+
+    #[test]
+    fn accept_filters_hits() {
+        let index = index();
+
+        let hits = index.search("dog", 10, |id| id != 1);
+
+        assert_eq!(hits.iter().map(|h| h.0).collect::<Vec<_>>(), vec![2]);
+    }
+}
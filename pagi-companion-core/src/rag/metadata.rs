@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+
+/// Who a memory originates from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MemorySource {
+    /// Something the user said or revealed about themselves.
+    #[default]
+    User,
+    /// Something the companion said, decided or observed.
+    Agent,
+    /// Something the model inferred about the user from a turn (a suggested memory),
+    /// which the user did not necessarily state.
+    Inferred,
+}
+
+/// Typed metadata attached to every episodic memory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemoryMetadata {
+    /// Unix timestamp (seconds) when the memory was recorded.
+    pub timestamp: i64,
+    pub source: MemorySource,
+    /// 0.0 (trivia) to 1.0 (core fact about the user).
+    pub importance: f32,
+    /// Lowercase topic tags (e.g. "pet", "work").
+    pub tags: Vec<String>,
+    /// -1.0 (painful) to 1.0 (joyful).
+    pub emotional_valence: f32,
+}
+
+impl Default for MemoryMetadata {
+    fn default() -> Self {
+        MemoryMetadata {
+            timestamp: chrono::Utc::now().timestamp(),
+            source: MemorySource::User,
+            importance: 0.5,
+            tags: Vec::new(),
+            emotional_valence: 0.0,
+        }
+    }
+}
+
+impl MemoryMetadata {
+    pub fn new(source: MemorySource) -> Self {
+        MemoryMetadata {
+            source,
+            ..Default::default()
+        }
+    }
+
+    /// Metadata for memories persisted before metadata existed.
+    ///
+    /// The timestamp is unknown, so it is pinned to the epoch rather than "now" to keep
+    /// legacy memories out of recent-time filters.
+    pub fn legacy() -> Self {
+        MemoryMetadata {
+            timestamp: 0,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.tags = tags
+            .into_iter()
+            .map(|t| normalize_tag(t.as_ref()))
+            .collect();
+        self
+    }
+
+    pub fn with_importance(mut self, importance: f32) -> Self {
+        self.importance = importance.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_emotional_valence(mut self, emotional_valence: f32) -> Self {
+        self.emotional_valence = emotional_valence.clamp(-1.0, 1.0);
+        self
+    }
+}
+
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// Filter applied to episodic memories before similarity ranking.
+///
+/// Every populated field must match; the default filter matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryFilter {
+    /// Inclusive lower bound on `timestamp`.
+    pub since: Option<i64>,
+    /// Inclusive upper bound on `timestamp`.
+    pub until: Option<i64>,
+    /// Matches memories carrying at least one of these tags (empty = any).
+    pub tags: Vec<String>,
+    pub min_importance: Option<f32>,
+    pub source: Option<MemorySource>,
+}
+
+impl MemoryFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restricts to memories recorded within the last `days` days.
+    pub fn within_last_days(mut self, days: i64) -> Self {
+        self.since = Some(chrono::Utc::now().timestamp() - days * 24 * 3600);
+        self
+    }
+
+    pub fn with_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.tags = tags
+            .into_iter()
+            .map(|t| normalize_tag(t.as_ref()))
+            .collect();
+        self
+    }
+
+    pub fn with_min_importance(mut self, min_importance: f32) -> Self {
+        self.min_importance = Some(min_importance);
+        self
+    }
+
+    pub fn with_source(mut self, source: MemorySource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// True when the filter matches every memory.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    pub fn matches(&self, metadata: &MemoryMetadata) -> bool {
+        if self.since.is_some_and(|since| metadata.timestamp < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| metadata.timestamp > until) {
+            return false;
+        }
+        if self
+            .min_importance
+            .is_some_and(|min| metadata.importance < min)
+        {
+            return false;
+        }
+        if self.source.is_some_and(|source| metadata.source != source) {
+            return false;
+        }
+        if !self.tags.is_empty()
+            && !metadata
+                .tags
+                .iter()
+                .any(|t| self.tags.iter().any(|f| f.eq_ignore_ascii_case(t)))
+        {
+            return false;
+        }
+        true
+    }
+}
@@ -1,3 +1,11 @@
+pub mod config;
+pub mod distance;
 pub mod embedding;
+pub mod fusion;
+pub mod hnsw;
+pub mod http_embedder;
 pub mod index;
-
+pub mod lexical;
+pub mod metadata;
+pub mod segment;
+pub mod text;
@@ -0,0 +1,575 @@
+use anyhow::{anyhow, bail, Result};
+
+use crate::rag::distance::DistanceMetric;
+use crate::rag::metadata::MemoryMetadata;
+
+/// Leading bytes of a segment file (`{user}_rag.seg`).
+pub const SEGMENT_MAGIC: &[u8; 8] = b"PAGISEG\0";
+/// Leading bytes of a write-ahead log file (`{user}_rag.wal`).
+pub const WAL_MAGIC: &[u8; 8] = b"PAGIWAL\0";
+/// Newest on-disk format version this build reads and writes.
+pub const FORMAT_VERSION: u32 = 1;
+
+const OP_ADD: u8 = 1;
+const OP_UPDATE: u8 = 2;
+const OP_DELETE: u8 = 3;
+
+/// One live memory as stored in a segment or logged by `WalRecord::Add`.
+#[derive(Debug, Clone)]
+pub struct SegmentRecord {
+    pub id: u64,
+    pub embedding: Vec<f32>,
+    pub content: String,
+    pub metadata: MemoryMetadata,
+}
+
+/// Compacted snapshot of one user's episodic index.
+///
+/// Layout (little-endian):
+/// - header: `magic[8] version:u32 dimension:u32 count:u64 next_id:u64 metric:u8 embedder_id:str16`
+/// - ids: `count x u64`
+/// - vectors: `count x dimension x f32`, packed
+/// - blob: `count x (content:str32 metadata_json:str32)`
+/// - trailer: `crc32:u32` over everything before it
+///
+/// `strN` is a `uN` byte length followed by UTF-8 bytes.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub dimension: usize,
+    pub next_id: u64,
+    pub metric: DistanceMetric,
+    pub embedder_id: Option<String>,
+    pub records: Vec<SegmentRecord>,
+}
+
+impl Segment {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        if let Some(bad) = self
+            .records
+            .iter()
+            .find(|r| r.embedding.len() != self.dimension)
+        {
+            bail!(
+                "Memory {} has {} dims, segment dimension is {}",
+                bad.id,
+                bad.embedding.len(),
+                self.dimension
+            );
+        }
+
+        let mut out = Vec::with_capacity(64 + self.records.len() * (8 + self.dimension * 4 + 128));
+        out.extend_from_slice(SEGMENT_MAGIC);
+        put_u32(&mut out, FORMAT_VERSION);
+        put_u32(&mut out, self.dimension as u32);
+        put_u64(&mut out, self.records.len() as u64);
+        put_u64(&mut out, self.next_id);
+        out.push(metric_to_byte(self.metric));
+        put_str16(&mut out, self.embedder_id.as_deref().unwrap_or(""))?;
+
+        for record in &self.records {
+            put_u64(&mut out, record.id);
+        }
+        for record in &self.records {
+            put_f32s(&mut out, &record.embedding);
+        }
+        for record in &self.records {
+            put_str32(&mut out, &record.content)?;
+            put_str32(&mut out, &serde_json::to_string(&record.metadata)?)?;
+        }
+
+        let crc = crc32fast::hash(&out);
+        put_u32(&mut out, crc);
+        Ok(out)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < SEGMENT_MAGIC.len() + 4 || &bytes[..SEGMENT_MAGIC.len()] != SEGMENT_MAGIC {
+            bail!("Not a RAG segment file");
+        }
+        let (body, trailer) = bytes.split_at(bytes.len() - 4);
+        let expected = u32::from_le_bytes(trailer.try_into().unwrap());
+        if crc32fast::hash(body) != expected {
+            bail!("RAG segment checksum mismatch");
+        }
+
+        let mut reader = Reader::new(&body[SEGMENT_MAGIC.len()..]);
+        let version = reader.u32()?;
+        if version > FORMAT_VERSION {
+            bail!("Unsupported RAG segment version {version} (newest supported: {FORMAT_VERSION})");
+        }
+        let dimension = reader.u32()? as usize;
+        let count = reader.u64()? as usize;
+        let next_id = reader.u64()?;
+        let metric = metric_from_byte(reader.u8()?)?;
+        let embedder_id = Some(reader.str16()?).filter(|id| !id.is_empty());
+
+        let ids = (0..count)
+            .map(|_| reader.u64())
+            .collect::<Result<Vec<_>>>()?;
+        let embeddings = (0..count)
+            .map(|_| reader.f32s(dimension))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut records = Vec::with_capacity(count);
+        for (id, embedding) in ids.into_iter().zip(embeddings) {
+            let content = reader.str32()?;
+            let metadata = serde_json::from_str(&reader.str32()?)?;
+            records.push(SegmentRecord {
+                id,
+                embedding,
+                content,
+                metadata,
+            });
+        }
+        reader.finish()?;
+
+        Ok(Segment {
+            dimension,
+            next_id,
+            metric,
+            embedder_id,
+            records,
+        })
+    }
+}
+
+/// A change made after the last compaction, appended to the write-ahead log.
+///
+/// Replaying the whole log in order is idempotent, so a crash between writing a new
+/// segment and truncating the log loses nothing.
+#[derive(Debug, Clone)]
+pub enum WalRecord {
+    Add(SegmentRecord),
+    Update {
+        id: u64,
+        content: String,
+        embedding: Vec<f32>,
+    },
+    Delete {
+        id: u64,
+    },
+}
+
+impl WalRecord {
+    /// Header written once at the start of every log file.
+    pub fn log_header() -> Vec<u8> {
+        let mut out = WAL_MAGIC.to_vec();
+        put_u32(&mut out, FORMAT_VERSION);
+        out
+    }
+
+    /// Encodes the record as a frame: `len:u32 crc32:u32 payload`.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        self.encode_with(|payload| Ok(payload.to_vec()))
+    }
+
+    /// Like `encode`, but passes the payload through `seal` (e.g. encryption) before
+    /// framing, so the checksum covers the stored bytes.
+    pub fn encode_with(&self, seal: impl Fn(&[u8]) -> Result<Vec<u8>>) -> Result<Vec<u8>> {
+        let mut payload = Vec::new();
+        match self {
+            WalRecord::Add(record) => {
+                payload.push(OP_ADD);
+                put_u64(&mut payload, record.id);
+                put_str32(&mut payload, &record.content)?;
+                put_str32(&mut payload, &serde_json::to_string(&record.metadata)?)?;
+                put_u32(&mut payload, record.embedding.len() as u32);
+                put_f32s(&mut payload, &record.embedding);
+            }
+            WalRecord::Update {
+                id,
+                content,
+                embedding,
+            } => {
+                payload.push(OP_UPDATE);
+                put_u64(&mut payload, *id);
+                put_str32(&mut payload, content)?;
+                put_u32(&mut payload, embedding.len() as u32);
+                put_f32s(&mut payload, embedding);
+            }
+            WalRecord::Delete { id } => {
+                payload.push(OP_DELETE);
+                put_u64(&mut payload, *id);
+            }
+        }
+
+        Ok(encode_frame(&seal(&payload)?))
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(payload);
+        let record = match reader.u8()? {
+            OP_ADD => {
+                let id = reader.u64()?;
+                let content = reader.str32()?;
+                let metadata = serde_json::from_str(&reader.str32()?)?;
+                let dimension = reader.u32()? as usize;
+                WalRecord::Add(SegmentRecord {
+                    id,
+                    embedding: reader.f32s(dimension)?,
+                    content,
+                    metadata,
+                })
+            }
+            OP_UPDATE => {
+                let id = reader.u64()?;
+                let content = reader.str32()?;
+                let dimension = reader.u32()? as usize;
+                WalRecord::Update {
+                    id,
+                    content,
+                    embedding: reader.f32s(dimension)?,
+                }
+            }
+            OP_DELETE => WalRecord::Delete { id: reader.u64()? },
+            op => bail!("Unknown WAL operation {op}"),
+        };
+        reader.finish()?;
+        Ok(record)
+    }
+
+    /// Decodes a log file, returning its records and the length of the valid prefix.
+    ///
+    /// A torn final frame (e.g. a crash mid-append) ends the log instead of failing it;
+    /// callers should truncate the file to the returned length. A corrupt frame before
+    /// the end, or an intact frame whose record does not decode, is an error.
+    pub fn decode_log(bytes: &[u8]) -> Result<(Vec<WalRecord>, usize)> {
+        Self::decode_log_with(bytes, |payload| Ok(payload.to_vec()))
+    }
+
+    /// Like `decode_log`, but passes each intact payload through `open` (the inverse of
+    /// the `seal` given to `encode_with`). `open` failures are errors, not torn tails.
+    pub fn decode_log_with(
+        bytes: &[u8],
+        open: impl Fn(&[u8]) -> Result<Vec<u8>>,
+    ) -> Result<(Vec<WalRecord>, usize)> {
+        if bytes.is_empty() {
+            return Ok((vec![], 0));
+        }
+        let header = Self::log_header();
+        if bytes.len() < header.len() || &bytes[..WAL_MAGIC.len()] != WAL_MAGIC {
+            bail!("Not a RAG write-ahead log");
+        }
+        let version = u32::from_le_bytes(bytes[WAL_MAGIC.len()..header.len()].try_into().unwrap());
+        if version > FORMAT_VERSION {
+            bail!("Unsupported RAG log version {version} (newest supported: {FORMAT_VERSION})");
+        }
+
+        let mut records = Vec::new();
+        let mut pos = header.len();
+        for (payload, end) in read_frames(bytes, pos)? {
+            let record = Self::decode(&open(payload)?)
+                .map_err(|e| anyhow!("Invalid RAG log record at offset {pos}: {e}"))?;
+            records.push(record);
+            pos = end;
+        }
+        Ok((records, pos))
+    }
+}
+
+/// Frames `payload` for an append-only log: `len:u32 crc32:u32 payload`.
+pub(crate) fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 8);
+    put_u32(&mut frame, payload.len() as u32);
+    put_u32(&mut frame, crc32fast::hash(payload));
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Intact frames of `bytes` from offset `pos`, each with the offset just past it.
+///
+/// A torn tail ends the frames: a final frame that is short or fails its checksum. A
+/// checksum failure with more data after the frame is corruption and an error.
+pub(crate) fn read_frames(bytes: &[u8], mut pos: usize) -> Result<Vec<(&[u8], usize)>> {
+    let mut frames = Vec::new();
+    while let Some(frame) = bytes.get(pos..pos + 8) {
+        let len = u32::from_le_bytes(frame[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(frame[4..].try_into().unwrap());
+        let Some(payload) = bytes.get(pos + 8..pos + 8 + len) else {
+            break;
+        };
+        if crc32fast::hash(payload) != crc {
+            if pos + 8 + len < bytes.len() {
+                bail!("Corrupt log frame at offset {pos} (checksum mismatch)");
+            }
+            break;
+        }
+        pos += 8 + len;
+        frames.push((payload, pos));
+    }
+    Ok(frames)
+}
+
+fn metric_to_byte(metric: DistanceMetric) -> u8 {
+    match metric {
+        DistanceMetric::Cosine => 0,
+        DistanceMetric::DotProduct => 1,
+        DistanceMetric::L2 => 2,
+    }
+}
+
+fn metric_from_byte(byte: u8) -> Result<DistanceMetric> {
+    Ok(match byte {
+        0 => DistanceMetric::Cosine,
+        1 => DistanceMetric::DotProduct,
+        2 => DistanceMetric::L2,
+        other => bail!("Unknown distance metric tag {other}"),
+    })
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f32s(out: &mut Vec<u8>, values: &[f32]) {
+    for value in values {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn put_str16(out: &mut Vec<u8>, value: &str) -> Result<()> {
+    let Ok(len) = u16::try_from(value.len()) else {
+        bail!(
+            "String of {} bytes is too long for the segment header",
+            value.len()
+        );
+    };
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+fn put_str32(out: &mut Vec<u8>, value: &str) -> Result<()> {
+    let Ok(len) = u32::try_from(value.len()) else {
+        bail!("String of {} bytes is too long to store", value.len());
+    };
+    put_u32(out, len);
+    out.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+/// Bounds-checked little-endian cursor.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let Some(slice) = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.pos..end))
+        else {
+            bail!("Truncated RAG record at byte {}", self.pos);
+        };
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32s(&mut self, count: usize) -> Result<Vec<f32>> {
+        let bytes = self.take(count.saturating_mul(4))?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect())
+    }
+
+    fn str16(&mut self) -> Result<String> {
+        let len = u16::from_le_bytes(self.take(2)?.try_into().unwrap()) as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+
+    fn str32(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+
+    fn finish(&self) -> Result<()> {
+        if self.pos != self.bytes.len() {
+            bail!(
+                "{} trailing bytes after RAG record",
+                self.bytes.len() - self.pos
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: u64) -> SegmentRecord {
+        SegmentRecord {
+            id,
+            embedding: vec![id as f32, 0.5, -1.0],
+            content: format!("memory {id} ✓"),
+            metadata: MemoryMetadata::legacy(),
+        }
+    }
+
+    fn log(records: &[WalRecord]) -> Vec<u8> {
+        let mut bytes = WalRecord::log_header();
+        for record in records {
+            bytes.extend(record.encode().unwrap());
+        }
+        bytes
+    }
+
+    fn ids(records: &[WalRecord]) -> Vec<u64> {
+        records
+            .iter()
+            .map(|r| match r {
+                WalRecord::Add(r) => r.id,
+                WalRecord::Update { id, .. } | WalRecord::Delete { id } => *id,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn segment_round_trip() {
+        let segment = Segment {
+            dimension: 3,
+            next_id: 9,
+            metric: DistanceMetric::DotProduct,
+            embedder_id: Some("hashed-ngram-v1".to_string()),
+            records: vec![record(2), record(7)],
+        };
+
+        let decoded = Segment::decode(&segment.encode().unwrap()).unwrap();
+
+        assert_eq!(decoded.dimension, 3);
+        assert_eq!(decoded.next_id, 9);
+        assert_eq!(decoded.metric, DistanceMetric::DotProduct);
+        assert_eq!(decoded.embedder_id.as_deref(), Some("hashed-ngram-v1"));
+        assert_eq!(decoded.records.len(), 2);
+        for (decoded, original) in decoded.records.iter().zip(&segment.records) {
+            assert_eq!(decoded.id, original.id);
+            assert_eq!(decoded.embedding, original.embedding);
+            assert_eq!(decoded.content, original.content);
+            assert_eq!(decoded.metadata, original.metadata);
+        }
+    }
+
+    #[test]
+    fn segment_rejects_flipped_bits_and_wrong_dimensions() {
+        let segment = Segment {
+            dimension: 3,
+            next_id: 1,
+            metric: DistanceMetric::Cosine,
+            embedder_id: None,
+            records: vec![record(0)],
+        };
+        let mut bytes = segment.encode().unwrap();
+        bytes[SEGMENT_MAGIC.len() + 30] ^= 1;
+        let err = Segment::decode(&bytes).unwrap_err();
+        assert!(err.to_string().contains("checksum"), "{err}");
+
+        let mut wide = segment.clone();
+        wide.records[0].embedding.push(1.0);
+        assert!(wide.encode().is_err());
+    }
+
+    #[test]
+    fn wal_round_trip() {
+        let records = vec![
+            WalRecord::Add(record(4)),
+            WalRecord::Update {
+                id: 4,
+                content: "edited".to_string(),
+                embedding: vec![1.0, 2.0, 3.0],
+            },
+            WalRecord::Delete { id: 4 },
+        ];
+        let bytes = log(&records);
+
+        let (decoded, valid_len) = WalRecord::decode_log(&bytes).unwrap();
+
+        assert_eq!(valid_len, bytes.len());
+        assert_eq!(ids(&decoded), vec![4, 4, 4]);
+        match &decoded[1] {
+            WalRecord::Update {
+                content, embedding, ..
+            } => {
+                assert_eq!(content, "edited");
+                assert_eq!(embedding, &vec![1.0, 2.0, 3.0]);
+            }
+            other => panic!("expected an update, got {other:?}"),
+        }
+        assert!(matches!(decoded[2], WalRecord::Delete { id: 4 }));
+    }
+
+    #[test]
+    fn torn_final_frame_ends_the_log() {
+        let bytes = log(&[WalRecord::Delete { id: 1 }, WalRecord::Add(record(2))]);
+        let first_end = log(&[WalRecord::Delete { id: 1 }]).len();
+
+        for cut in first_end + 1..bytes.len() {
+            let (decoded, valid_len) = WalRecord::decode_log(&bytes[..cut]).unwrap();
+            assert_eq!(ids(&decoded), vec![1], "cut at {cut}");
+            assert_eq!(valid_len, first_end);
+        }
+
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        let (decoded, valid_len) = WalRecord::decode_log(&flipped).unwrap();
+        assert_eq!(ids(&decoded), vec![1]);
+        assert_eq!(valid_len, first_end);
+    }
+
+    #[test]
+    fn corrupt_frame_before_the_end_is_an_error() {
+        let header_len = WalRecord::log_header().len();
+        let mut bytes = log(&[WalRecord::Delete { id: 1 }, WalRecord::Delete { id: 2 }]);
+        bytes[header_len + 8] ^= 1;
+
+        let err = WalRecord::decode_log(&bytes).unwrap_err();
+
+        assert!(err.to_string().contains("checksum"), "{err}");
+    }
+
+    #[test]
+    fn undecodable_record_in_an_intact_frame_is_an_error() {
+        let mut bytes = log(&[WalRecord::Delete { id: 1 }]);
+        let offset = bytes.len();
+        bytes.extend(encode_frame(&[0xff, 1, 2]));
+        bytes.extend(WalRecord::Delete { id: 3 }.encode().unwrap());
+
+        let err = WalRecord::decode_log(&bytes).unwrap_err();
+
+        assert!(
+            err.to_string().contains(&format!("offset {offset}")),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let mut bytes = log(&[]);
+        bytes[WAL_MAGIC.len()] = FORMAT_VERSION as u8 + 1;
+        assert!(WalRecord::decode_log(&bytes).is_err());
+        assert!(WalRecord::decode_log(b"not a log").is_err());
+        assert_eq!(WalRecord::decode_log(&[]).unwrap().1, 0);
+    }
+}
@@ -0,0 +1,302 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand_core::{OsRng, RngCore};
+use zeroize::Zeroizing;
+
+/// Leading bytes of every sealed file or record.
+pub const SEALED_MAGIC: &[u8; 8] = b"PAGIENC1";
+/// Width of the master key and of Argon2 salts.
+pub const KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 24;
+
+/// Known plaintext sealed into the keyring to detect a wrong passphrase or key file.
+const KEY_CHECK_PLAINTEXT: &[u8] = b"pagi-companion-key-check";
+const KEY_CHECK_AAD: &str = "keyring";
+
+/// 256-bit key protecting everything in `companion_data`. Zeroed on drop.
+pub struct MasterKey(Zeroizing<[u8; KEY_LENGTH]>);
+
+impl MasterKey {
+    pub fn from_bytes(bytes: [u8; KEY_LENGTH]) -> Self {
+        MasterKey(Zeroizing::new(bytes))
+    }
+
+    /// Generates a random key (e.g. to write a new key file).
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; KEY_LENGTH];
+        OsRng.fill_bytes(&mut bytes);
+        Self::from_bytes(bytes)
+    }
+
+    /// Derives the key from a passphrase with Argon2id (default parameters).
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Result<Self> {
+        if passphrase.is_empty() {
+            bail!("Empty passphrase");
+        }
+        let mut key = Zeroizing::new([0u8; KEY_LENGTH]);
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+            .map_err(|e| anyhow!("Key derivation failed: {e}"))?;
+        Ok(MasterKey(key))
+    }
+
+    /// Reads a key file holding 32 raw bytes or 64 hex characters.
+    pub fn from_key_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = Zeroizing::new(
+            std::fs::read(path)
+                .map_err(|e| anyhow!("Failed to read key file {}: {}", path.display(), e))?,
+        );
+
+        let mut key = [0u8; KEY_LENGTH];
+        if data.len() == KEY_LENGTH {
+            key.copy_from_slice(&data);
+        } else {
+            let text = std::str::from_utf8(&data).unwrap_or_default().trim();
+            hex::decode_to_slice(text, &mut key).map_err(|_| {
+                anyhow!(
+                    "Key file {} must hold {} raw bytes or {} hex characters",
+                    path.display(),
+                    KEY_LENGTH,
+                    KEY_LENGTH * 2
+                )
+            })?;
+        }
+        Ok(Self::from_bytes(key))
+    }
+
+    /// The key as hex, for writing a key file.
+    pub fn to_hex(&self) -> Zeroizing<String> {
+        Zeroizing::new(hex::encode(self.0.as_ref()))
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(self.0.as_ref().into())
+    }
+
+    /// Encrypts `plaintext` as `SEALED_MAGIC | nonce | ciphertext+tag`.
+    ///
+    /// `context` is authenticated but not stored (e.g. the file name), so a sealed blob
+    /// moved to another file fails to open.
+    pub fn seal(&self, context: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher()
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: context.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Encryption failed"))?;
+
+        let mut out = Vec::with_capacity(SEALED_MAGIC.len() + NONCE_LENGTH + ciphertext.len());
+        out.extend_from_slice(SEALED_MAGIC);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    /// Decrypts a blob produced by `seal` with the same `context`.
+    pub fn open(&self, context: &str, sealed: &[u8]) -> Result<Vec<u8>> {
+        if !is_sealed(sealed) || sealed.len() < SEALED_MAGIC.len() + NONCE_LENGTH {
+            bail!("Not an encrypted PAGI blob");
+        }
+        let (nonce, ciphertext) = sealed[SEALED_MAGIC.len()..].split_at(NONCE_LENGTH);
+        self.cipher()
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: context.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Decryption failed for {context} (wrong key or tampered data)"))
+    }
+
+    /// Sealed known plaintext stored next to the data to validate the key on startup.
+    pub fn key_check(&self) -> Result<Vec<u8>> {
+        self.seal(KEY_CHECK_AAD, KEY_CHECK_PLAINTEXT)
+    }
+
+    pub fn verify_key_check(&self, key_check: &[u8]) -> Result<()> {
+        match self.open(KEY_CHECK_AAD, key_check) {
+            Ok(plaintext) if plaintext == KEY_CHECK_PLAINTEXT => Ok(()),
+            _ => bail!("Wrong passphrase or key file for this data directory"),
+        }
+    }
+}
+
+/// Whether `data` was produced by `MasterKey::seal`.
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(SEALED_MAGIC)
+}
+
+/// Optional encryption applied to every file the knowledge bases write.
+///
+/// Without a key, data passes through as plaintext. With a key, writes are sealed and
+/// reads reject unsealed data, so plaintext planted in the data directory is never
+/// trusted. Existing plaintext files are converted by `kb::migration`, which uses
+/// `allowing_plaintext`.
+#[derive(Clone, Default)]
+pub struct StorageCipher {
+    key: Option<Arc<MasterKey>>,
+    allow_plaintext: bool,
+}
+
+impl StorageCipher {
+    pub fn plaintext() -> Self {
+        StorageCipher {
+            key: None,
+            allow_plaintext: false,
+        }
+    }
+
+    pub fn new(key: MasterKey) -> Self {
+        StorageCipher {
+            key: Some(Arc::new(key)),
+            allow_plaintext: false,
+        }
+    }
+
+    /// Also accepts unsealed data while a key is set. Only for migrating plaintext data.
+    pub fn allowing_plaintext(mut self) -> Self {
+        self.allow_plaintext = true;
+        self
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    pub fn master_key(&self) -> Option<&MasterKey> {
+        self.key.as_deref()
+    }
+
+    /// Seals `plaintext` for storage under `context` (plaintext passthrough without a key).
+    pub fn seal(&self, context: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        match &self.key {
+            Some(key) => key.seal(context, plaintext),
+            None => Ok(plaintext.to_vec()),
+        }
+    }
+
+    /// Opens stored data. Unsealed data is only accepted without a key, or in
+    /// `allowing_plaintext` mode.
+    pub fn open(&self, context: &str, data: &[u8]) -> Result<Vec<u8>> {
+        match (&self.key, is_sealed(data)) {
+            (Some(key), true) => key.open(context, data),
+            (None, true) => {
+                bail!("{context} is encrypted; set PAGI_DATA_PASSPHRASE or PAGI_DATA_KEY_FILE")
+            }
+            (Some(_), false) if !self.allow_plaintext => bail!(
+                "{context} is not encrypted although a storage key is configured; \
+                 run encrypt_companion_data to migrate plaintext data"
+            ),
+            (_, false) => Ok(data.to_vec()),
+        }
+    }
+}
+
+impl std::fmt::Debug for StorageCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StorageCipher")
+            .field("encrypted", &self.is_encrypted())
+            .finish()
+    }
+}
+
+/// Generates a random Argon2 salt.
+pub fn generate_salt() -> [u8; KEY_LENGTH] {
+    let mut salt = [0u8; KEY_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_open_round_trip() {
+        let key = MasterKey::generate();
+        let sealed = key.seal("alice.json", b"{\"mood\":1}").unwrap();
+
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.windows(6).any(|w| w == b"\"mood\""));
+        assert_eq!(key.open("alice.json", &sealed).unwrap(), b"{\"mood\":1}");
+        assert_ne!(sealed, key.seal("alice.json", b"{\"mood\":1}").unwrap());
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let sealed = MasterKey::generate().seal("alice.json", b"secret").unwrap();
+
+        assert!(MasterKey::generate().open("alice.json", &sealed).is_err());
+    }
+
+    #[test]
+    fn blob_moved_to_another_file_is_rejected() {
+        let key = MasterKey::generate();
+        let sealed = key.seal("alice.json", b"secret").unwrap();
+
+        let err = key.open("mallory.json", &sealed).unwrap_err();
+        assert!(err.to_string().contains("mallory.json"), "{err}");
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let key = MasterKey::generate();
+        let mut sealed = key.seal("alice.json", b"secret").unwrap();
+        *sealed.last_mut().unwrap() ^= 1;
+
+        assert!(key.open("alice.json", &sealed).is_err());
+        assert!(key.open("alice.json", SEALED_MAGIC).is_err());
+    }
+
+    #[test]
+    fn key_check_detects_a_wrong_passphrase() {
+        let salt = generate_salt();
+        let key = MasterKey::from_passphrase("correct horse", &salt).unwrap();
+        let check = key.key_check().unwrap();
+
+        let same = MasterKey::from_passphrase("correct horse", &salt).unwrap();
+        assert!(same.verify_key_check(&check).is_ok());
+        let wrong = MasterKey::from_passphrase("battery staple", &salt).unwrap();
+        assert!(wrong.verify_key_check(&check).is_err());
+        assert!(MasterKey::from_passphrase("", &salt).is_err());
+    }
+
+    #[test]
+    fn storage_cipher_rejects_plaintext_once_keyed() {
+        let cipher = StorageCipher::new(MasterKey::generate());
+        assert!(cipher.open("alice.json", b"{}").is_err());
+        assert_eq!(
+            cipher
+                .clone()
+                .allowing_plaintext()
+                .open("alice.json", b"{}")
+                .unwrap(),
+            b"{}"
+        );
+
+        let sealed = cipher.seal("alice.json", b"{}").unwrap();
+        assert_eq!(cipher.open("alice.json", &sealed).unwrap(), b"{}");
+        assert!(StorageCipher::plaintext()
+            .open("alice.json", &sealed)
+            .is_err());
+        assert_eq!(
+            StorageCipher::plaintext()
+                .seal("alice.json", b"{}")
+                .unwrap(),
+            b"{}"
+        );
+    }
+}
@@ -1,22 +1,31 @@
 pub mod cryptography;
+pub mod encryption;
 
 use anyhow::Result;
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use self::encryption::MasterKey;
 
 /// Defines the secure identity of the Agent.
 ///
-/// NOTE: This stores raw key bytes for bare-metal persistence (dev/research).
+/// NOTE: Without a storage master key this persists raw key bytes (dev/research);
+/// with one, the private key is wrapped before it is written (`wrap_private_key`).
 /// For production, consider OS key stores / TPM / HSM.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AgentIdentity {
     /// Unique identifier for the agent (for now caller-provided).
     pub agent_id: String,
 
-    /// Ed25519 private signing key bytes.
-    #[serde(with = "serde_bytes")]
+    /// Ed25519 private signing key bytes (empty while the key is wrapped).
+    #[serde(with = "serde_bytes", default, skip_serializing_if = "Vec::is_empty")]
     pub private_key_bytes: Vec<u8>,
 
+    /// Private key sealed under the storage master key, bound to `agent_id`.
+    #[serde(with = "serde_bytes", default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_private_key: Option<Vec<u8>>,
+
     /// Ed25519 public verifying key bytes.
     #[serde(with = "serde_bytes")]
     pub public_key_bytes: Vec<u8>,
@@ -31,12 +40,49 @@ impl AgentIdentity {
         AgentIdentity {
             agent_id,
             private_key_bytes: signing_key.to_bytes().to_vec(),
+            wrapped_private_key: None,
             public_key_bytes: verifying_key.to_bytes().to_vec(),
         }
     }
 
+    fn wrap_context(&self) -> String {
+        format!("identity:{}", self.agent_id)
+    }
+
+    /// Returns a copy whose private key is sealed under `key` and whose raw bytes are
+    /// cleared, ready to be persisted.
+    pub fn wrapped(&self, key: &MasterKey) -> Result<AgentIdentity> {
+        if self.wrapped_private_key.is_some() && self.private_key_bytes.is_empty() {
+            return Ok(self.clone());
+        }
+        let wrapped = key.seal(&self.wrap_context(), &self.private_key_bytes)?;
+        Ok(AgentIdentity {
+            agent_id: self.agent_id.clone(),
+            private_key_bytes: Vec::new(),
+            wrapped_private_key: Some(wrapped),
+            public_key_bytes: self.public_key_bytes.clone(),
+        })
+    }
+
+    /// Restores the raw private key from `wrapped_private_key` (no-op if already raw).
+    pub fn unwrap_private_key(&mut self, key: &MasterKey) -> Result<()> {
+        let Some(wrapped) = self.wrapped_private_key.take() else {
+            return Ok(());
+        };
+        self.private_key_bytes = key.open(&self.wrap_context(), &wrapped)?;
+        Ok(())
+    }
+
+    /// Whether the private key is only available in wrapped form.
+    pub fn is_wrapped(&self) -> bool {
+        self.private_key_bytes.is_empty() && self.wrapped_private_key.is_some()
+    }
+
     /// Reconstructs a `SigningKey` from persisted bytes.
     pub fn signing_key(&self) -> Result<SigningKey> {
+        if self.is_wrapped() {
+            anyhow::bail!("Private key is wrapped; call unwrap_private_key first");
+        }
         let b: [u8; 32] = self
             .private_key_bytes
             .as_slice()
@@ -56,3 +102,9 @@ impl AgentIdentity {
     }
 }
 
+
+impl Drop for AgentIdentity {
+    fn drop(&mut self) {
+        self.private_key_bytes.zeroize();
+    }
+}
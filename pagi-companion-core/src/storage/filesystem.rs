@@ -0,0 +1,185 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use super::{validate_key, StorageBackend};
+
+/// Default root, relative to the working directory.
+pub const DEFAULT_DATA_DIR: &str = "./companion_data";
+
+/// Stores each key as a file under `root`.
+///
+/// Writes go to a temp file that is fsynced and then renamed over the target, so a
+/// crash leaves either the old or the new contents, never a torn file.
+#[derive(Debug, Clone)]
+pub struct FileSystemStorage {
+    root: PathBuf,
+}
+
+impl Default for FileSystemStorage {
+    fn default() -> Self {
+        Self::new(DEFAULT_DATA_DIR)
+    }
+}
+
+impl FileSystemStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileSystemStorage { root: root.into() }
+    }
+
+    /// Roots storage at `PAGI_DATA_DIR` (default: `./companion_data`).
+    pub fn from_env() -> Self {
+        match std::env::var("PAGI_DATA_DIR") {
+            Ok(root) if !root.is_empty() => Self::new(root),
+            _ => Self::default(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        validate_key(key)?;
+        Ok(self.root.join(key))
+    }
+
+    /// Makes a rename durable. Best effort: not every platform can fsync a directory.
+    async fn sync_root(&self) {
+        match fs::File::open(&self.root).await {
+            Ok(dir) => {
+                if let Err(e) = dir.sync_all().await {
+                    info!(dir = %self.root.display(), error = %e, "storage_dir_sync_unsupported");
+                }
+            }
+            Err(e) => info!(dir = %self.root.display(), error = %e, "storage_dir_sync_unsupported"),
+        }
+    }
+}
+
+/// A temp name next to `path` that no other write, in this process or another one
+/// sharing the directory, is using.
+fn tmp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    PathBuf::from(name)
+}
+
+async fn write_tmp(tmp: &Path, data: &[u8]) -> Result<()> {
+    let mut file = fs::File::create(tmp).await?;
+    file.write_all(data).await?;
+    file.sync_all().await?;
+    Ok(())
+}
+
+async fn remove_file_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FileSystemStorage {
+    fn describe(&self) -> String {
+        format!("fs:{}", self.root.display())
+    }
+
+    async fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.path(key)?).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write(&self, key: &str, data: &[u8]) -> Result<()> {
+        let path = self.path(key)?;
+        fs::create_dir_all(&self.root).await?;
+        let tmp = tmp_path(&path);
+
+        let written = match write_tmp(&tmp, data).await {
+            Ok(()) => fs::rename(&tmp, &path).await.map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        if written.is_err() {
+            remove_file_if_exists(&tmp).await?;
+        }
+        written?;
+        self.sync_root().await;
+        Ok(())
+    }
+
+    async fn append(&self, key: &str, data: &[u8]) -> Result<u64> {
+        let path = self.path(key)?;
+        fs::create_dir_all(&self.root).await?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        file.write_all(data).await?;
+        file.sync_data().await?;
+        Ok(file.metadata().await?.len())
+    }
+
+    async fn size(&self, key: &str) -> Result<Option<u64>> {
+        match fs::metadata(self.path(key)?).await {
+            Ok(meta) => Ok(Some(meta.len())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        remove_file_if_exists(&self.path(key)?).await
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut entries = match fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(keys),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type().await?.is_file() && validate_key(&name).is_ok() {
+                keys.push(name);
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let from = self.path(from)?;
+        let to = self.path(to)?;
+        if !fs::try_exists(&from).await? {
+            return Ok(());
+        }
+        // Link (or copy) into place under a temp name, then rename, so the copy itself
+        // is never half-written.
+        let tmp = tmp_path(&to);
+        if fs::hard_link(&from, &tmp).await.is_err() {
+            fs::copy(&from, &tmp).await?;
+        }
+        if let Err(e) = fs::rename(&tmp, &to).await {
+            remove_file_if_exists(&tmp).await?;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use redb::{Database, ReadableTable, TableDefinition};
+
+use super::{validate_key, StorageBackend};
+
+/// `(key, sequence) -> chunk`. `write` stores one chunk; each `append` adds another,
+/// so appending to a large log never rewrites it.
+const CHUNKS: TableDefinition<(&str, u64), &[u8]> = TableDefinition::new("chunks");
+/// `key -> total length`, which doubles as the key listing.
+const SIZES: TableDefinition<&str, u64> = TableDefinition::new("sizes");
+
+/// Stores every key in a single embedded redb database file.
+///
+/// Each operation is one ACID transaction, which gives atomic writes and durable
+/// appends without per-file fsync/rename dances.
+#[derive(Clone)]
+pub struct KvStorage {
+    db: Arc<Database>,
+    location: String,
+}
+
+impl KvStorage {
+    /// Opens (or creates) the database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let db = Database::create(path)?;
+
+        // Create the tables up front so read transactions can always open them.
+        let txn = db.begin_write()?;
+        txn.open_table(CHUNKS)?;
+        txn.open_table(SIZES)?;
+        txn.commit()?;
+
+        Ok(KvStorage {
+            db: Arc::new(db),
+            location: path.display().to_string(),
+        })
+    }
+
+    /// redb is synchronous; run transactions off the async executor.
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Database) -> Result<T> + Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || f(&db)).await?
+    }
+}
+
+impl std::fmt::Debug for KvStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KvStorage")
+            .field("location", &self.location)
+            .finish()
+    }
+}
+
+/// Deletes every chunk of `key` inside an open write transaction.
+fn clear_chunks(chunks: &mut redb::Table<(&str, u64), &[u8]>, key: &str) -> Result<()> {
+    let seqs: Vec<u64> = chunks
+        .range((key, 0)..=(key, u64::MAX))?
+        .map(|entry| entry.map(|(k, _)| k.value().1))
+        .collect::<std::result::Result<_, _>>()?;
+    for seq in seqs {
+        chunks.remove((key, seq))?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl StorageBackend for KvStorage {
+    fn describe(&self) -> String {
+        format!("redb:{}", self.location)
+    }
+
+    async fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        validate_key(key)?;
+        let key = key.to_string();
+        self.run(move |db| {
+            let txn = db.begin_read()?;
+            if txn.open_table(SIZES)?.get(key.as_str())?.is_none() {
+                return Ok(None);
+            }
+            let chunks = txn.open_table(CHUNKS)?;
+            let mut data = Vec::new();
+            for entry in chunks.range((key.as_str(), 0)..=(key.as_str(), u64::MAX))? {
+                data.extend_from_slice(entry?.1.value());
+            }
+            Ok(Some(data))
+        })
+        .await
+    }
+
+    async fn write(&self, key: &str, data: &[u8]) -> Result<()> {
+        validate_key(key)?;
+        let (key, data) = (key.to_string(), data.to_vec());
+        self.run(move |db| {
+            let txn = db.begin_write()?;
+            {
+                let mut chunks = txn.open_table(CHUNKS)?;
+                clear_chunks(&mut chunks, &key)?;
+                chunks.insert((key.as_str(), 0), data.as_slice())?;
+                txn.open_table(SIZES)?
+                    .insert(key.as_str(), data.len() as u64)?;
+            }
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn append(&self, key: &str, data: &[u8]) -> Result<u64> {
+        validate_key(key)?;
+        let (key, data) = (key.to_string(), data.to_vec());
+        self.run(move |db| {
+            let txn = db.begin_write()?;
+            let size = {
+                let mut chunks = txn.open_table(CHUNKS)?;
+                let next_seq = chunks
+                    .range((key.as_str(), 0)..=(key.as_str(), u64::MAX))?
+                    .next_back()
+                    .transpose()?
+                    .map_or(0, |(k, _)| k.value().1 + 1);
+                chunks.insert((key.as_str(), next_seq), data.as_slice())?;
+
+                let mut sizes = txn.open_table(SIZES)?;
+                let size = sizes.get(key.as_str())?.map_or(0, |s| s.value()) + data.len() as u64;
+                sizes.insert(key.as_str(), size)?;
+                size
+            };
+            txn.commit()?;
+            Ok(size)
+        })
+        .await
+    }
+
+    async fn size(&self, key: &str) -> Result<Option<u64>> {
+        validate_key(key)?;
+        let key = key.to_string();
+        self.run(move |db| {
+            let txn = db.begin_read()?;
+            let size = txn.open_table(SIZES)?.get(key.as_str())?.map(|s| s.value());
+            Ok(size)
+        })
+        .await
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        validate_key(key)?;
+        let key = key.to_string();
+        self.run(move |db| {
+            let txn = db.begin_write()?;
+            {
+                clear_chunks(&mut txn.open_table(CHUNKS)?, &key)?;
+                txn.open_table(SIZES)?.remove(key.as_str())?;
+            }
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        self.run(|db| {
+            let txn = db.begin_read()?;
+            let keys = txn
+                .open_table(SIZES)?
+                .iter()?
+                .map(|entry| entry.map(|(k, _)| k.value().to_string()))
+                .collect::<std::result::Result<_, _>>()?;
+            Ok(keys)
+        })
+        .await
+    }
+}
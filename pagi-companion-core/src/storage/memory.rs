@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+
+use super::{validate_key, StorageBackend};
+
+/// Volatile storage for tests and ephemeral sessions; nothing touches the disk.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    files: RwLock<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn poisoned<T>(_: T) -> anyhow::Error {
+    anyhow!("Memory storage lock poisoned")
+}
+
+#[async_trait]
+impl StorageBackend for MemoryStorage {
+    fn describe(&self) -> String {
+        "memory".to_string()
+    }
+
+    async fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        validate_key(key)?;
+        Ok(self.files.read().map_err(poisoned)?.get(key).cloned())
+    }
+
+    async fn write(&self, key: &str, data: &[u8]) -> Result<()> {
+        validate_key(key)?;
+        self.files
+            .write()
+            .map_err(poisoned)?
+            .insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    async fn append(&self, key: &str, data: &[u8]) -> Result<u64> {
+        validate_key(key)?;
+        let mut files = self.files.write().map_err(poisoned)?;
+        let file = files.entry(key.to_string()).or_default();
+        file.extend_from_slice(data);
+        Ok(file.len() as u64)
+    }
+
+    async fn size(&self, key: &str) -> Result<Option<u64>> {
+        validate_key(key)?;
+        Ok(self
+            .files
+            .read()
+            .map_err(poisoned)?
+            .get(key)
+            .map(|f| f.len() as u64))
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        validate_key(key)?;
+        self.files.write().map_err(poisoned)?.remove(key);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        Ok(self
+            .files
+            .read()
+            .map_err(poisoned)?
+            .keys()
+            .cloned()
+            .collect())
+    }
+}
@@ -0,0 +1,60 @@
+pub mod filesystem;
+pub mod kv;
+pub mod memory;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+
+pub use self::filesystem::FileSystemStorage;
+pub use self::kv::KvStorage;
+pub use self::memory::MemoryStorage;
+
+/// Where the knowledge bases keep their files.
+///
+/// Keys are flat names such as `alice.json` or `alice_rag.seg`. Implementations must
+/// make `write` atomic (readers see the old or the new value, never a mix) and
+/// `append` durable once it returns, which is what crash recovery in the KBs relies on.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Human-readable location for logs (e.g. the root directory).
+    fn describe(&self) -> String;
+
+    /// Reads `key`, or `None` if it does not exist.
+    async fn read(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Atomically replaces `key` with `data`.
+    async fn write(&self, key: &str, data: &[u8]) -> Result<()>;
+
+    /// Appends `data` to `key` (creating it), returning the new length.
+    async fn append(&self, key: &str, data: &[u8]) -> Result<u64>;
+
+    /// Length of `key`, or `None` if it does not exist.
+    async fn size(&self, key: &str) -> Result<Option<u64>>;
+
+    /// Removes `key`; removing a missing key is not an error.
+    async fn remove(&self, key: &str) -> Result<()>;
+
+    /// All stored keys, sorted.
+    async fn list(&self) -> Result<Vec<String>>;
+
+    /// Atomically copies `from` over `to` (no-op if `from` does not exist).
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        if let Some(data) = self.read(from).await? {
+            self.write(to, &data).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Rejects keys that could escape a storage root or collide with temp files.
+pub fn validate_key(key: &str) -> Result<()> {
+    let valid = !key.is_empty()
+        && key != "."
+        && key != ".."
+        && !key.contains(['/', '\\', '\0'])
+        && !key.ends_with(".tmp");
+    if !valid {
+        bail!("Invalid storage key {key:?}");
+    }
+    Ok(())
+}
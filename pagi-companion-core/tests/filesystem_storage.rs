@@ -0,0 +1,44 @@
+//! `FileSystemStorage` under concurrent writers.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use pagi_companion_core::storage::{FileSystemStorage, StorageBackend};
+
+fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("pagi-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_writes_to_one_key_do_not_collide() -> Result<()> {
+    let dir = scratch_dir("concurrent-writes");
+    let storage = Arc::new(FileSystemStorage::new(&dir));
+
+    let writers: Vec<_> = (0..32u8)
+        .map(|i| {
+            let storage = storage.clone();
+            tokio::spawn(async move { storage.write("state.json", &vec![i; 4096]).await })
+        })
+        .collect();
+    for writer in writers {
+        writer.await??;
+    }
+
+    let data = storage.read("state.json").await?.expect("written");
+    assert_eq!(data.len(), 4096);
+    assert!(data.iter().all(|&b| b == data[0]), "torn write");
+    let leftovers: Vec<_> = std::fs::read_dir(&dir)?
+        .flatten()
+        .map(|entry| entry.file_name())
+        .filter(|name| name != "state.json")
+        .collect();
+    assert!(
+        leftovers.is_empty(),
+        "temp files left behind: {leftovers:?}"
+    );
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
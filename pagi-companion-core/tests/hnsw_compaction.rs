@@ -0,0 +1,88 @@
+//! Compaction drops the HNSW graph; it is rebuilt from a snapshot and swapped in later.
+
+use pagi_companion_core::rag::hnsw::{HnswParams, IndexMode};
+use pagi_companion_core::rag::index::VectorIndex;
+use pagi_companion_core::rag::metadata::MemoryFilter;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const DIMENSION: usize = 16;
+
+fn hnsw_index(rng: &mut StdRng, count: usize) -> VectorIndex {
+    let mut index = VectorIndex::with_mode(IndexMode::Hnsw(HnswParams {
+        exact_scan_threshold: 0,
+        ..HnswParams::default()
+    }));
+    for i in 0..count {
+        index.add(format!("memory {i}"), vector(rng));
+    }
+    index
+}
+
+fn vector(rng: &mut StdRng) -> Vec<f32> {
+    (0..DIMENSION).map(|_| rng.gen_range(-1.0..1.0)).collect()
+}
+
+fn nearest(index: &VectorIndex, query: &[f32]) -> String {
+    index
+        .search_scored(query, 1, &MemoryFilter::default())
+        .remove(0)
+        .2
+}
+
+#[test]
+fn compaction_leaves_the_graph_to_a_later_build() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut index = hnsw_index(&mut rng, 64);
+    assert!(index.pending_graph_build().is_none());
+
+    for id in 0..16 {
+        index.delete(id);
+    }
+    index.compact();
+    assert!(index.hnsw_to_json_bytes().unwrap().is_none());
+
+    let build = index.pending_graph_build().expect("graph was dropped");
+    let graph = build.run();
+    // Added while the graph was being built; linked in when it is installed.
+    let late = vector(&mut rng);
+    index.add("late".to_string(), late.clone());
+
+    assert!(index.install_graph(&build, graph));
+    assert!(index.pending_graph_build().is_none());
+    assert_eq!(nearest(&index, &late), "late");
+    let restored = index.hnsw_to_json_bytes().unwrap().unwrap();
+    let mut reloaded = VectorIndex::from_segment(index.to_segment());
+    reloaded.restore_hnsw_json_bytes(&restored);
+    assert!(reloaded.hnsw_to_json_bytes().unwrap().is_some());
+}
+
+#[test]
+fn builds_started_before_a_compaction_are_discarded() {
+    let mut rng = StdRng::seed_from_u64(11);
+    let mut index = hnsw_index(&mut rng, 64);
+    index.delete(0);
+    index.compact();
+    let stale = index.pending_graph_build().unwrap();
+    let graph = stale.run();
+
+    index.delete(1);
+    index.compact();
+
+    assert!(!index.install_graph(&stale, graph));
+    let current = index.pending_graph_build().unwrap();
+    let graph = current.run();
+    assert!(index.install_graph(&current, graph));
+}
+
+#[test]
+fn exact_mode_needs_no_build() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let mut index = VectorIndex::new();
+    for i in 0..8 {
+        index.add(format!("memory {i}"), vector(&mut rng));
+    }
+    index.delete(0);
+    index.compact();
+    assert!(index.pending_graph_build().is_none());
+}
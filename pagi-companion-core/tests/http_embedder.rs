@@ -0,0 +1,170 @@
+//! `HttpEmbedder` against a local mock `/v1/embeddings` server.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use pagi_companion_core::rag::config::EmbeddingServiceConfig;
+use pagi_companion_core::rag::embedding::Embedder;
+use pagi_companion_core::rag::http_embedder::HttpEmbedder;
+use serde_json::{json, Value};
+use url::Url;
+
+const DIMENSION: usize = 4;
+
+/// Answers with the queued statuses first, then with one `dimension`-wide vector per
+/// input (listed in reverse, with `index` set) and records the size of every batch.
+struct MockServer {
+    failures: Mutex<VecDeque<u16>>,
+    dimension: usize,
+    batches: Mutex<Vec<usize>>,
+}
+
+impl MockServer {
+    fn new(failures: &[u16], dimension: usize) -> Arc<Self> {
+        Arc::new(MockServer {
+            failures: Mutex::new(failures.iter().copied().collect()),
+            dimension,
+            batches: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn start(self: &Arc<Self>) -> Result<Url> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = Url::parse(&format!("http://{}/v1/embeddings", listener.local_addr()?))?;
+        let server = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = server.handle(stream);
+            }
+        });
+        Ok(url)
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse()?;
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let (status, body) = match self.failures.lock().unwrap().pop_front() {
+            Some(status) => (status, "injected failure".to_string()),
+            None => (200, self.reply(&serde_json::from_slice(&body)?)),
+        };
+        let response = format!(
+            "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes())?;
+        Ok(())
+    }
+
+    /// Vector `i` is `[i, i, ...]`, so tests can check the order was restored.
+    fn reply(&self, request: &Value) -> String {
+        let inputs = request["input"].as_array().map(Vec::len).unwrap_or(0);
+        self.batches.lock().unwrap().push(inputs);
+        let data: Vec<Value> = (0..inputs)
+            .rev()
+            .map(|i| json!({ "index": i, "embedding": vec![i as f32; self.dimension] }))
+            .collect();
+        json!({ "data": data }).to_string()
+    }
+}
+
+fn embedder(api_url: Url, batch_size: usize, max_retries: u32) -> Result<HttpEmbedder> {
+    HttpEmbedder::with_config(EmbeddingServiceConfig {
+        api_url,
+        api_key: String::new(),
+        model_name: "mock".to_string(),
+        dimension: DIMENSION,
+        batch_size,
+        max_retries,
+    })
+}
+
+fn texts(count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("memory {i}")).collect()
+}
+
+#[tokio::test]
+async fn splits_into_batches_and_keeps_order() -> Result<()> {
+    let server = MockServer::new(&[], DIMENSION);
+    let embedder = embedder(server.start()?, 2, 0)?;
+
+    let embeddings = embedder.embed_batch(&texts(5)).await?;
+
+    assert_eq!(*server.batches.lock().unwrap(), vec![2, 2, 1]);
+    let firsts: Vec<f32> = embeddings.iter().map(|e| e[0]).collect();
+    assert_eq!(firsts, vec![0.0, 1.0, 0.0, 1.0, 0.0]);
+    assert!(embeddings.iter().all(|e| e.len() == DIMENSION));
+    Ok(())
+}
+
+#[tokio::test]
+async fn retries_server_errors() -> Result<()> {
+    let server = MockServer::new(&[500, 503], DIMENSION);
+    let embedder = embedder(server.start()?, 8, 2)?;
+
+    let embeddings = embedder.embed_batch(&texts(3)).await?;
+
+    assert_eq!(embeddings.len(), 3);
+    assert_eq!(*server.batches.lock().unwrap(), vec![3]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn gives_up_after_max_retries() -> Result<()> {
+    let server = MockServer::new(&[500, 500], DIMENSION);
+    let embedder = embedder(server.start()?, 8, 1)?;
+
+    let err = embedder.embed_text("hello").await.unwrap_err();
+
+    assert!(err.to_string().contains("status=500"), "{err}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn does_not_retry_client_errors() -> Result<()> {
+    let server = MockServer::new(&[400], DIMENSION);
+    let embedder = embedder(server.start()?, 8, 3)?;
+
+    let err = embedder.embed_text("hello").await.unwrap_err();
+
+    assert!(err.to_string().contains("status=400"), "{err}");
+    assert!(server.failures.lock().unwrap().is_empty());
+    assert!(server.batches.lock().unwrap().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn rejects_dimension_mismatch() -> Result<()> {
+    let server = MockServer::new(&[], DIMENSION + 1);
+    let embedder = embedder(server.start()?, 8, 3)?;
+
+    let err = embedder.embed_batch(&texts(2)).await.unwrap_err();
+
+    assert!(err.to_string().contains("dimension mismatch"), "{err}");
+    assert_eq!(
+        *server.batches.lock().unwrap(),
+        vec![2],
+        "mismatch must not be retried"
+    );
+    Ok(())
+}
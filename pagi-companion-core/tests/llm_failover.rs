@@ -0,0 +1,255 @@
+//! Tactical LLM retries, `Retry-After`, circuit breaking and failover against a local
+//! mock server that injects failures.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use pagi_companion_core::brain::config::{
+    CircuitBreakerConfig, LlmEndpoint, LlmProviderKind, RetryPolicy, TacticalLLMConfig,
+};
+use pagi_companion_core::brain::prompt::{PromptAssembler, PromptSections};
+use pagi_companion_core::brain::provider::resilient::CircuitState;
+use pagi_companion_core::brain::provider::ResilientProvider;
+use pagi_companion_core::brain::repair::RepairPolicy;
+use pagi_companion_core::brain::tactical_llm::TacticalLLM;
+
+const REPLY: &str = r#"{"response":"ok","suggested_emotion_change":"","state_commands":{}}"#;
+
+/// Scripted mock: `/flaky` answers with the queued statuses, then 200; `/down` always
+/// fails with 500; `/auth` always rejects with 401; anything else answers 200.
+#[derive(Default)]
+struct MockServer {
+    flaky: Mutex<VecDeque<(u16, Option<&'static str>)>>,
+    hits: Mutex<Vec<String>>,
+}
+
+impl MockServer {
+    fn start(self: &Arc<Self>) -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let base = format!("http://{}", listener.local_addr()?);
+        let server = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = server.handle(stream);
+            }
+        });
+        Ok(base)
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let path = request_line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or("/")
+            .to_string();
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse()?;
+                }
+            }
+        }
+        reader.read_exact(&mut vec![0; content_length])?;
+        self.hits.lock().unwrap().push(path.clone());
+
+        let (status, retry_after) = match path.as_str() {
+            "/flaky" => self
+                .flaky
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or((200, None)),
+            "/down" => (500, None),
+            "/auth" => (401, None),
+            _ => (200, None),
+        };
+        let body = if status == 200 {
+            REPLY
+        } else {
+            "injected failure"
+        };
+        let mut response = format!(
+            "HTTP/1.1 {status} Mock\r\nContent-Length: {}\r\n",
+            body.len()
+        );
+        if let Some(retry_after) = retry_after {
+            response.push_str(&format!("Retry-After: {retry_after}\r\n"));
+        }
+        response.push_str("Connection: close\r\n\r\n");
+        response.push_str(body);
+        stream.write_all(response.as_bytes())?;
+        Ok(())
+    }
+
+    fn take_hits(&self) -> Vec<String> {
+        std::mem::take(&mut *self.hits.lock().unwrap())
+    }
+}
+
+fn endpoint(base: &str, path: &str) -> Result<LlmEndpoint> {
+    LlmEndpoint::parse(&format!("raw:mock@{base}{path}"), "")
+}
+
+fn config(base: &str, primary: &str, fallbacks: &[&str]) -> Result<TacticalLLMConfig> {
+    let primary = endpoint(base, primary)?;
+    Ok(TacticalLLMConfig {
+        provider: LlmProviderKind::Raw,
+        api_url: primary.api_url,
+        api_key: String::new(),
+        model_name: primary.model_name,
+        fallbacks: fallbacks
+            .iter()
+            .map(|path| endpoint(base, path))
+            .collect::<Result<_>>()?,
+        retry: RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(20),
+            max_delay: Duration::from_millis(100),
+            max_retry_after: Duration::from_secs(2),
+        },
+        circuit_breaker: CircuitBreakerConfig {
+            failure_threshold: 3,
+            cooldown: Duration::from_millis(300),
+        },
+        repair: RepairPolicy::strict(),
+        response_schema: true,
+    })
+}
+
+async fn ask(llm: &TacticalLLM) -> Result<String> {
+    let prompt =
+        PromptAssembler::default().assemble(PromptSections::new("hello").with_system("system"));
+    Ok(llm.generate_structured_output(&prompt).await?.response)
+}
+
+/// A mock server and the resilient provider behind `llm`, for checking circuit states.
+struct Harness {
+    server: Arc<MockServer>,
+    base: String,
+}
+
+impl Harness {
+    fn start() -> Result<Self> {
+        let server = Arc::new(MockServer::default());
+        let base = server.start()?;
+        Ok(Harness { server, base })
+    }
+
+    fn fail_next(&self, failures: impl IntoIterator<Item = (u16, Option<&'static str>)>) {
+        self.server.flaky.lock().unwrap().extend(failures);
+    }
+
+    fn resilient(
+        &self,
+        primary: &str,
+        fallbacks: &[&str],
+    ) -> Result<(Arc<ResilientProvider>, TacticalLLM, String)> {
+        let config = config(&self.base, primary, fallbacks)?;
+        let label = config.endpoints()[0].label();
+        let resilient = Arc::new(ResilientProvider::from_config(
+            &config,
+            reqwest::Client::new(),
+        ));
+        let llm = TacticalLLM::with_provider(resilient.clone())
+            .with_repair_policy(RepairPolicy::strict());
+        Ok((resilient, llm, label))
+    }
+}
+
+#[tokio::test]
+async fn retries_transient_failures_and_honors_retry_after() -> Result<()> {
+    let harness = Harness::start()?;
+    harness.fail_next([(503, Some("1")), (429, None)]);
+    let llm = TacticalLLM::with_config(config(&harness.base, "/flaky", &[])?)?;
+
+    let started = Instant::now();
+    assert_eq!(ask(&llm).await?, "ok");
+    assert_eq!(harness.server.take_hits(), ["/flaky"; 3]);
+    assert!(
+        started.elapsed() >= Duration::from_secs(1),
+        "Retry-After was not honored"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn fails_over_immediately_on_non_retryable_errors() -> Result<()> {
+    let harness = Harness::start()?;
+    let llm = TacticalLLM::with_config(config(&harness.base, "/auth", &["/ok"])?)?;
+
+    assert_eq!(ask(&llm).await?, "ok");
+    assert_eq!(harness.server.take_hits(), ["/auth", "/ok"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn open_circuit_skips_the_endpoint_until_a_trial() -> Result<()> {
+    let harness = Harness::start()?;
+    let (resilient, llm, primary) = harness.resilient("/down", &["/ok"])?;
+
+    // Exhausted retries open the circuit; later calls skip the endpoint entirely.
+    assert_eq!(ask(&llm).await?, "ok");
+    assert_eq!(
+        harness.server.take_hits(),
+        ["/down", "/down", "/down", "/ok"]
+    );
+    assert_eq!(resilient.circuit_state(&primary), Some(CircuitState::Open));
+    assert_eq!(ask(&llm).await?, "ok");
+    assert_eq!(harness.server.take_hits(), ["/ok"]);
+
+    // After the cooldown a single trial call probes the endpoint; its failure reopens it.
+    tokio::time::sleep(Duration::from_millis(350)).await;
+    assert_eq!(ask(&llm).await?, "ok");
+    assert_eq!(harness.server.take_hits(), ["/down", "/ok"]);
+    assert_eq!(resilient.circuit_state(&primary), Some(CircuitState::Open));
+    Ok(())
+}
+
+#[tokio::test]
+async fn non_retryable_errors_do_not_reset_the_failure_count() -> Result<()> {
+    let harness = Harness::start()?;
+    let (resilient, llm, primary) = harness.resilient("/flaky", &["/ok"])?;
+
+    // Two transient failures, then a 401: the endpoint is not counted as recovered.
+    harness.fail_next([(500, None), (500, None), (401, None)]);
+    assert_eq!(ask(&llm).await?, "ok");
+    assert_eq!(
+        harness.server.take_hits(),
+        ["/flaky", "/flaky", "/flaky", "/ok"]
+    );
+    assert_eq!(
+        resilient.circuit_state(&primary),
+        Some(CircuitState::Closed)
+    );
+
+    // So one more transient failure reaches the threshold.
+    harness.fail_next([(500, None)]);
+    assert_eq!(ask(&llm).await?, "ok");
+    assert_eq!(harness.server.take_hits(), ["/flaky", "/ok"]);
+    assert_eq!(resilient.circuit_state(&primary), Some(CircuitState::Open));
+    Ok(())
+}
+
+#[tokio::test]
+async fn reports_the_last_failure_when_every_endpoint_fails() -> Result<()> {
+    let harness = Harness::start()?;
+    let llm = TacticalLLM::with_config(config(&harness.base, "/down", &["/auth"])?)?;
+
+    let error = ask(&llm).await.expect_err("all endpoints are failing");
+    assert!(format!("{error:#}").contains("status=401"), "{error:#}");
+    Ok(())
+}
@@ -0,0 +1,67 @@
+//! Golden-file check of the built-in system prompt template: renders every persona preset
+//! and compares the result with `prompts/golden/<preset>.v<N>.txt`.
+//!
+//! After an intended template change, re-bless with
+//! `PROMPT_GOLDEN_BLESS=1 cargo test -p pagi-companion-core --test prompt_templates`.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use pagi_companion_core::companion::persona::{persona_preset, PERSONA_PRESETS};
+use pagi_companion_core::companion::templates::PromptTemplates;
+
+/// Fixed clock so `since_last_interaction` is reproducible.
+const NOW: i64 = 1_700_000_000;
+
+#[test]
+fn presets_match_goldens() -> Result<()> {
+    let bless = std::env::var("PROMPT_GOLDEN_BLESS").is_ok_and(|v| v == "1");
+    let templates = PromptTemplates::builtin();
+    let golden_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("prompts/golden");
+
+    let mut mismatches = Vec::new();
+    for (days_ago, preset) in PERSONA_PRESETS.iter().enumerate() {
+        let mut matrix = persona_preset(preset)?;
+        matrix.last_interaction_time = NOW - days_ago as i64 * 86_400;
+        let rendered = templates.render_system_prompt_at(&matrix, NOW)? + "\n";
+
+        let path = golden_dir.join(format!("{preset}.v{}.txt", templates.version()));
+        if bless {
+            std::fs::create_dir_all(&golden_dir)?;
+            std::fs::write(&path, &rendered)?;
+            println!("blessed {}", path.display());
+            continue;
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(expected) if expected == rendered => {}
+            Ok(expected) => {
+                println!("MISMATCH {preset}\n--- expected\n{expected}--- rendered\n{rendered}");
+                mismatches.push(*preset);
+            }
+            Err(e) => {
+                println!("MISSING {} ({e})", path.display());
+                mismatches.push(*preset);
+            }
+        }
+    }
+
+    assert!(
+        mismatches.is_empty(),
+        "golden prompts differ for {mismatches:?}; re-bless with PROMPT_GOLDEN_BLESS=1 if intended"
+    );
+    Ok(())
+}
+
+#[test]
+fn typo_reached_by_any_preset_fails_at_load() {
+    // Only the last preset takes the branch with the typo.
+    let last = PERSONA_PRESETS[PERSONA_PRESETS.len() - 1];
+    let name = persona_preset(last).unwrap().name;
+    let source =
+        format!("{{% if name == {name:?} %}}{{{{ no_such_field }}}}{{% endif %}}{{{{ name }}}}");
+
+    let err = PromptTemplates::from_source(source, 1, "test")
+        .err()
+        .expect("typo is rejected");
+    assert!(err.to_string().contains("undefined value"), "{err}");
+}
@@ -0,0 +1,526 @@
+//! Scenario suite for psychology rule sets: plays the same scripted interactions against
+//! every `rules/*.toml`, checks invariants that must hold under any rules, and compares
+//! the final states with `rules/golden/<rule set>.txt`. Seeded random interactions
+//! (every attachment style, arbitrary commands and gaps) then check the invariants
+//! alone. Also checks `PsychologyRules::validate`, loading older rule files and
+//! reloading a watched rules file.
+//!
+//! After an intended rule change, re-bless with
+//! `PSYCH_SCENARIOS_BLESS=1 cargo test -p pagi-companion-core --test psychology_rules`.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Result};
+use pagi_companion_core::companion::commands::{ProgressHint, StateCommand, UserSignal};
+use pagi_companion_core::companion::models::{
+    AttachmentStyle, EmotionalState, PersonalityStateMatrix, RelationshipStage,
+};
+use pagi_companion_core::companion::persona::{persona_preset, PERSONA_PRESETS};
+use pagi_companion_core::companion::psychology::PsychologicalEngine;
+use pagi_companion_core::companion::rules::{PsychologyRules, PSYCHOLOGY_RULES_VERSION};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Fixed clock so decay is reproducible.
+const START: i64 = 1_700_000_000;
+const HOUR: i64 = 3600;
+/// Random interaction sequences per attachment style and rule set, and exchanges per
+/// sequence.
+const PROPERTY_RUNS: u64 = 10;
+const PROPERTY_STEPS: usize = 40;
+const STYLES: [AttachmentStyle; 4] = [
+    AttachmentStyle::Secure,
+    AttachmentStyle::Anxious,
+    AttachmentStyle::Avoidant,
+    AttachmentStyle::Disorganized,
+];
+
+struct Scenario {
+    name: &'static str,
+    preset: &'static str,
+    /// Replaces the preset's attachment style, starting at that style's resting levels.
+    style: Option<AttachmentStyle>,
+    /// Hours after the previous step, and the commands of the reply.
+    steps: Vec<(i64, Vec<StateCommand>)>,
+}
+
+fn scenarios() -> Vec<Scenario> {
+    let signal = |signal| vec![StateCommand::UserSignal(signal)];
+    let shift = |emotion, delta| vec![StateCommand::EmotionShift { emotion, delta }];
+    vec![
+        Scenario {
+            name: "anxious_pursuit",
+            preset: "maya",
+            style: None,
+            steps: vec![
+                (1, signal(UserSignal::Distance)),
+                (1, signal(UserSignal::Distance)),
+                (1, signal(UserSignal::Distance)),
+                (2, signal(UserSignal::Closeness)),
+            ],
+        },
+        Scenario {
+            name: "avoidant_closeness",
+            preset: "jordan",
+            style: None,
+            steps: (0..4).map(|_| (1, signal(UserSignal::Closeness))).collect(),
+        },
+        Scenario {
+            name: "emotional_spike",
+            preset: "skylar",
+            style: None,
+            steps: vec![(1, shift(EmotionalState::Anxious, 0.8)), (12, vec![])],
+        },
+        Scenario {
+            name: "long_absence",
+            preset: "skylar",
+            style: None,
+            steps: vec![(1, vec![StateCommand::Arousal(0.4)]), (72, vec![])],
+        },
+        Scenario {
+            name: "relationship_growth",
+            preset: "maya",
+            style: None,
+            steps: (0..40)
+                .map(|_| (6, signal(UserSignal::Closeness)))
+                .collect(),
+        },
+        Scenario {
+            name: "anxious_absence",
+            preset: "maya",
+            style: None,
+            steps: vec![(1, signal(UserSignal::Closeness)), (120, vec![])],
+        },
+        Scenario {
+            name: "disorganized_oscillation",
+            preset: "skylar",
+            style: Some(AttachmentStyle::Disorganized),
+            steps: [UserSignal::Closeness; 3]
+                .into_iter()
+                .chain([UserSignal::Distance; 3])
+                .map(|step| (1, signal(step)))
+                .collect(),
+        },
+        Scenario {
+            name: "earned_security",
+            preset: "jordan",
+            style: None,
+            steps: (0..100)
+                .map(|_| (8, signal(UserSignal::Closeness)))
+                .collect(),
+        },
+    ]
+}
+
+fn run(engine: &PsychologicalEngine, scenario: &Scenario) -> Result<PersonalityStateMatrix> {
+    let mut matrix = persona_preset(scenario.preset)?;
+    matrix.last_interaction_time = START;
+    if let Some(style) = scenario.style {
+        let rules = engine.rules();
+        let resting = &rules.attachment(style).resting;
+        matrix.attachment_style = style;
+        matrix.anxiety_level = resting.anxiety;
+        matrix.avoidance_level = resting.avoidance;
+    }
+    let mut now = START;
+    for (hours, commands) in &scenario.steps {
+        now += hours * HOUR;
+        let stage = matrix.relationship_stage;
+        engine.process_llm_state_update_at(&mut matrix, commands, now)?;
+        check_invariants(engine, &matrix, stage)?;
+    }
+    Ok(matrix)
+}
+
+fn check_invariants(
+    engine: &PsychologicalEngine,
+    matrix: &PersonalityStateMatrix,
+    previous_stage: RelationshipStage,
+) -> Result<()> {
+    let rules = engine.rules();
+    let clamps = &rules.clamps;
+    let earned_max = rules.attachment.earned_security.max;
+    for (name, value, [min, max]) in [
+        ("anxiety_level", matrix.anxiety_level, clamps.anxiety_level),
+        (
+            "avoidance_level",
+            matrix.avoidance_level,
+            clamps.avoidance_level,
+        ),
+        ("sexual_energy", matrix.sexual_energy, clamps.sexual_energy),
+        (
+            "earned_security",
+            matrix.attachment.earned_security,
+            [0.0, earned_max],
+        ),
+        ("trust", matrix.relationship.trust, [0.0, 1.0]),
+        ("intimacy", matrix.relationship.intimacy, [0.0, 1.0]),
+    ] {
+        if !(min..=max).contains(&value) || !(0.0..=1.0).contains(&value) {
+            bail!("{name} {value} outside [{min}, {max}]");
+        }
+    }
+    for emotion in EmotionalState::ALL {
+        let intensity = matrix.emotions.get(emotion);
+        if !(0.0..=1.0).contains(&intensity) {
+            bail!("{emotion:?} intensity {intensity} outside [0, 1]");
+        }
+    }
+    if matrix.emotions.get(matrix.current_emotional_state)
+        < EmotionalState::ALL
+            .into_iter()
+            .map(|emotion| matrix.emotions.get(emotion))
+            .fold(0.0, f32::max)
+    {
+        bail!(
+            "{:?} is not the dominant emotion",
+            matrix.current_emotional_state
+        );
+    }
+    if matrix
+        .relationship_stage
+        .index()
+        .abs_diff(previous_stage.index())
+        > 1
+    {
+        bail!(
+            "stage jumped from {previous_stage:?} to {:?}",
+            matrix.relationship_stage
+        );
+    }
+    Ok(())
+}
+
+fn summary(matrix: &PersonalityStateMatrix) -> String {
+    let emotions = EmotionalState::ALL
+        .into_iter()
+        .map(|emotion| format!("{emotion:?}={:.3}", matrix.emotions.get(emotion)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "anxiety={:.3} avoidance={:.3} sexual_energy={:.3} state={:?} stage={:?} trust={:.3} intimacy={:.3}\n  \
+         earned_security={:.3} phase={:?}\n  {emotions}",
+        matrix.anxiety_level,
+        matrix.avoidance_level,
+        matrix.sexual_energy,
+        matrix.current_emotional_state,
+        matrix.relationship_stage,
+        matrix.relationship.trust,
+        matrix.relationship.intimacy,
+        matrix.attachment.earned_security,
+        matrix.attachment.phase,
+    )
+}
+
+/// A random, possibly extreme, command as the LLM could send it after validation.
+fn random_command(rng: &mut StdRng) -> StateCommand {
+    let emotion = EmotionalState::ALL[rng.gen_range(0..EmotionalState::ALL.len())];
+    match rng.gen_range(0..5) {
+        0 => StateCommand::Arousal(rng.gen_range(-3.0..3.0)),
+        1 | 2 => StateCommand::UserSignal(if rng.gen_bool(0.5) {
+            UserSignal::Closeness
+        } else {
+            UserSignal::Distance
+        }),
+        3 => StateCommand::EmotionShift {
+            emotion,
+            delta: rng.gen_range(-3.0..3.0),
+        },
+        _ => StateCommand::RelationshipProgress(ProgressHint::Delta(rng.gen_range(-1.0..1.0))),
+    }
+}
+
+/// Plays seeded random interactions from random starting levels for every attachment
+/// style, with a random persona, checking the invariants after each exchange.
+fn property_runs(engine: &PsychologicalEngine) -> Result<()> {
+    for (style_seed, style) in STYLES.into_iter().enumerate() {
+        for run in 0..PROPERTY_RUNS {
+            let seed = style_seed as u64 * PROPERTY_RUNS + run;
+            let mut rng = StdRng::seed_from_u64(seed);
+            let preset = PERSONA_PRESETS[rng.gen_range(0..PERSONA_PRESETS.len())];
+            let mut matrix = persona_preset(preset)?;
+            matrix.attachment_style = style;
+            matrix.anxiety_level = rng.gen_range(0.0..=1.0);
+            matrix.avoidance_level = rng.gen_range(0.0..=1.0);
+            matrix.sexual_energy = rng.gen_range(0.0..=1.0);
+            matrix.last_interaction_time = START;
+
+            let mut now = START;
+            for step in 0..PROPERTY_STEPS {
+                // Mostly conversation-paced, sometimes days or weeks apart.
+                now += match rng.gen_range(0..10) {
+                    0 => rng.gen_range(24..24 * 30) * HOUR,
+                    _ => rng.gen_range(0..4 * HOUR),
+                };
+                let commands: Vec<_> = (0..rng.gen_range(0..5))
+                    .map(|_| random_command(&mut rng))
+                    .collect();
+                let stage = matrix.relationship_stage;
+                engine.process_llm_state_update_at(&mut matrix, &commands, now)?;
+                if let Err(e) = check_invariants(engine, &matrix, stage) {
+                    bail!("seed {seed}, {preset} as {style:?}, step {step}: {e}");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn rule_files() -> Result<Vec<PathBuf>> {
+    let rules_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("rules");
+    let mut rule_files: Vec<PathBuf> = std::fs::read_dir(&rules_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    rule_files.retain(|path| path.extension().is_some_and(|ext| ext == "toml"));
+    rule_files.sort();
+    Ok(rule_files)
+}
+
+#[tokio::test]
+async fn scenarios_match_goldens() -> Result<()> {
+    let bless = std::env::var("PSYCH_SCENARIOS_BLESS").is_ok_and(|v| v == "1");
+    let golden_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("rules/golden");
+
+    let mut failures = Vec::new();
+    for path in rule_files()? {
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let engine = PsychologicalEngine::with_rules(PsychologyRules::load_file(&path).await?);
+
+        let mut rendered = String::new();
+        for scenario in scenarios() {
+            match run(&engine, &scenario) {
+                Ok(matrix) => writeln!(rendered, "{}: {}", scenario.name, summary(&matrix))?,
+                Err(e) => {
+                    println!("FAILED {name}/{}: {e}", scenario.name);
+                    failures.push(format!("{name}/{}", scenario.name));
+                }
+            }
+        }
+
+        let golden = golden_dir.join(format!("{name}.txt"));
+        if bless {
+            std::fs::create_dir_all(&golden_dir)?;
+            std::fs::write(&golden, &rendered)?;
+            println!("blessed {}", golden.display());
+            continue;
+        }
+        match std::fs::read_to_string(&golden) {
+            Ok(expected) if expected == rendered => {}
+            Ok(expected) => {
+                println!("MISMATCH {name}\n--- expected\n{expected}--- rendered\n{rendered}");
+                failures.push(name);
+            }
+            Err(e) => {
+                println!("MISSING {} ({e})", golden.display());
+                failures.push(name);
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "psychology scenarios failed for {failures:?}; re-bless with PSYCH_SCENARIOS_BLESS=1 if intended"
+    );
+    Ok(())
+}
+
+#[test]
+fn random_interactions_stay_in_range() -> Result<()> {
+    let mut rule_files = rule_files()?;
+    rule_files.push(fixture("psychology_v1.toml"));
+    for path in rule_files {
+        let rules = PsychologyRules::from_toml(&std::fs::read_to_string(&path)?)?;
+        if let Err(e) = property_runs(&PsychologicalEngine::with_rules(rules)) {
+            panic!("{}: {e}", path.display());
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn version_1_rules_still_load() -> Result<()> {
+    let rules =
+        PsychologyRules::from_toml(&std::fs::read_to_string(fixture("psychology_v1.toml"))?)?;
+    assert_eq!(rules.version, 1);
+    assert_eq!(rules.attachment.earned_security.max, 0.0);
+    for style in STYLES {
+        let attachment = rules.attachment(style);
+        assert_eq!(attachment.regulation, 0.0, "{style:?}");
+        assert!(attachment.oscillation.is_none(), "{style:?}");
+    }
+
+    // Without regulation or earned security, signals move the levels as in version 1.
+    let engine = PsychologicalEngine::with_rules(rules);
+    let mut matrix = persona_preset("maya")?;
+    matrix.last_interaction_time = START;
+    let anxiety = matrix.anxiety_level;
+    let closeness = [StateCommand::UserSignal(UserSignal::Closeness)];
+    engine.process_llm_state_update_at(&mut matrix, &closeness, START + HOUR)?;
+    assert!(matrix.anxiety_level < anxiety);
+    assert_eq!(matrix.attachment.earned_security, 0.0);
+    Ok(())
+}
+
+#[test]
+fn newer_rule_versions_are_rejected() {
+    let source = std::fs::read_to_string(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("rules/psychology.toml"),
+    )
+    .unwrap();
+    let newer = PSYCHOLOGY_RULES_VERSION + 1;
+    let source = source.replacen(
+        &format!("version = {PSYCHOLOGY_RULES_VERSION}"),
+        &format!("version = {newer}"),
+        1,
+    );
+    let err = PsychologyRules::from_toml(&source).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains(&format!("version {newer} is not supported")),
+        "{err}"
+    );
+}
+
+/// The problems `validate` reports for the built-in rules after `edit`.
+fn problems(edit: impl FnOnce(&mut PsychologyRules)) -> String {
+    let mut rules = PsychologyRules::builtin();
+    edit(&mut rules);
+    match rules.validate() {
+        Ok(()) => String::new(),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn builtin_rules_are_valid() {
+    assert_eq!(problems(|_| {}), "");
+}
+
+#[test]
+fn validate_rejects_nan() {
+    let report = problems(|rules| rules.decay.emotion_half_life_hours = f32::NAN);
+    assert!(
+        report.contains("decay.emotion_half_life_hours must be positive"),
+        "{report}"
+    );
+
+    let report = problems(|rules| rules.baseline.content = f32::NAN);
+    assert!(
+        report.contains("baseline.content must be between 0.0 and 1.0"),
+        "{report}"
+    );
+
+    let report = problems(|rules| rules.clamps.max_emotion_shift = f32::NAN);
+    assert!(
+        report.contains("clamps.max_emotion_shift must be a non-negative number"),
+        "{report}"
+    );
+
+    let report = problems(|rules| rules.clamps.sexual_energy = [f32::NAN, 1.0]);
+    assert!(
+        report.contains("clamps.sexual_energy must be [min, max]"),
+        "{report}"
+    );
+
+    let report = problems(|rules| rules.attachment.anxious.closeness.anxiety = f32::NAN);
+    assert!(
+        report.contains("attachment.anxious.closeness.anxiety"),
+        "{report}"
+    );
+}
+
+#[test]
+fn validate_rejects_inverted_clamp() {
+    let report = problems(|rules| rules.clamps.anxiety_level = [0.8, 0.2]);
+    assert!(
+        report.contains("clamps.anxiety_level must be [min, max] within 0.0 - 1.0, got [0.8, 0.2]"),
+        "{report}"
+    );
+
+    let report = problems(|rules| rules.clamps.avoidance_level = [0.0, 1.5]);
+    assert!(report.contains("clamps.avoidance_level"), "{report}");
+}
+
+#[test]
+fn validate_rejects_non_monotonic_stages() {
+    let report = problems(|rules| {
+        let stages = &mut rules.relationship.stages;
+        stages.dating.trust = stages.friend.trust - 0.1;
+    });
+    assert!(
+        report.contains("relationship.stages.dating must not require less than friend"),
+        "{report}"
+    );
+
+    let report = problems(|rules| {
+        let stages = &mut rules.relationship.stages;
+        stages.long_term_partner.interactions = stages.intimate.interactions - 1;
+    });
+    assert!(
+        report
+            .contains("relationship.stages.long_term_partner must not require less than intimate"),
+        "{report}"
+    );
+}
+
+#[test]
+fn validate_reports_every_problem() {
+    let report = problems(|rules| {
+        rules.clamps.anxiety_level = [0.8, 0.2];
+        rules.baseline.happy = f32::NAN;
+    });
+    assert!(report.contains("clamps.anxiety_level"), "{report}");
+    assert!(report.contains("baseline.happy"), "{report}");
+}
+
+/// Writes `contents` with a distinct modification time, so the watcher sees a change
+/// however coarse the file system's clock is.
+fn rewrite(path: &Path, contents: &str, generation: u64) -> Result<()> {
+    std::fs::write(path, contents)?;
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(START as u64 + generation);
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(modified)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn watched_rules_file_applies_valid_edits_only() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("pagi-rules-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("psychology.toml");
+    let builtin = std::fs::read_to_string(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("rules/psychology.toml"),
+    )?;
+    rewrite(&path, &builtin, 0)?;
+    let engine = PsychologicalEngine::with_rules(PsychologyRules::load_file(&path).await?);
+    let watcher = engine.watch_rules_file(&path, Duration::from_millis(10));
+
+    let edited = builtin.replace("hysteresis = 0.1\n", "hysteresis = 0.25\n");
+    assert_ne!(edited, builtin);
+    rewrite(&path, &edited, 1)?;
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    while engine.rules().relationship.hysteresis != 0.25 {
+        assert!(tokio::time::Instant::now() < deadline, "edit not applied");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    rewrite(&path, "[relationship]\nhysteresis = ", 2)?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(engine.rules().relationship.hysteresis, 0.25);
+
+    // The watcher stops with its engine.
+    drop(engine);
+    tokio::time::timeout(Duration::from_secs(1), watcher).await??;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,161 @@
+//! The per-user state log: replay, time travel, rollback and damaged logs.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use pagi_companion_core::companion::history::{StateCause, StateEvent, StateRecorder};
+use pagi_companion_core::companion::kb::SemanticKB;
+use pagi_companion_core::companion::models::PersonalityStateMatrix;
+use pagi_companion_core::companion::persona::{persona_preset, DEFAULT_PERSONA_PRESET};
+use pagi_companion_core::storage::{MemoryStorage, StorageBackend};
+use serde_json::Value;
+
+const USER: &str = "alice";
+const LOG_KEY: &str = "alice_state.log";
+const START: i64 = 1_700_000_000;
+
+fn json(matrix: &PersonalityStateMatrix) -> Value {
+    serde_json::to_value(matrix).unwrap()
+}
+
+/// Saves one turn at `START + n * 100` that raises anxiety and adds a boundary.
+async fn record_turn(kb: &SemanticKB, matrix: &mut PersonalityStateMatrix, n: i64) -> Result<()> {
+    let mut recorder = StateRecorder::new(matrix, START + n * 100)?;
+    matrix.anxiety_level = 0.5 + 0.1 * n as f32;
+    recorder.record(matrix, StateCause::Attachment)?;
+    matrix.current_boundaries_list.push(format!("boundary {n}"));
+    recorder.record(matrix, StateCause::UserEdit)?;
+    let mut events = recorder.finish();
+    for event in &mut events {
+        event.turn = Some(format!("turn-{n}"));
+    }
+    kb.save_matrix_with_events(USER, matrix, &events).await
+}
+
+/// A KB holding three recorded turns, and the matrix after each of them.
+async fn three_turns() -> Result<(Arc<MemoryStorage>, SemanticKB, Vec<Value>)> {
+    let storage = Arc::new(MemoryStorage::new());
+    let kb = SemanticKB::new_with_storage(storage.clone());
+    let mut matrix = persona_preset(DEFAULT_PERSONA_PRESET)?;
+    let mut after = Vec::new();
+    for n in 1..=3 {
+        record_turn(&kb, &mut matrix, n).await?;
+        after.push(json(&matrix));
+    }
+    Ok((storage, kb, after))
+}
+
+#[tokio::test]
+async fn replay_rebuilds_the_saved_matrix() -> Result<()> {
+    let (_, kb, after) = three_turns().await?;
+
+    let history = kb.load_state_history(USER).await?;
+
+    // A snapshot, then two events per turn.
+    assert_eq!(history.len(), 7);
+    assert_eq!(history.events()[0].cause, StateCause::Snapshot);
+    assert_eq!(json(&history.current()?), after[2]);
+    assert_eq!(json(&kb.load_matrix_by_user_id(USER).await?), after[2]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn at_and_diff_read_a_mid_log_turn() -> Result<()> {
+    let (_, kb, after) = three_turns().await?;
+    let history = kb.load_state_history(USER).await?;
+
+    assert_eq!(json(&history.at(START + 250)?), after[1]);
+    assert_eq!(json(&history.at(START + 200)?), after[1]);
+    assert!(history.at(START).is_err(), "nothing was recorded yet");
+
+    let changes = history.diff(START + 100, START + 200)?;
+    let paths: Vec<&str> = changes.iter().map(|c| c.path.as_str()).collect();
+    assert_eq!(paths, vec!["anxiety_level", "current_boundaries_list"]);
+    assert_eq!(changes[0].before, after[0]["anxiety_level"]);
+    assert_eq!(changes[0].after, after[1]["anxiety_level"]);
+
+    let start = history.turn_start("turn-2").unwrap();
+    assert_eq!(json(&history.replay(start)?), after[0]);
+    assert!(history.turn_start("turn-9").is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn rollback_restores_and_persists_the_pre_turn_matrix() -> Result<()> {
+    let (storage, kb, after) = three_turns().await?;
+    let history = kb.load_state_history(USER).await?;
+    let start = history.turn_start("turn-3").unwrap();
+
+    let restored = kb.rollback_state(USER, start).await?;
+
+    assert_eq!(json(&restored), after[1]);
+    let reloaded = SemanticKB::new_with_storage(storage);
+    assert_eq!(
+        json(&reloaded.load_matrix_by_user_id(USER).await?),
+        after[1]
+    );
+    let history = reloaded.load_state_history(USER).await?;
+    assert_eq!(
+        history.events().last().unwrap().cause,
+        StateCause::Rollback { to: start }
+    );
+    assert_eq!(json(&history.current()?), after[1]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn save_matrix_is_logged_as_a_user_edit() -> Result<()> {
+    let (_, kb, _) = three_turns().await?;
+    let mut matrix = kb.load_matrix_by_user_id(USER).await?;
+    matrix.current_boundaries_list.clear();
+
+    kb.save_matrix(USER, &matrix).await?;
+
+    let history = kb.load_state_history(USER).await?;
+    assert_eq!(history.events().last().unwrap().cause, StateCause::UserEdit);
+    assert_eq!(json(&history.current()?), json(&matrix));
+    Ok(())
+}
+
+#[tokio::test]
+async fn torn_or_garbage_tail_keeps_the_valid_prefix() -> Result<()> {
+    for tail in [&b"\x40\x00\x00\x00\x12\x34"[..], b"garbage\n"] {
+        let (storage, kb, after) = three_turns().await?;
+        let intact_len = storage.size(LOG_KEY).await?.unwrap();
+        storage.append(LOG_KEY, tail).await?;
+
+        let history = kb.load_state_history(USER).await?;
+        assert_eq!(history.len(), 7);
+        assert_eq!(json(&history.current()?), after[2]);
+
+        // The next append drops the tail first, so the new turn stays readable.
+        let fresh = SemanticKB::new_with_storage(storage.clone());
+        let mut matrix = fresh.load_matrix_by_user_id(USER).await?;
+        record_turn(&fresh, &mut matrix, 4).await?;
+        let history = fresh.load_state_history(USER).await?;
+        assert_eq!(history.len(), 9);
+        assert_eq!(json(&history.current()?), json(&matrix));
+        assert!(storage.size(LOG_KEY).await?.unwrap() > intact_len);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn undecodable_event_is_an_error_and_is_not_truncated() -> Result<()> {
+    let (storage, kb, _) = three_turns().await?;
+    let event = StateEvent::snapshot(&persona_preset(DEFAULT_PERSONA_PRESET)?, START)?;
+    // An intact frame whose payload is not an event, e.g. from a newer build.
+    storage
+        .append(LOG_KEY, &event.encode_with(|_| Ok(b"{\"v\":2}".to_vec()))?)
+        .await?;
+    let len = storage.size(LOG_KEY).await?;
+
+    let err = kb.load_state_history(USER).await.unwrap_err();
+    assert!(err.to_string().contains("offset"), "{err}");
+
+    let fresh = SemanticKB::new_with_storage(storage.clone());
+    let mut matrix = persona_preset(DEFAULT_PERSONA_PRESET)?;
+    assert!(record_turn(&fresh, &mut matrix, 4).await.is_err());
+    assert_eq!(storage.size(LOG_KEY).await?, len);
+    Ok(())
+}
@@ -0,0 +1,97 @@
+//! The `StorageBackend` contract, checked against every backend.
+
+use anyhow::Result;
+use pagi_companion_core::storage::{FileSystemStorage, KvStorage, MemoryStorage, StorageBackend};
+
+fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("pagi-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+async fn check_contract(storage: &dyn StorageBackend) -> Result<()> {
+    let name = storage.describe();
+
+    assert_eq!(storage.read("alice.json").await?, None, "{name}");
+    assert_eq!(storage.size("alice.json").await?, None, "{name}");
+    assert!(storage.list().await?.is_empty(), "{name}");
+
+    storage.write("alice.json", b"first").await?;
+    storage.write("alice.json", b"second").await?;
+    assert_eq!(
+        storage.read("alice.json").await?.as_deref(),
+        Some(&b"second"[..]),
+        "{name}"
+    );
+    assert_eq!(storage.size("alice.json").await?, Some(6), "{name}");
+
+    assert_eq!(
+        storage.append("alice_state.log", b"abc").await?,
+        3,
+        "{name}"
+    );
+    assert_eq!(storage.append("alice_state.log", b"de").await?, 5, "{name}");
+    assert_eq!(
+        storage.read("alice_state.log").await?.as_deref(),
+        Some(&b"abcde"[..]),
+        "{name}"
+    );
+
+    storage.write("bob.json", b"").await?;
+    assert_eq!(storage.size("bob.json").await?, Some(0), "{name}");
+    assert_eq!(
+        storage.list().await?,
+        vec!["alice.json", "alice_state.log", "bob.json"],
+        "{name}"
+    );
+
+    storage.copy("alice.json", "alice.json.bak").await?;
+    storage.copy("missing.json", "alice.json").await?;
+    assert_eq!(
+        storage.read("alice.json.bak").await?.as_deref(),
+        Some(&b"second"[..]),
+        "{name}"
+    );
+    assert_eq!(
+        storage.read("alice.json").await?.as_deref(),
+        Some(&b"second"[..]),
+        "{name}"
+    );
+
+    storage.remove("alice.json").await?;
+    storage.remove("alice.json").await?;
+    assert_eq!(storage.read("alice.json").await?, None, "{name}");
+    assert_eq!(storage.size("alice.json").await?, None, "{name}");
+    assert_eq!(
+        storage.list().await?,
+        vec!["alice.json.bak", "alice_state.log", "bob.json"],
+        "{name}"
+    );
+
+    for key in ["", "..", "a/b", "alice.json.tmp"] {
+        assert!(storage.write(key, b"x").await.is_err(), "{name}: {key:?}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn memory_storage_meets_the_contract() -> Result<()> {
+    check_contract(&MemoryStorage::new()).await
+}
+
+#[tokio::test]
+async fn filesystem_storage_meets_the_contract() -> Result<()> {
+    let dir = scratch_dir("contract-fs");
+    check_contract(&FileSystemStorage::new(&dir)).await?;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn kv_storage_meets_the_contract() -> Result<()> {
+    let dir = scratch_dir("contract-kv");
+    std::fs::create_dir_all(&dir)?;
+    check_contract(&KvStorage::open(dir.join("store.redb"))?).await?;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}